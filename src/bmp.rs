@@ -1,9 +1,28 @@
 use std::fs;
 use std::convert::TryInto;
-use std::collections::VecDeque;
 use std::fmt;
 use std::collections::HashMap;
-use std::io::Write;
+use std::collections::hash_map::Entry;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use crate::quantize;
+use crate::quantize::Quantizer;
+use crate::dither;
+use crate::dither::{Dither, DitherMethod};
+use crate::huffman;
+use crate::icc;
+use crate::icc::{IccProfile, ProfileSource};
+use crate::color::{ColorTransform, RgbColorSpace};
+use crate::png;
+use crate::netpbm;
+use crate::netpbm::{NetpbmFormat, NetpbmOptions};
 //use std::io::ErrorKind;
 
 //support packed dibs, dibs that have no empty gaps
@@ -16,45 +35,128 @@ http://fileformats.archiveteam.org/wiki/BMP
 */
 
 const HEADER_OFFSET: usize = 14;
+//makes the temp file names of write_file_atomically unique within the process
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 //Errors
-pub enum ErrorKind {
-  Unsupported,
-  DoesNotExist,
+#[derive(Debug)]
+pub enum Error {
+  //reading or writing failed, the original error is kept as the source
+  Io(io::Error),
+  //the file says there are length bytes at offset, but it is only file_size bytes long
+  Truncated { offset: usize, length: usize, file_size: usize },
+  //a header field has a value that makes no sense, or isn't handled. field uses the Win32 name, eg: biBitCount
+  InvalidHeaderField { field: &'static str, value: i64 },
+  UnsupportedCompression(Compression),
+  //coordinates outside of a width x height image
+  OutOfBounds { x: usize, y: usize, width: usize, height: usize },
+  //an ICC profile that can't be parsed, and why
+  InvalidProfile(String),
+  //a PNG (in a BI_PNG bitmap) that can't be decoded, and why
+  InvalidPng(String),
+  //a PBM, PGM, PPM or PAM file that can't be read, and why
+  InvalidNetpbm(String),
+  //something valid that can't be read or written, and why
+  Unsupported(&'static str),
+  //the requested part of the file isn't there
+  DoesNotExist(&'static str),
   WrongFileType,
   UseExtraBitMasks,
+  //a size or offset that doesn't fit, named after the field or value that overflowed
+  Overflow { field: &'static str },
+  //pixel data of the wrong length for the width and height
+  WrongDimensions { expected: usize, actual: usize },
+  //bit masks that can be read, but aren't one of the PixelFormats (eg: 16 bit RGB444). masks are red, green, blue, alpha
+  NoPixelFormat { bitcount: u16, masks: [u32; 4] },
+  //BCA_HUFFMAN1D data with no valid code at this bit of the pixel array
+  InvalidHuffmanCode { bit: usize },
 }
 
-impl ErrorKind {
-  fn as_str(&self) -> &str {
-    match *self {
-      ErrorKind::Unsupported => "File is unsupported",
-      ErrorKind::DoesNotExist => "Requested object does not exist",
-      ErrorKind::WrongFileType => "Wrong file type. Must be a .bmp file",
-      ErrorKind::UseExtraBitMasks => "Use extra bit masks instead",
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Io(e) => write!(f, "Could not read or write the file: {}", e),
+      Error::Truncated { offset, length, file_size } => write!(f, "File is truncated: expected {} bytes at offset {}, but the file is only {} bytes", length, offset, file_size),
+      Error::InvalidHeaderField { field, value } => write!(f, "Invalid or unsupported value for {}: {}", field, value),
+      Error::UnsupportedCompression(compression) => write!(f, "Compression {} is unsupported", compression),
+      Error::OutOfBounds { x, y, width, height } => write!(f, "Pixel ({}, {}) is outside of the {}x{} image", x, y, width, height),
+      Error::InvalidProfile(reason) => write!(f, "Invalid ICC profile: {}", reason),
+      Error::InvalidPng(reason) => write!(f, "Invalid PNG: {}", reason),
+      Error::InvalidNetpbm(reason) => write!(f, "Invalid Netpbm file: {}", reason),
+      Error::Unsupported(reason) => write!(f, "Unsupported: {}", reason),
+      Error::DoesNotExist(what) => write!(f, "Requested {} does not exist", what),
+      Error::WrongFileType => write!(f, "Wrong file type. Must be a .bmp file"),
+      Error::UseExtraBitMasks => write!(f, "Use extra bit masks instead"),
+      Error::Overflow { field } => write!(f, "{} is too large", field),
+      Error::WrongDimensions { expected, actual } => write!(f, "Pixel data is {} bytes, but the dimensions need {}", actual, expected),
+      Error::NoPixelFormat { bitcount, masks } => write!(f, "{} bit pixels with masks {:#x?} have no PixelFormat", bitcount, masks),
+      Error::InvalidHuffmanCode { bit } => write!(f, "Invalid huffman code at bit {} of the pixel array", bit),
     }
   }
 }
 
-impl fmt::Debug for ErrorKind {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}", self.as_str())
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(e) => Some(e),
+      _ => None,
+    }
   }
 }
 
-impl fmt::Display for ErrorKind {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "Error: {}", self.as_str())
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Error {
+    return Error::Io(e);
+  }
+}
+
+//bfType, the first two bytes of the file
+//http://fileformats.archiveteam.org/wiki/BMP#Identification
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signature {
+  //BM, windows (and OS/2) bitmap
+  Bitmap,
+  //BA, OS/2 bitmap array
+  BitmapArray,
+  //CI, OS/2 color icon
+  ColorIcon,
+  //CP, OS/2 color pointer
+  ColorPointer,
+  //IC, OS/2 icon
+  Icon,
+  //PT, OS/2 pointer
+  Pointer,
+}
+
+impl Signature {
+  pub fn from_bytes(bytes: [u8; 2]) -> Option<Signature> {
+    match &bytes {
+      b"BM" => return Some(Signature::Bitmap),
+      b"BA" => return Some(Signature::BitmapArray),
+      b"CI" => return Some(Signature::ColorIcon),
+      b"CP" => return Some(Signature::ColorPointer),
+      b"IC" => return Some(Signature::Icon),
+      b"PT" => return Some(Signature::Pointer),
+      _ => return None,
+    }
+  }
+  pub fn to_bytes(self) -> [u8; 2] {
+    match self {
+      Signature::Bitmap => return *b"BM",
+      Signature::BitmapArray => return *b"BA",
+      Signature::ColorIcon => return *b"CI",
+      Signature::ColorPointer => return *b"CP",
+      Signature::Icon => return *b"IC",
+      Signature::Pointer => return *b"PT",
+    }
   }
 }
 
 //File header
 struct BITMAPFILEHEADER {
-  bfType: String,
+  bfType: [u8; 2],
   bfSize: u32,
-  bfReserved1: Vec<u8>,
-  bfReserved2: Vec<u8>,
-  bfOffBits: u16,
+  bfOffBits: u32,
 }
 
 /*
@@ -143,13 +245,176 @@ enum DIBHEADER {
 }
 */
 
+//biCompression
+//https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-wmf/4e588f70-bd92-4a6f-b77f-35d0feaf7a57
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+  BI_RGB,
+  BI_RLE8,
+  BI_RLE4,
+  BI_BITFIELDS,
+  BI_JPEG,
+  BI_PNG,
+  BI_ALPHABITFIELDS,
+  BI_CMYK,
+  BI_CMYKRLE8,
+  BI_CMYKRLE4,
+  //OS/2 2.x, stored as 3 and 4 like BI_BITFIELDS and BI_JPEG
+  BCA_HUFFMAN1D,
+  BCA_RLE24,
+  Unknown(u32),
+}
+
+impl Compression {
+  //the same number means different things in OS/2 bitmaps
+  pub fn from_u32(int: u32, os2: bool) -> Compression {
+    match int {
+      0 => Compression::BI_RGB,
+      1 => Compression::BI_RLE8,
+      2 => Compression::BI_RLE4,
+      3 if os2 => Compression::BCA_HUFFMAN1D,
+      3 => Compression::BI_BITFIELDS,
+      4 if os2 => Compression::BCA_RLE24,
+      4 => Compression::BI_JPEG,
+      5 => Compression::BI_PNG,
+      6 => Compression::BI_ALPHABITFIELDS,
+      11 => Compression::BI_CMYK,
+      12 => Compression::BI_CMYKRLE8,
+      13 => Compression::BI_CMYKRLE4,
+      _ => Compression::Unknown(int),
+    }
+  }
+  pub fn to_u32(self) -> u32 {
+    match self {
+      Compression::BI_RGB => 0,
+      Compression::BI_RLE8 => 1,
+      Compression::BI_RLE4 => 2,
+      Compression::BI_BITFIELDS | Compression::BCA_HUFFMAN1D => 3,
+      Compression::BI_JPEG | Compression::BCA_RLE24 => 4,
+      Compression::BI_PNG => 5,
+      Compression::BI_ALPHABITFIELDS => 6,
+      Compression::BI_CMYK => 11,
+      Compression::BI_CMYKRLE8 => 12,
+      Compression::BI_CMYKRLE4 => 13,
+      Compression::Unknown(int) => int,
+    }
+  }
+}
+
+impl fmt::Display for Compression {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Compression::Unknown(int) => write!(f, "unknown ({})", int),
+      _ => write!(f, "{:?}", self),
+    }
+  }
+}
+
+//bV5CSType, the color space of V4 and V5 headers
+//https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-wmf/eb4bbd50-b3ce-4917-895c-be31f214797f
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSType {
+  //the endpoints and gammas in the header describe the color space
+  LCS_CALIBRATED_RGB,
+  LCS_sRGB,
+  //the system default, which is sRGB
+  LCS_WINDOWS_COLOR_SPACE,
+  //ProfileData is the file name of an ICC profile
+  PROFILE_LINKED,
+  //ProfileData is an ICC profile
+  PROFILE_EMBEDDED,
+  Unknown(u32),
+}
+
+impl CSType {
+  //four character codes, which read backwards since they are stored little endian
+  pub fn from_u32(int: u32) -> CSType {
+    match int {
+      0 => CSType::LCS_CALIBRATED_RGB,
+      //'sRGB'
+      0x73524742 => CSType::LCS_sRGB,
+      //'Win '
+      0x57696e20 => CSType::LCS_WINDOWS_COLOR_SPACE,
+      //'LINK'
+      0x4c494e4b => CSType::PROFILE_LINKED,
+      //'MBED'
+      0x4d424544 => CSType::PROFILE_EMBEDDED,
+      _ => CSType::Unknown(int),
+    }
+  }
+  pub fn to_u32(self) -> u32 {
+    match self {
+      CSType::LCS_CALIBRATED_RGB => 0,
+      CSType::LCS_sRGB => 0x73524742,
+      CSType::LCS_WINDOWS_COLOR_SPACE => 0x57696e20,
+      CSType::PROFILE_LINKED => 0x4c494e4b,
+      CSType::PROFILE_EMBEDDED => 0x4d424544,
+      CSType::Unknown(int) => int,
+    }
+  }
+}
+
+impl fmt::Display for CSType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CSType::Unknown(int) => write!(f, "unknown ({:#010x})", int),
+      _ => write!(f, "{:?}", self),
+    }
+  }
+}
+
+//bV5Intent, the rendering intent
+//https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-wmf/9fec0834-607d-427d-abd5-ab240fb0db38
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Intent {
+  //saturation
+  LCS_GM_BUSINESS,
+  //relative colorimetric
+  LCS_GM_GRAPHICS,
+  //perceptual
+  LCS_GM_IMAGES,
+  //absolute colorimetric
+  LCS_GM_ABS_COLORIMETRIC,
+  Unknown(u32),
+}
+
+impl Intent {
+  pub fn from_u32(int: u32) -> Intent {
+    match int {
+      1 => Intent::LCS_GM_BUSINESS,
+      2 => Intent::LCS_GM_GRAPHICS,
+      4 => Intent::LCS_GM_IMAGES,
+      8 => Intent::LCS_GM_ABS_COLORIMETRIC,
+      _ => Intent::Unknown(int),
+    }
+  }
+  pub fn to_u32(self) -> u32 {
+    match self {
+      Intent::LCS_GM_BUSINESS => 1,
+      Intent::LCS_GM_GRAPHICS => 2,
+      Intent::LCS_GM_IMAGES => 4,
+      Intent::LCS_GM_ABS_COLORIMETRIC => 8,
+      Intent::Unknown(int) => int,
+    }
+  }
+}
+
+impl fmt::Display for Intent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Intent::Unknown(int) => write!(f, "unknown ({})", int),
+      _ => write!(f, "{:?}", self),
+    }
+  }
+}
+
 pub struct DIBHEADER {
-  pub size: u16,
+  pub size: u32,
   pub width: u32,
   pub height: i32,
   pub planes: u16,
   pub bitcount: u16,
-  pub compression: Option<String>,
+  pub compression: Option<Compression>,
   pub sizeimage: Option<u32>,
   pub XPelsPerMeter: Option<u32>,
   pub YPelsPerMeter: Option<u32>,
@@ -159,21 +424,156 @@ pub struct DIBHEADER {
   pub GreenMask: Option<u32>,
   pub BlueMask: Option<u32>,
   pub AlphaMask: Option<u32>,
-  pub CSType: Option<String>,
+  pub CSType: Option<CSType>,
   pub Endpoints: Option<[[i32; 3]; 3]>,
   pub GammaRed: Option<u32>,
   pub GammaGreen: Option<u32>,
   pub GammaBlue: Option<u32>,
-  pub Intent: Option<String>,
-  pub ProfileData: Option<u16>,
-  pub ProfileSize: Option<u16>,
+  pub Intent: Option<Intent>,
+  pub ProfileData: Option<u32>,
+  pub ProfileSize: Option<u32>,
   pub Reserved: Option<Vec<u8>>,
+  //OS/2 2.x only
+  //usUnits, 0 means XPelsPerMeter and YPelsPerMeter are in pixels per meter
+  pub Units: Option<u16>,
+  //usRecording, 0 means rows go bottom to top
+  pub Recording: Option<u16>,
+  //usRendering, cSize1, cSize2. the halftoning algorithm, see get_halftoning
+  pub Rendering: Option<u16>,
+  pub Size1: Option<u32>,
+  pub Size2: Option<u32>,
+  //ulColorEncoding, 0 means RGB
+  pub ColorEncoding: Option<u32>,
+  //ulIdentifier, for the application's own use
+  pub Identifier: Option<u32>,
+}
+
+//what the pixel array of a BI_JPEG or BI_PNG bitmap is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmbeddedFormat {
+  Jpeg,
+  Png,
+}
+
+impl EmbeddedFormat {
+  //from the first bytes of the file
+  pub fn detect(bytes: &[u8]) -> Option<EmbeddedFormat> {
+    if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+      return Some(EmbeddedFormat::Jpeg);
+    } else if bytes.starts_with(&png::SIGNATURE) {
+      return Some(EmbeddedFormat::Png);
+    }
+    return None;
+  }
+  pub fn compression(self) -> Compression {
+    match self {
+      EmbeddedFormat::Jpeg => Compression::BI_JPEG,
+      EmbeddedFormat::Png => Compression::BI_PNG,
+    }
+  }
+  //(width, height) from the IHDR chunk or the first SOF marker
+  fn get_dimensions(self, bytes: &[u8]) -> Option<(u32, u32)> {
+    let read_u16 = |offset: usize| -> Option<u32> { Some(u16::from_be_bytes(bytes.get(offset..offset+2)?.try_into().unwrap()) as u32) };
+    match self {
+      EmbeddedFormat::Png => {
+        //signature, then the IHDR length and type
+        if bytes.get(12..16)? != b"IHDR" {
+          return None;
+        }
+        let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().unwrap());
+        let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().unwrap());
+        return Some((width, height));
+      },
+      EmbeddedFormat::Jpeg => {
+        //skip from marker to marker
+        let mut position: usize = 2;
+        loop {
+          if *bytes.get(position)? != 0xff {
+            return None;
+          }
+          let marker = *bytes.get(position+1)?;
+          match marker {
+            //fill bytes
+            0xff => position += 1,
+            //markers without a length
+            0x01 | 0xd0..=0xd8 => position += 2,
+            //start of frame, except DHT (c4), JPG (c8) and DAC (cc)
+            0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+              //length, precision, then height before width
+              let height = read_u16(position+5)?;
+              let width = read_u16(position+7)?;
+              return Some((width, height));
+            },
+            //end of image or start of scan before a frame
+            0xd9 | 0xda => return None,
+            _ => position += 2+read_u16(position+2)? as usize,
+          }
+        }
+      },
+    }
+  }
+}
+
+//how an OS/2 2.x bitmap was halftoned (usRendering)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Halftoning {
+  None,
+  //damping is the percentage of the error that is spread (cSize1)
+  ErrorDiffusion { damping: u32 },
+  //processing algorithm for noncoded document acquisition, with the pattern size
+  Panda { x: u32, y: u32 },
+  SuperCircle { x: u32, y: u32 },
+  Unknown(u16),
+}
+
+//an image inside an OS/2 bitmap array ("BA") file
+#[derive(Clone, Debug, PartialEq)]
+pub struct BitmapArrayEntry {
+  //where the BITMAPARRAYFILEHEADER of this image starts
+  pub offset: usize,
+  //cxDisplay and cyDisplay, the screen resolution the image is meant for. 0 means any
+  pub display_width: u16,
+  pub display_height: u16,
+  //usType of the image's own file header
+  pub image_type: Signature,
+  pub width: u32,
+  pub height: i32,
+  pub bitcount: u16,
+}
+
+//color table. stored as RGBTRIPLE (core headers) or RGBQUAD, but the colors here are always rgb
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+  pub colors: Vec<[u8; 3]>,
+  //biClrImportant, the first this many colors are the important ones. 0 means all of them
+  pub important: u32,
 }
 
-//rgbtriple and rgbquad
-enum ColorTable {
-  RGBTRIPLE(Vec<[u8; 3]>),
-  RGBQUAD(Vec<[u8; 4]>),
+impl Palette {
+  pub fn new(colors: Vec<[u8; 3]>) -> Palette {
+    return Palette { colors, important: 0 };
+  }
+  pub fn len(&self) -> usize {
+    return self.colors.len();
+  }
+  pub fn is_empty(&self) -> bool {
+    return self.colors.is_empty();
+  }
+  pub fn get(&self, index: usize) -> Option<[u8; 3]> {
+    return self.colors.get(index).copied();
+  }
+  pub fn set(&mut self, index: usize, color: [u8; 3]) -> Result<(), Error> {
+    match self.colors.get_mut(index) {
+      Some(entry) => {
+        *entry = color;
+        return Ok(());
+      },
+      None => return Err(Error::DoesNotExist("palette entry")),
+    }
+  }
+  pub fn push(&mut self, color: [u8; 3]) {
+    self.colors.push(color);
+  }
 }
 
 //extra bit masks, these are unofficial names
@@ -195,21 +595,412 @@ enum EXTRA_BIT_MASKS {
   BI_ALPHABITFIELDS_MASKS(BI_ALPHABITFIELDS_MASKS),
 }
 
+//pixel formats that can be written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+  //color table indexes
+  Indexed1,
+  Indexed2,
+  Indexed4,
+  Indexed8,
+  //16 bit, 5 bits per channel
+  Rgb555,
+  //16 bit, 6 bits for green (BI_BITFIELDS)
+  Rgb565,
+  Rgb24,
+  //32 bit, unused 4th byte
+  Bgrx32,
+  //32 bit with alpha (BI_BITFIELDS with an alpha mask)
+  Bgra32,
+  //32 bit with any masks. alpha can be 0 for no alpha
+  Bitfields32 { red: u32, green: u32, blue: u32, alpha: u32 },
+}
+
+impl PixelFormat {
+  pub fn bitcount(&self) -> u16 {
+    match self {
+      PixelFormat::Indexed1 => 1,
+      PixelFormat::Indexed2 => 2,
+      PixelFormat::Indexed4 => 4,
+      PixelFormat::Indexed8 => 8,
+      PixelFormat::Rgb555 | PixelFormat::Rgb565 => 16,
+      PixelFormat::Rgb24 => 24,
+      PixelFormat::Bgrx32 | PixelFormat::Bgra32 | PixelFormat::Bitfields32 { .. } => 32,
+    }
+  }
+  //size of the color table, None if not indexed
+  pub fn max_colors(&self) -> Option<usize> {
+    match self {
+      PixelFormat::Indexed1 | PixelFormat::Indexed2 | PixelFormat::Indexed4 | PixelFormat::Indexed8 => Some(1 << self.bitcount()),
+      _ => None,
+    }
+  }
+  //red, green, blue, alpha masks if they have to be written (BI_BITFIELDS)
+  fn masks(&self) -> Option<[u32; 4]> {
+    match self {
+      PixelFormat::Rgb565 => Some([0xf800, 0x07e0, 0x001f, 0]),
+      PixelFormat::Bgra32 => Some([0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000]),
+      PixelFormat::Bitfields32 { red, green, blue, alpha } => Some([*red, *green, *blue, *alpha]),
+      _ => None,
+    }
+  }
+}
+
+//everything needed to find and decode a pixel, worked out once from the headers so the pixel array can be read directly
+#[derive(Clone)]
+struct PixelLayout {
+  //start of the pixel array (bfOffBits)
+  offset: usize,
+  width: usize,
+  height: usize,
+  top_down: bool,
+  bitcount: u16,
+  //bytes per row, including padding
+  row_length: usize,
+  //red, green, blue, alpha. only used for 16, 24 and 32 bit
+  masks: [u32; 4],
+  //rgba, only used for 1, 2, 4 and 8 bit
+  palette: Vec<[u8; 4]>,
+}
+
+impl PixelLayout {
+  //byte offset of a row, y is counted from the top no matter how the rows are stored
+  fn row_start(&self, y: usize) -> usize {
+    if self.top_down {
+      return self.offset+y*self.row_length;
+    } else {
+      return self.offset+(self.height-1-y)*self.row_length;
+    }
+  }
+  //the pixel as stored, either a palette index or a packed value that the masks apply to
+  fn read_raw<F: Fn(usize) -> u8>(&self, byte_at: F, row_start: usize, x: usize) -> u32 {
+    match self.bitcount {
+      1 | 2 | 4 => {
+        let bit = x*self.bitcount as usize;
+        //leftmost pixel is in the most significant bits
+        let shift = 8-self.bitcount as usize-bit%8;
+        return ((byte_at(row_start+bit/8) >> shift) & ((1u8 << self.bitcount)-1)) as u32;
+      },
+      8 => return byte_at(row_start+x) as u32,
+      16 => return u16::from_le_bytes([byte_at(row_start+x*2), byte_at(row_start+x*2+1)]) as u32,
+      24 => return u32::from_le_bytes([byte_at(row_start+x*3), byte_at(row_start+x*3+1), byte_at(row_start+x*3+2), 0]),
+      _ => return u32::from_le_bytes([byte_at(row_start+x*4), byte_at(row_start+x*4+1), byte_at(row_start+x*4+2), byte_at(row_start+x*4+3)]),
+    }
+  }
+  fn write_raw<F: Fn(usize) -> u8, G: FnMut(usize, u8)>(&self, byte_at: F, mut set_byte: G, row_start: usize, x: usize, raw: u32) {
+    match self.bitcount {
+      1 | 2 | 4 => {
+        let bit = x*self.bitcount as usize;
+        let shift = 8-self.bitcount as usize-bit%8;
+        let mask = ((1u8 << self.bitcount)-1) << shift;
+        let byte = byte_at(row_start+bit/8);
+        set_byte(row_start+bit/8, (byte & !mask) | (((raw as u8) << shift) & mask));
+      },
+      8 => set_byte(row_start+x, raw as u8),
+      _ => {
+        let bytes_per_pixel = (self.bitcount/8) as usize;
+        let raw_bytes = raw.to_le_bytes();
+        for (i, byte) in raw_bytes.iter().take(bytes_per_pixel).enumerate() {
+          set_byte(row_start+x*bytes_per_pixel+i, *byte);
+        }
+      },
+    }
+  }
+  //scale a masked channel to 0-255
+  fn extract_channel(raw: u32, mask: u32) -> u8 {
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    let value = ((raw & mask) >> shift) as u64;
+    return ((value*255+max/2)/max) as u8;
+  }
+  fn pack_channel(channel: u8, mask: u32) -> u32 {
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    return ((((channel as u64)*max+127)/255) as u32) << shift;
+  }
+  fn raw_to_rgba(&self, raw: u32) -> [u8; 4] {
+    if self.bitcount <= 8 {
+      //indexes not in the color table are treated as black
+      return *self.palette.get(raw as usize).unwrap_or(&[0, 0, 0, 255]);
+    }
+    let mut rgba: [u8; 4] = [0, 0, 0, 255];
+    for (channel, mask) in rgba.iter_mut().zip(self.masks) {
+      if mask != 0 {
+        *channel = PixelLayout::extract_channel(raw, mask);
+      }
+    }
+    return rgba;
+  }
+  fn rgba_to_raw(&self, rgba: [u8; 4]) -> u32 {
+    if self.bitcount <= 8 {
+      //closest color in the color table
+      let mut closest: usize = 0;
+      let mut closest_diff: u32 = u32::MAX;
+      for (i, color) in self.palette.iter().enumerate() {
+        let diff: u32 = (0..3).map(|c| (color[c] as i32-rgba[c] as i32).pow(2) as u32).sum();
+        if diff < closest_diff {
+          closest = i;
+          closest_diff = diff;
+        }
+      }
+      return closest as u32;
+    }
+    let mut raw: u32 = 0;
+    for (channel, mask) in rgba.iter().zip(self.masks) {
+      if mask != 0 {
+        raw |= PixelLayout::pack_channel(*channel, mask);
+      }
+    }
+    return raw;
+  }
+  fn get(&self, contents: &[u8], x: usize, y: usize) -> [u8; 4] {
+    return self.raw_to_rgba(self.read_raw(|i| contents[i], self.row_start(y), x));
+  }
+  //for drawing, anything outside of the image is skipped
+  fn set_clipped(&self, contents: &[Cell<u8>], x: i64, y: i64, raw: u32) {
+    if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+      return;
+    }
+    self.write_raw(|i| contents[i].get(), |i, byte| contents[i].set(byte), self.row_start(y as usize), x as usize, raw);
+  }
+  fn fill_span_clipped(&self, contents: &[Cell<u8>], x1: i64, x2: i64, y: i64, raw: u32) {
+    for x in x1.max(0)..=x2.min(self.width as i64-1) {
+      self.set_clipped(contents, x, y, raw);
+    }
+  }
+}
+
+//a pixel array from get_decoded_pixels, either the file's own or one decoded from compressed data
+enum PixelData<'a> {
+  Stored(&'a [u8]),
+  Decoded(Arc<Vec<u8>>),
+}
+
+impl Deref for PixelData<'_> {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    match self {
+      PixelData::Stored(pixels) => return pixels,
+      PixelData::Decoded(pixels) => return pixels,
+    }
+  }
+}
+
+//the last decoded pixels of a compressed image, and the contents they were decoded from
+//contents is public and can be changed directly, so the cache is only used while they are still the same
+struct DecodedCache {
+  source: Vec<u8>,
+  pixels: Arc<Vec<u8>>,
+  layout: PixelLayout,
+}
+
+//https://docs.microsoft.com/en-us/windows/win32/gdi/bitmap-header-types
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderVersion {
+  //BITMAPCOREHEADER, 12 bytes
+  Core,
+  //BITMAPINFOHEADER, 40 bytes
+  Info,
+  //BITMAPV4HEADER, 108 bytes
+  V4,
+  //BITMAPV5HEADER, 124 bytes
+  V5,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SaveCompression {
+  //BI_RGB
+  Rgb,
+  //BI_BITFIELDS, masks are written even if they are the default ones
+  Bitfields,
+  //BI_RLE8 for 8 bit, BI_RLE4 for 4 bit
+  Rle,
+}
+
+//color space fields of BITMAPV4HEADER and BITMAPV5HEADER, as stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorSpaceInfo {
+  pub cs_type: CSType,
+  pub endpoints: [[i32; 3]; 3],
+  pub gamma: [u32; 3],
+  //only in BITMAPV5HEADER
+  pub intent: Intent,
+}
+
+impl ColorSpaceInfo {
+  pub fn get_endpoints(&self) -> CIEXYZTRIPLE {
+    return CIEXYZTRIPLE::from_fxpt2dot30(self.endpoints);
+  }
+  pub fn set_endpoints(&mut self, endpoints: CIEXYZTRIPLE) -> Result<(), Error> {
+    self.endpoints = match endpoints.to_fxpt2dot30() {
+      Ok(returned_endpoints) => returned_endpoints,
+      Err(e) => return Err(e),
+    };
+    return Ok(());
+  }
+  //red, green, blue
+  pub fn get_gamma(&self) -> [f64; 3] {
+    return self.gamma.map(|gamma| gamma as f64/65536.0);
+  }
+  pub fn set_gamma(&mut self, gamma: [f64; 3]) -> Result<(), Error> {
+    let mut fixed: [u32; 3] = [0; 3];
+    for i in 0..3 {
+      //16.16 fixed point, unsigned
+      let value = (gamma[i]*65536.0).round();
+      if !(0.0..=u32::MAX as f64).contains(&value) {
+        return Err(Error::Overflow { field: "gamma" });
+      }
+      fixed[i] = value as u32;
+    }
+    self.gamma = fixed;
+    return Ok(());
+  }
+}
+
+//a CIE XYZ color
+//https://docs.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-ciexyz
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CIEXYZ {
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
+}
+
+//the endpoints of a LCS_CALIBRATED_RGB color space, the CIE XYZ of full red, green and blue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CIEXYZTRIPLE {
+  pub red: CIEXYZ,
+  pub green: CIEXYZ,
+  pub blue: CIEXYZ,
+}
+
+impl CIEXYZTRIPLE {
+  //FXPT2DOT30, 2 integer bits and 30 fraction bits
+  pub fn from_fxpt2dot30(endpoints: [[i32; 3]; 3]) -> CIEXYZTRIPLE {
+    let to_xyz = |endpoint: [i32; 3]| {
+      let [x, y, z] = endpoint.map(|value| value as f64/(1u32 << 30) as f64);
+      return CIEXYZ { x, y, z };
+    };
+    return CIEXYZTRIPLE { red: to_xyz(endpoints[0]), green: to_xyz(endpoints[1]), blue: to_xyz(endpoints[2]) };
+  }
+  //Overflow if a value isn't between -2 and 2
+  pub fn to_fxpt2dot30(self) -> Result<[[i32; 3]; 3], Error> {
+    let mut endpoints: [[i32; 3]; 3] = [[0; 3]; 3];
+    for (i, xyz) in [self.red, self.green, self.blue].iter().enumerate() {
+      for (j, value) in [xyz.x, xyz.y, xyz.z].iter().enumerate() {
+        let fixed = (value*(1u32 << 30) as f64).round();
+        if !(i32::MIN as f64..=i32::MAX as f64).contains(&fixed) {
+          return Err(Error::Overflow { field: "endpoint" });
+        }
+        endpoints[i][j] = fixed as i32;
+      }
+    }
+    return Ok(endpoints);
+  }
+  //rows of x, y, z, one per primary
+  pub fn to_array(self) -> [[f64; 3]; 3] {
+    return [self.red, self.green, self.blue].map(|xyz| [xyz.x, xyz.y, xyz.z]);
+  }
+}
+
+//how save_to_new_with_options/encode write the file. anything not set is kept the same as the image
+#[derive(Clone, Debug, Default)]
+pub struct SaveOptions {
+  header: Option<HeaderVersion>,
+  top_down: Option<bool>,
+  compression: Option<SaveCompression>,
+  resolution: Option<(u32, u32)>,
+  color_space: Option<ColorSpaceInfo>,
+  icc_profile: Option<Vec<u8>>,
+}
+
+impl SaveOptions {
+  pub fn new() -> SaveOptions {
+    return SaveOptions::default();
+  }
+  pub fn header(mut self, header: HeaderVersion) -> SaveOptions {
+    self.header = Some(header);
+    return self;
+  }
+  pub fn top_down(mut self, top_down: bool) -> SaveOptions {
+    self.top_down = Some(top_down);
+    return self;
+  }
+  pub fn compression(mut self, compression: SaveCompression) -> SaveOptions {
+    self.compression = Some(compression);
+    return self;
+  }
+  //XPelsPerMeter and YPelsPerMeter
+  pub fn resolution(mut self, x_pels_per_meter: u32, y_pels_per_meter: u32) -> SaveOptions {
+    self.resolution = Some((x_pels_per_meter, y_pels_per_meter));
+    return self;
+  }
+  //only written for V4 and V5 headers
+  pub fn color_space(mut self, color_space: ColorSpaceInfo) -> SaveOptions {
+    self.color_space = Some(color_space);
+    return self;
+  }
+  //ICC profile to embed, only for V5 headers. by default the image's own profile is kept
+  pub fn icc_profile(mut self, icc_profile: Vec<u8>) -> SaveOptions {
+    self.icc_profile = Some(icc_profile);
+    return self;
+  }
+}
+
 pub struct BMP {
   pub contents: Vec<u8>,
-  from_file: bool,
   //bitmap_file_header: BITMAPFILEHEADER,
   //dib_header: DIBHEADER,
+  //RLE, huffman 1D and PNG pixels, so reading them one at a time doesn't decode the whole image every time
+  decoded: Mutex<Option<DecodedCache>>,
 }
 
 impl BMP {
   /*pub fn new() -> BMP {
-    return BMP { contents: Vec::new(), from_file: false };
+    return BMP { contents: Vec::new() };
   }*/
+  //panics if the file can't be read, see open
   pub fn new_from_file(file_path: &str) -> BMP {
     let contents = fs::read(file_path)
       .expect("Error encountered");
-    return BMP { contents: contents, from_file: true, };
+    return BMP::from_bytes(contents);
+  }
+  //the bytes of a whole file, which aren't checked until they are used
+  pub fn from_bytes(contents: Vec<u8>) -> BMP {
+    return BMP { contents, decoded: Mutex::new(None) };
+  }
+  //reads the whole file. WrongFileType if it doesn't start with a bitmap signature
+  pub fn open(file_path: &str) -> Result<BMP, Error> {
+    let contents = fs::read(file_path)?;
+    if contents.len() < 2 || Signature::from_bytes([contents[0], contents[1]]).is_none() {
+      return Err(Error::WrongFileType);
+    }
+    return Ok(BMP::from_bytes(contents));
+  }
+  //reads one bitmap from a stream, stopping at the end of it (bfSize) so images one after another can be read one by one
+  //WrongFileType if it doesn't start with a bitmap signature
+  pub fn read_from<R: Read>(mut reader: R) -> Result<BMP, Error> {
+    let mut contents: Vec<u8> = vec![0; HEADER_OFFSET];
+    reader.read_exact(&mut contents)?;
+    let signature = match Signature::from_bytes([contents[0], contents[1]]) {
+      Some(signature) => signature,
+      None => return Err(Error::WrongFileType),
+    };
+    let size = BMP::bytes_to_int(contents[2..6].try_into().unwrap()) as usize;
+    if size == 0 || signature == Signature::BitmapArray {
+      //some writers leave bfSize as 0, so there is no way of knowing where the image ends
+      //in bitmap arrays it is only the size of the first BITMAPARRAYFILEHEADER
+      reader.read_to_end(&mut contents)?;
+    } else if size < HEADER_OFFSET {
+      return Err(Error::InvalidHeaderField { field: "bfSize", value: size as i64 });
+    } else {
+      //take() instead of allocating bfSize bytes up front, in case the size is garbage
+      reader.by_ref().take((size-HEADER_OFFSET) as u64).read_to_end(&mut contents)?;
+      if contents.len() < size {
+        return Err(Error::Truncated { offset: 0, length: size, file_size: contents.len() });
+      }
+    }
+    return Ok(BMP::from_bytes(contents));
   }
   //utilities
   fn bytes_to_int(bytes: [u8; 4]) -> u32 {
@@ -218,150 +1009,105 @@ impl BMP {
   fn two_bytes_to_int(bytes: [u8; 2]) -> u16 {
     u16::from_le_bytes(bytes)
   }
-  fn byte_to_int(byte: u8) -> u8 {
-    u8::from_le_bytes([byte])
-  }
   fn two_bytes_to_signed_int(bytes: [u8; 2]) -> i16 {
     i16::from_le_bytes(bytes)
   }
   fn bytes_to_signed_int(bytes: [u8; 4]) -> i32 {
     i32::from_le_bytes(bytes)
   }
-  fn bytes_to_string(bytes: &[u8]) -> String {
-    String::from_utf8_lossy(&bytes).to_string()
+  fn write_int(contents: &mut [u8], offset: usize, int: u32) {
+    contents[offset..offset+4].copy_from_slice(&int.to_le_bytes());
   }
-  pub fn num_bytes_to_kilobytes(bytes: u32) -> u32 {
+  pub fn num_bytes_to_kilobytes(bytes: usize) -> usize {
     //1024 bytes per kilobyte
     bytes/1024
   }
-  fn vec_to_4u8_array(vector: Vec<u8>) -> [u8; 4] {
-    let mut array: [u8; 4] = [0u8; 4];
-    //vector.len() should be 4
-    for i in 0..vector.len() {
-      array[i] = vector[i];
-    }
-    return array;
-  }
-  fn vec_to_2u8_array(vector: Vec<u8>) -> [u8; 2] {
-    let mut array: [u8; 2] = [0u8; 2];
-    //vector.len() should be 2
-    for i in 0..vector.len() {
-      array[i] = vector[i];
-    }
-    return array;
-  }
-  fn vec_to_1u8_array(vector: Vec<u8>) -> [u8; 1] {
-    let mut array: [u8; 1] = [0u8; 1];
-    //vector.len() should be 1
-    for i in 0..vector.len() {
-      array[i] = vector[i];
-    }
-    return array;
-  }
-  fn int_to_compression(int: u32) -> String {
-    let compression_table: HashMap<u32, String> = HashMap::from([
-      (0, "BI_RGB".to_string()),
-      (1, "BI_RLE8".to_string()),
-      (2, "BI_RLE4".to_string()),
-      (3, "BI_BITFIELDS".to_string()),
-      (4, "BI_JPEG".to_string()),
-      (5, "BI_PNG".to_string()),
-      (6, "BI_ALPHABITFIELDS".to_string())
-    ]);
-    return compression_table.get(&int).unwrap().to_string();
-  }
-  //color related utilities
-  fn alpha_to_percentage(alpha: u8) -> f64 {
-    //.into() turns the u8 into f64 (expected return type)
-    return (alpha/255).into();
-  }
-  fn rgb_to_color(rgb: [u8; 3]) -> String {
-    //changes rgb to readable color. (takes rgba) eg: black
-    //finds closest color and returns
-    //colors.keys()
-    //([0, 49, 83], "prussian blue")
-    let colors: HashMap<[u8; 3], String> = HashMap::from([
-      ([255, 255, 255], "white".to_string()),
-      ([0, 0, 0], "black".to_string()),
-      ([255, 0, 0], "red".to_string()),
-      ([0, 255, 0], "green".to_string()),
-      ([0, 0, 255], "blue".to_string()),
-      ([255, 128, 0], "orange".to_string()),
-      ([128, 64, 0], "brown".to_string()),
-      ([0, 128, 0], "dark green".to_string()),
-      ([255, 255, 0], "yellow".to_string()),
-      ([128, 128, 128], "gray".to_string()),
-      ([255, 192, 203], "pink".to_string()),
-      ([128, 0, 128], "purple".to_string()),
-      ([0, 128, 255], "azure".to_string()),
-      ([183, 65, 14], "rust".to_string()),
-      ([0, 128, 128], "teal".to_string()),
-      ([192, 192, 192], "silver".to_string()),
-      ([0, 255, 191], "aquamarine".to_string()),
-      ([128, 0, 0], "maroon".to_string())
-    ]);
-    if colors.contains_key(&rgb) {
-      return colors.get(&rgb).unwrap().to_string();
-    } else {
-      //by default lets say its white
-      let mut closest: [u8; 3] = [255, 255, 255];
-      for c in colors.keys() {
-        let r_diff = (c[0] as i8-rgb[0] as i8).abs() as u16;
-        let g_diff = (c[1] as i8-rgb[1] as i8).abs() as u16;
-        let b_diff = (c[2] as i8-rgb[2] as i8).abs() as u16;
-        let total_diff: u16 = r_diff+g_diff+b_diff;
-        let r2_diff = (closest[0] as i8-rgb[0] as i8).abs() as u16;
-        let g2_diff = (closest[1] as i8-rgb[1] as i8).abs() as u16;
-        let b2_diff = (closest[2] as i8-rgb[2] as i8).abs() as u16;
-        let total_diff2: u16 = r2_diff+g2_diff+b2_diff;
-        if total_diff > total_diff2 {
-          closest = *c;
-        }
-      }
-      return "Similar to ".to_string()+colors.get(&closest).unwrap();
+  //pixel rows are padded to a multiple of 4 bytes
+  fn get_row_length(bitcount: u16, width: u32) -> Result<usize, Error> {
+    let bits = match (bitcount as usize).checked_mul(width as usize) {
+      Some(bits) => bits,
+      None => return Err(Error::Overflow { field: "row length" }),
+    };
+    match bits.checked_add(31) {
+      Some(padded_bits) => return Ok(padded_bits/32*4),
+      None => return Err(Error::Overflow { field: "row length" }),
     }
   }
+  //start + a*b, erroring instead of wrapping around
+  fn checked_offset(start: usize, a: usize, b: usize) -> Result<usize, Error> {
+    match a.checked_mul(b).and_then(|product| product.checked_add(start)) {
+      Some(offset) => return Ok(offset),
+      None => return Err(Error::Overflow { field: "offset" }),
+    }
+  }
+  fn int_to_compression(int: u32, os2: bool) -> Compression {
+    return Compression::from_u32(int, os2);
+  }
   //file header related
   fn get_header(&self) -> BITMAPFILEHEADER {
-    let header_bytes: &[u8; 14] = self.get_header_bytes();
+    let header_bytes: [u8; 14] = self.get_header_bytes();
     return BITMAPFILEHEADER {
-      bfType: BMP::bytes_to_string(&header_bytes[..2]),
+      bfType: [header_bytes[0], header_bytes[1]],
       bfSize: BMP::bytes_to_int(header_bytes[2..6].try_into().unwrap()),
-      bfReserved1: header_bytes[6..8].try_into().unwrap(),
-      bfReserved2: header_bytes[8..10].try_into().unwrap(),
-      bfOffBits: BMP::bytes_to_int(header_bytes[10..14].try_into().unwrap()) as u16,
+      bfOffBits: BMP::bytes_to_int(header_bytes[10..14].try_into().unwrap()),
     };
   }
-  fn get_header_bytes(&self) -> &[u8; 14] {
-    //turn slice into array
-    self.contents[..14].try_into().unwrap()
+  fn get_header_bytes(&self) -> [u8; 14] {
+    //missing bytes of a truncated file are read as 0
+    let mut header_bytes: [u8; 14] = [0; 14];
+    let length = self.contents.len().min(14);
+    header_bytes[..length].copy_from_slice(&self.contents[..length]);
+    return header_bytes;
   }
-  fn get_offset(&self) -> u16 {
+  fn get_offset(&self) -> u32 {
     self.get_header().bfOffBits
   }
-  pub fn get_size(&self, use_header: bool) -> u32 {
+  //WrongFileType if it isn't one of the bitmap signatures
+  pub fn get_signature(&self) -> Result<Signature, Error> {
+    if self.contents.len() < 2 {
+      return Err(Error::Truncated { offset: 0, length: 2, file_size: self.contents.len() });
+    }
+    return match Signature::from_bytes(self.get_header().bfType) {
+      Some(signature) => Ok(signature),
+      None => Err(Error::WrongFileType),
+    };
+  }
+  //bfSize, or the actual length of the contents, which can be more than a u32 holds
+  pub fn get_size(&self, use_header: bool) -> usize {
     if use_header {
-      return self.get_header().bfSize;
+      return self.get_header().bfSize as usize;
     } else {
-      return self.contents.len().try_into().unwrap();
+      return self.contents.len();
     }
   }
   //dib header related
-  pub fn get_dib_header(&self) -> Result<DIBHEADER, ErrorKind> {
+  pub fn get_dib_header(&self) -> Result<DIBHEADER, Error> {
     //this will not work because there may be other data besides the DIB header
     //let dib_size: i32 = self.get_offset()-14;
     //instead we will read the first 4 bytes after the header, which *should* specify the DIB header size, so we can figure out what kind of header it is
+    match self.get_signature() {
+      //there is no single dib header, see get_bitmap_array and extract_from_bitmap_array
+      Ok(Signature::BitmapArray) => return Err(Error::Unsupported("bitmap arrays have no single dib header")),
+      Ok(_) => {},
+      Err(e) => return Err(e),
+    };
+    if self.contents.len() < HEADER_OFFSET+4 {
+      return Err(Error::Truncated { offset: 0, length: HEADER_OFFSET+4, file_size: self.contents.len() });
+    }
     let dib_size: u32 = BMP::bytes_to_int(self.contents[HEADER_OFFSET..HEADER_OFFSET+4].try_into().unwrap());
+    if ([12, 40, 108, 124].contains(&dib_size) || BMP::is_os2_header_size(dib_size)) && self.contents.len() < HEADER_OFFSET+dib_size as usize {
+      return Err(Error::Truncated { offset: HEADER_OFFSET, length: dib_size as usize, file_size: self.contents.len() });
+    }
     let dib_header: DIBHEADER;
     match dib_size {
       12 => {
         //"BITMAPCOREHEADER"
         dib_header = DIBHEADER {
-          size: dib_size as u16,
+          size: dib_size,
           width: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+6].try_into().unwrap()) as u32,
           height: BMP::two_bytes_to_signed_int(self.contents[HEADER_OFFSET+6..HEADER_OFFSET+8].try_into().unwrap()) as i32,
-          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+10].try_into().unwrap()) as u16,
-          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+10..HEADER_OFFSET+12].try_into().unwrap()) as u16,
+          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+10].try_into().unwrap()),
+          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+10..HEADER_OFFSET+12].try_into().unwrap()),
           compression: None,
           sizeimage: None,
           XPelsPerMeter: None,
@@ -381,17 +1127,24 @@ impl BMP {
           ProfileData: None,
           ProfileSize: None,
           Reserved: None,
+          Units: None,
+          Recording: None,
+          Rendering: None,
+          Size1: None,
+          Size2: None,
+          ColorEncoding: None,
+          Identifier: None,
         };
       },
       40 => {
         //"BITMAPINFOHEADER"
         dib_header = DIBHEADER {
-          size: dib_size as u16,
+          size: dib_size,
           width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
           height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
-          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()) as u16,
-          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()) as u16,
-          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()))),
+          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
+          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
+          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()), false)),
           sizeimage: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+20..HEADER_OFFSET+24].try_into().unwrap())),
           XPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+24..HEADER_OFFSET+28].try_into().unwrap())),
           YPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+28..HEADER_OFFSET+32].try_into().unwrap())),
@@ -410,17 +1163,24 @@ impl BMP {
           ProfileData: None,
           ProfileSize: None,
           Reserved: None,
+          Units: None,
+          Recording: None,
+          Rendering: None,
+          Size1: None,
+          Size2: None,
+          ColorEncoding: None,
+          Identifier: None,
         };
       },
       108 => {
         //"BITMAPV4HEADER"
         dib_header = DIBHEADER {
-          size: dib_size as u16,
+          size: dib_size,
           width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
           height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
-          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()) as u16,
-          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()) as u16,
-          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()))),
+          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
+          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
+          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()), false)),
           sizeimage: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+20..HEADER_OFFSET+24].try_into().unwrap())),
           XPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+24..HEADER_OFFSET+28].try_into().unwrap())),
           YPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+28..HEADER_OFFSET+32].try_into().unwrap())),
@@ -430,7 +1190,7 @@ impl BMP {
           GreenMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+44..HEADER_OFFSET+48].try_into().unwrap())),
           BlueMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+48..HEADER_OFFSET+52].try_into().unwrap())),
           AlphaMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+52..HEADER_OFFSET+56].try_into().unwrap())),
-          CSType: Some(BMP::bytes_to_string(&self.contents[HEADER_OFFSET+56..HEADER_OFFSET+60])),
+          CSType: Some(CSType::from_u32(BMP::bytes_to_int(self.contents[HEADER_OFFSET+56..HEADER_OFFSET+60].try_into().unwrap()))),
           //rgb
           Endpoints: Some([[BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+60..HEADER_OFFSET+64].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+64..HEADER_OFFSET+68].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+68..HEADER_OFFSET+72].try_into().unwrap())], [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+72..HEADER_OFFSET+76].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+76..HEADER_OFFSET+80].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+80..HEADER_OFFSET+84].try_into().unwrap())], [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+84..HEADER_OFFSET+88].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+88..HEADER_OFFSET+92].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+92..HEADER_OFFSET+96].try_into().unwrap())]]),
           GammaRed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+96..HEADER_OFFSET+100].try_into().unwrap())),
@@ -440,6 +1200,13 @@ impl BMP {
           ProfileData: None,
           ProfileSize: None,
           Reserved: None,
+          Units: None,
+          Recording: None,
+          Rendering: None,
+          Size1: None,
+          Size2: None,
+          ColorEncoding: None,
+          Identifier: None,
         };
       },
       124 => {
@@ -448,12 +1215,12 @@ impl BMP {
           //long 4 bytes
           //CIEXYZTRIPLE 36 bytes
         dib_header = DIBHEADER {
-          size: dib_size as u16,
+          size: dib_size,
           width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
           height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
-          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()) as u16,
-          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()) as u16,
-          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()))),
+          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
+          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
+          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()), false)),
           sizeimage: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+20..HEADER_OFFSET+24].try_into().unwrap())),
           XPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+24..HEADER_OFFSET+28].try_into().unwrap())),
           YPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+28..HEADER_OFFSET+32].try_into().unwrap())),
@@ -463,650 +1230,3601 @@ impl BMP {
           GreenMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+44..HEADER_OFFSET+48].try_into().unwrap())),
           BlueMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+48..HEADER_OFFSET+52].try_into().unwrap())),
           AlphaMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+52..HEADER_OFFSET+56].try_into().unwrap())),
-          CSType: Some(BMP::bytes_to_string(&self.contents[HEADER_OFFSET+56..HEADER_OFFSET+60])),
+          CSType: Some(CSType::from_u32(BMP::bytes_to_int(self.contents[HEADER_OFFSET+56..HEADER_OFFSET+60].try_into().unwrap()))),
           //rgb
           Endpoints: Some([[BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+60..HEADER_OFFSET+64].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+64..HEADER_OFFSET+68].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+68..HEADER_OFFSET+72].try_into().unwrap())],  [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+72..HEADER_OFFSET+76].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+76..HEADER_OFFSET+80].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+80..HEADER_OFFSET+84].try_into().unwrap())], [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+84..HEADER_OFFSET+88].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+88..HEADER_OFFSET+92].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+92..HEADER_OFFSET+96].try_into().unwrap())]]),
           GammaRed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+96..HEADER_OFFSET+100].try_into().unwrap())),
           GammaGreen: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+100..HEADER_OFFSET+104].try_into().unwrap())),
           GammaBlue: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+104..HEADER_OFFSET+108].try_into().unwrap())),
-          Intent: Some(BMP::bytes_to_string(&self.contents[HEADER_OFFSET+108..HEADER_OFFSET+112])),
-          ProfileData: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+112..HEADER_OFFSET+116].try_into().unwrap()) as u16),
-          ProfileSize: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+116..HEADER_OFFSET+120].try_into().unwrap()) as u16),
-          Reserved: Some(self.contents[HEADER_OFFSET+120..HEADER_OFFSET+124].try_into().unwrap()),
+          Intent: Some(Intent::from_u32(BMP::bytes_to_int(self.contents[HEADER_OFFSET+108..HEADER_OFFSET+112].try_into().unwrap()))),
+          ProfileData: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+112..HEADER_OFFSET+116].try_into().unwrap())),
+          ProfileSize: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+116..HEADER_OFFSET+120].try_into().unwrap())),
+          Reserved: Some(self.contents[HEADER_OFFSET+120..HEADER_OFFSET+124].to_vec()),
+          Units: None,
+          Recording: None,
+          Rendering: None,
+          Size1: None,
+          Size2: None,
+          ColorEncoding: None,
+          Identifier: None,
+        };
+      },
+      _ if BMP::is_os2_header_size(dib_size) => {
+        //"BITMAPCOREHEADER2", OS/2 2.x
+        //http://www.fileformat.info/format/os2bmp/egff.htm
+        //it can be cut short after any field (as long as it's at least 16 bytes), missing fields count as 0
+        let field = |start: usize, length: usize| -> Option<u32> {
+          if start+length > dib_size as usize {
+            return None;
+          }
+          let mut bytes: [u8; 4] = [0; 4];
+          bytes[..length].copy_from_slice(&self.contents[HEADER_OFFSET+start..HEADER_OFFSET+start+length]);
+          return Some(BMP::bytes_to_int(bytes));
+        };
+        dib_header = DIBHEADER {
+          size: dib_size,
+          width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
+          height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
+          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
+          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
+          //3 and 4 mean huffman 1D and RLE24 here
+          compression: field(16, 4).map(|compression| BMP::int_to_compression(compression, true)),
+          sizeimage: field(20, 4),
+          XPelsPerMeter: field(24, 4),
+          YPelsPerMeter: field(28, 4),
+          ClrUsed: field(32, 4),
+          ClrImportant: field(36, 4),
+          RedMask: None,
+          GreenMask: None,
+          BlueMask: None,
+          AlphaMask: None,
+          CSType: None,
+          Endpoints: None,
+          GammaRed: None,
+          GammaGreen: None,
+          GammaBlue: None,
+          Intent: None,
+          ProfileData: None,
+          ProfileSize: None,
+          Reserved: None,
+          Units: field(40, 2).map(|units| units as u16),
+          Recording: field(44, 2).map(|recording| recording as u16),
+          Rendering: field(46, 2).map(|rendering| rendering as u16),
+          Size1: field(48, 4),
+          Size2: field(52, 4),
+          ColorEncoding: field(56, 4),
+          Identifier: field(60, 4),
         };
       },
       _ => {
         //"unsupported"
-        return Err(ErrorKind::Unsupported);
+        return Err(Error::InvalidHeaderField { field: "biSize", value: dib_size as i64 });
       },
     }
     return Ok(dib_header);
   }
-  //extra bit masks
-  fn get_extra_bit_masks(&self) -> Result<EXTRA_BIT_MASKS, ErrorKind> {
-    //should be mutable instead of redefined, maybe
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
+  //OS/2 2.x headers can be any size from 16 to 64 bytes
+  //40 is a BITMAPINFOHEADER, and 52 and 56 are taken to be Adobe's BITMAPV2INFOHEADER and BITMAPV3INFOHEADER, which aren't supported
+  fn is_os2_header_size(size: u32) -> bool {
+    return (16..=64).contains(&size) && size != 40 && size != 52 && size != 56;
+  }
+  //OS/2 bitmap arrays ("BA") hold several versions of an image, eg: for different screen resolutions
+  //each BITMAPARRAYFILEHEADER has the offset of the next one, and is followed by a normal file header and dib header
+  //http://www.fileformat.info/format/os2bmp/egff.htm
+  pub fn get_bitmap_array(&self) -> Result<Vec<BitmapArrayEntry>, Error> {
+    let images = self.get_bitmap_array_images();
+    return match images {
+      Ok(returned_images) => Ok(returned_images.into_iter().map(|(entry, _)| entry).collect()),
+      Err(e) => Err(e),
     };
-    match dib_header.size {
-      40 => {
-        //see previous comment, should be mutable instead of redefined, maybe
-        //offset should be 14+40
-        let TOTAL_OFFSET = 54;
-        let compression = dib_header.compression.unwrap();
-        if compression == "BI_BITFIELDS" {
-          return Ok(EXTRA_BIT_MASKS::BI_BITFIELDS_MASKS(BI_BITFIELDS_MASKS {
-            red: BMP::bytes_to_int(self.contents[TOTAL_OFFSET..TOTAL_OFFSET+4].try_into().unwrap()),
-            green: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+4..TOTAL_OFFSET+8].try_into().unwrap()),
-            blue: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+8..TOTAL_OFFSET+12].try_into().unwrap()),
-          }));
-        } else if compression == "BI_ALPHABITFIELDS" {
-          return Ok(EXTRA_BIT_MASKS::BI_ALPHABITFIELDS_MASKS(BI_ALPHABITFIELDS_MASKS {
-            red: BMP::bytes_to_int(self.contents[TOTAL_OFFSET..TOTAL_OFFSET+4].try_into().unwrap()),
-            green: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+4..TOTAL_OFFSET+8].try_into().unwrap()),
-            blue: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+8..TOTAL_OFFSET+12].try_into().unwrap()),
-            alpha: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+12..TOTAL_OFFSET+16].try_into().unwrap()),
-          }));
-        } else {
-          return Err(ErrorKind::DoesNotExist);
-        }
-      },
-      _ => return Err(ErrorKind::DoesNotExist),
-    }
   }
-  //color table
-  //in between pixel array and everything else, I guess?
-  //update: use the dib header's 'size' attribute - the actual size
-  //return some kind of vector/array
-  fn get_color_table(&self) -> Result<ColorTable, ErrorKind> {
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
+  //the image as a bitmap file of its own
+  pub fn extract_from_bitmap_array(&self, index: usize) -> Result<BMP, Error> {
+    let images = self.get_bitmap_array_images();
+    let images = match images {
+      Ok(returned_images) => returned_images,
       Err(e) => return Err(e),
     };
-    //match (?) and extract header, get size
-    //14 is the file header size
-    let mut offset: u16 = 14;
-    //where the actual pixel data starts, so the color table must end sometime before
-    let end: u16;
-    //either rgbtriple or masks or 
-    let data_type: &str;
-    //12, 40, 108, 124
-    match dib_header.size {
-      /*DIBHEADER::BITMAPCOREHEADER(b) | DIBHEADER::BITMAPINFOHEADER(b) | DIBHEADER::BITMAPV4HEADER(b) | DIBHEADER::BITMAPV5HEADER(b) => {
-        size = b.size;
-      }*/
-      12 => {
-        //https://docs.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-bitmapcoreinfo
-        offset += dib_header.size;
-        end = self.get_header().bfOffBits;
-        //RGBTRIPLE, 3 bytes
-        data_type = "rgbtriple";
-      },
-      40 | 108 | 124 => {
-        //16 bit array instead of rgbquad is possible, but should not be used if file is "stored in a file or transferred to another application" https://www.digicamsoft.com/bmp/bmp.html
-        offset += dib_header.size;
-        end = self.get_header().bfOffBits;
-        //https://docs.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-bitmapinfo
-        //if compression is BI_RGB, using RGBQUAD 
-        //size of array is biClrUsed
-        let compression = dib_header.compression.unwrap();
-        if compression == "BI_BITFIELDS" && (dib_header.bitcount == 16 || dib_header.bitcount == 32) {
-          //extra bit masks, not color table. return error, or maybe extra bit masks? hmm
-          return Err(ErrorKind::UseExtraBitMasks);
-        } else if compression == "BI_RGB" && dib_header.bitcount >= 16 {
-          //no color table
-          //color table used for optimizing color palette or something instead, idk
-          return Err(ErrorKind::DoesNotExist);
-        } else {
-          data_type = "rgbquad";
+    return match images.into_iter().nth(index) {
+      Some((_, image)) => Ok(image),
+      None => Err(Error::DoesNotExist("bitmap array image")),
+    };
+  }
+  fn get_bitmap_array_images(&self) -> Result<Vec<(BitmapArrayEntry, BMP)>, Error> {
+    let mut images: Vec<(BitmapArrayEntry, BMP)> = Vec::new();
+    let mut offset: usize = 0;
+    loop {
+      if self.contents.len() < offset+HEADER_OFFSET {
+        return Err(Error::Truncated { offset, length: HEADER_OFFSET, file_size: self.contents.len() });
+      }
+      if Signature::from_bytes([self.contents[offset], self.contents[offset+1]]) != Some(Signature::BitmapArray) {
+        if offset == 0 {
+          return Err(Error::WrongFileType);
         }
+        return Err(Error::InvalidHeaderField { field: "offNext", value: offset as i64 });
+      }
+      let next = BMP::bytes_to_int(self.contents[offset+6..offset+10].try_into().unwrap()) as usize;
+      let display_width = BMP::two_bytes_to_int(self.contents[offset+10..offset+12].try_into().unwrap());
+      let display_height = BMP::two_bytes_to_int(self.contents[offset+12..offset+14].try_into().unwrap());
+      let mut image_start = offset+HEADER_OFFSET;
+      let image_type = match self.contents.get(image_start..image_start+2) {
+        Some(image_type) => Signature::from_bytes([image_type[0], image_type[1]]),
+        None => return Err(Error::Truncated { offset: image_start, length: HEADER_OFFSET, file_size: self.contents.len() }),
+      };
+      let image_type = match image_type {
+        Some(Signature::BitmapArray) | None => return Err(Error::WrongFileType),
+        Some(returned_image_type) => returned_image_type,
+      };
+      if image_type == Signature::ColorIcon || image_type == Signature::ColorPointer {
+        //color icons and pointers have the AND/XOR mask first, then the file header of the color image right after its color table
+        image_start = match self.get_embedded_headers_end(image_start) {
+          Ok((headers_end, _)) => headers_end,
+          Err(e) => return Err(e),
+        };
+      }
+      let image = match self.extract_embedded_bitmap(image_start) {
+        Ok(returned_image) => returned_image,
+        Err(e) => return Err(e),
+      };
+      let dib_header = match image.get_dib_header() {
+        Ok(returned_dib_header) => returned_dib_header,
+        Err(e) => return Err(e),
+      };
+      images.push((BitmapArrayEntry {
+        offset,
+        display_width,
+        display_height,
+        image_type,
+        width: dib_header.width,
+        height: dib_header.height,
+        bitcount: dib_header.bitcount,
+      }, image));
+      if next == 0 {
+        break;
+      }
+      //only going forwards, so a bad offNext can't loop forever
+      if next <= offset {
+        return Err(Error::InvalidHeaderField { field: "offNext", value: next as i64 });
+      }
+      offset = next;
+    }
+    return Ok(images);
+  }
+  //end of the file header, dib header and color table of a bitmap embedded at start
+  fn get_embedded_headers_end(&self, start: usize) -> Result<(usize, DIBHEADER), Error> {
+    if self.contents.len() < start+HEADER_OFFSET+4 {
+      return Err(Error::Truncated { offset: start, length: HEADER_OFFSET+4, file_size: self.contents.len() });
+    }
+    let dib_size = BMP::bytes_to_int(self.contents[start+HEADER_OFFSET..start+HEADER_OFFSET+4].try_into().unwrap()) as usize;
+    if self.contents.len() < start+HEADER_OFFSET+dib_size {
+      return Err(Error::Truncated { offset: start+HEADER_OFFSET, length: dib_size, file_size: self.contents.len() });
+    }
+    //the headers on their own are enough to parse the dib header
+    let headers = BMP::from_bytes(self.contents[start..start+HEADER_OFFSET+dib_size].to_vec());
+    let dib_header = match headers.get_dib_header() {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    let entry_size: usize = if dib_size == 12 { 3 } else { 4 };
+    let mut colors: usize = 0;
+    if dib_header.bitcount <= 8 {
+      colors = 1 << dib_header.bitcount;
+      if let Some(clr_used) = dib_header.ClrUsed {
+        if clr_used != 0 {
+          colors = clr_used as usize;
+        }
+      }
+    }
+    let table_end = start+HEADER_OFFSET+dib_size+colors*entry_size;
+    if self.contents.len() < table_end {
+      return Err(Error::Truncated { offset: start+HEADER_OFFSET+dib_size, length: colors*entry_size, file_size: self.contents.len() });
+    }
+    return Ok((table_end, dib_header));
+  }
+  //copies the bitmap whose file header is at start out into a file of its own. its bfOffBits is from the start of the whole file
+  fn extract_embedded_bitmap(&self, start: usize) -> Result<BMP, Error> {
+    let (table_end, dib_header) = match self.get_embedded_headers_end(start) {
+      Ok(returned_headers_end) => returned_headers_end,
+      Err(e) => return Err(e),
+    };
+    let pixel_offset = BMP::bytes_to_int(self.contents[start+10..start+14].try_into().unwrap()) as usize;
+    let mut pixel_length = match BMP::get_row_length(dib_header.bitcount, dib_header.width) {
+      Ok(row_length) => match row_length.checked_mul(dib_header.height.unsigned_abs() as usize) {
+        Some(pixel_length) => pixel_length,
+        None => return Err(Error::Overflow { field: "pixel array" }),
       },
-      _ => {
-        return Err(ErrorKind::DoesNotExist);
-      },
+      Err(e) => return Err(e),
+    };
+    if dib_header.compression.unwrap_or(Compression::BI_RGB) != Compression::BI_RGB {
+      //compressed data is sizeimage long, or if that isn't set, assume it goes to the end of the file
+      pixel_length = match dib_header.sizeimage {
+        Some(sizeimage) if sizeimage != 0 => sizeimage as usize,
+        _ => self.contents.len().saturating_sub(pixel_offset),
+      };
+    }
+    let pixel_end = match pixel_offset.checked_add(pixel_length) {
+      Some(pixel_end) if pixel_end <= self.contents.len() => pixel_end,
+      _ => return Err(Error::Truncated { offset: pixel_offset, length: pixel_length, file_size: self.contents.len() }),
     };
-    let color_table: ColorTable;
-    if data_type == "rgbtriple" {
-      let mut color_table_vec: Vec::<[u8; 3]> = Vec::new();
-      //3 bytes
-      for i in 0..(f64::from((end-offset)/3).floor() as i64) {
-        let ii = i as u16;
-        color_table_vec.push([BMP::byte_to_int(self.contents[(offset+ii*3) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*3+1) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*3+2) as usize]) as u8]);
-      }
-      color_table = ColorTable::RGBTRIPLE(color_table_vec);
-    } else /*if "rgbquad" == data_type*/ {
-      let mut color_table_vec: Vec::<[u8; 4]> = Vec::new();
-      //4 bytes
-      for i in 0..(f64::from((end-offset)/4).floor() as i64) {
-        let ii = i as u16;
-        color_table_vec.push([BMP::byte_to_int(self.contents[(offset+ii*4) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*4+1) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*4+2) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*4+3) as usize]) as u8]);
-      }
-      color_table = ColorTable::RGBQUAD(color_table_vec);
-    }
-    return Ok(color_table);
-  }
-  //pixel array
-  fn get_pixel_data(&self) -> Result<VecDeque<Vec<Vec<u8>>>, ErrorKind> {
-    //figure out if top down or bottom up
-    //let it panic if it is an error
+    let mut contents: Vec<u8> = self.contents[start..table_end].to_vec();
+    let new_offset = contents.len();
+    contents.extend_from_slice(&self.contents[pixel_offset..pixel_end]);
+    if contents.len() > u32::MAX as usize {
+      return Err(Error::Overflow { field: "bfSize" });
+    }
+    let file_size = contents.len() as u32;
+    BMP::write_int(&mut contents, 2, file_size);
+    BMP::write_int(&mut contents, 10, new_offset as u32);
+    return Ok(BMP::from_bytes(contents));
+  }
+  pub fn get_halftoning(&self) -> Result<Halftoning, Error> {
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
-    //figure out row size and image height
-    //figure out pixel formatw1
-    //figure out is padded
-    //monochrome is 1 bit per pixel. lets not support that for now
-    //Vec<[[u8; dib_header.bitcount/4]; dib_header.width]>
-    //change to array https://discord.com/channels/273534239310479360/273541522815713281/951356330696912967
-    let mut rows: VecDeque<Vec<Vec<u8>>> = VecDeque::new();
-    let header = self.get_header();
-    if dib_header.height < 0 {
-      //top down (starts from top left)
-      //add rows as normal, to the back of vector
-      //header.bfOffBits
-      //https://en.wikipedia.org/wiki/BMP_file_format#Pixel_storage
-      let row_length = f64::from((dib_header.bitcount as u16*dib_header.width as u16/32)).ceil() as u32 * 4;
-      //this may not work if there is profile data or other stuff after image?
-      let rows_num = (self.contents.len() as u32-header.bfOffBits as u32)/row_length;
-      for row_num in 0..rows_num {
-        //let row: Vec<[u8; dib_header.bitcount/4]> = Vec::new();
-        let mut row: Vec<Vec<u8>> = Vec::new();
-        for pixel in 0..dib_header.width {
-          if dib_header.bitcount >= 8 {
-            let start: u32 = (header.bfOffBits as u32)+(row_num as u32)*row_length+(pixel as u32)*((dib_header.bitcount/8) as u32);
-            row.push(self.contents[start as usize..(start+(dib_header.bitcount/8) as u32) as usize].to_vec());
-          } else {
-            //we need to do bitwise operators if the pixels are smaller than 1 byte size (1 bit, 2 bit, 4 bit)
-            let start: u32 = (header.bfOffBits as u32)+(row_num as u32)*row_length+(pixel as u32)*(((dib_header.bitcount/8) as f64).ceil() as u32);
-            let byte: u8 = self.contents[start as usize];
-            if dib_header.bitcount == 1 {
-              let split_bits: [u8; 8] = [byte >> 7, (byte & 0b01000000) >> 6, (byte & 0b00100000) >> 5, (byte & 0b00010000) >> 4, (byte & 0b00001000) >> 3, (byte & 0b00000100) >> 2, (byte & 0b00000010) >> 1, byte & 0b00000001];
-              row.push(vec![split_bits[(pixel % ((8/dib_header.bitcount) as u32)) as usize]]);
-            } else if dib_header.bitcount == 2 {
-              let split_bits: [u8; 4] = [byte >> 6, (byte & 0b00110000) >> 4, (byte & 0b00001100) >> 2, byte & 0b00000011];
-              row.push(vec![split_bits[(pixel % ((8/dib_header.bitcount) as u32)) as usize]]);
-            } else if dib_header.bitcount == 4 {
-              let split_bits: [u8; 2] = [byte >> 4, byte & 0b00001111];
-              row.push(vec![split_bits[(pixel % ((8/dib_header.bitcount) as u32)) as usize]]);
-            }
-          }
-        }
-        rows.push_back(row);
-      }
-      //self.contents[]
-    } else if dib_header.height > 0 {
-      //bottom up (starts from lower left)
-      //add rows to front of vector
-      //let start: u32 = (header.bfOffBits as u32)+(row_num as u32)*row_length+(pixel as u32)*((dib_header.bitcount/8) as u32);
-      let row_length = f64::from((dib_header.bitcount as u16*dib_header.width as u16/32)).ceil() as u32 * 4;
-      let rows_num = (self.contents.len() as u32-header.bfOffBits as u32)/row_length;
-      for row_num in 0..rows_num {
-        let mut row: Vec<Vec<u8>> = Vec::new();
-        for pixel in 0..dib_header.width {
-          if dib_header.bitcount >= 8 {
-            let start: u32 = (header.bfOffBits as u32)+(row_num as u32)*row_length+(pixel as u32)*((dib_header.bitcount/8) as u32);
-            row.push(self.contents[start as usize..(start+(dib_header.bitcount/8) as u32) as usize].to_vec());
-          } else {
-            //we need to do bitwise operators if the pixels are smaller than 1 byte size (1 bit, 2 bit, 4 bit)
-            let start: u32 = (header.bfOffBits as u32)+(row_num as u32)*row_length+(pixel as u32)*(((dib_header.bitcount/8) as f64).ceil() as u32);
-            let byte: u8 = self.contents[start as usize];
-            if dib_header.bitcount == 1 {
-              let split_bits: [u8; 8] = [byte >> 7, (byte & 0b01000000) >> 6, (byte & 0b00100000) >> 5, (byte & 0b00010000) >> 4, (byte & 0b00001000) >> 3, (byte & 0b00000100) >> 2, (byte & 0b00000010) >> 1, byte & 0b00000001];
-              row.push(vec![split_bits[(pixel % ((8/dib_header.bitcount) as u32)) as usize]]);
-            } else if dib_header.bitcount == 2 {
-              let split_bits: [u8; 4] = [byte >> 6, (byte & 0b00110000) >> 4, (byte & 0b00001100) >> 2, byte & 0b00000011];
-              row.push(vec![split_bits[(pixel % ((8/dib_header.bitcount) as u32)) as usize]]);
-            } else if dib_header.bitcount == 4 {
-              let split_bits: [u8; 2] = [byte >> 4, byte & 0b00001111];
-              row.push(vec![split_bits[(pixel % ((8/dib_header.bitcount) as u32)) as usize]]);
-            }
-          }
-        }
-        rows.push_front(row);
-      }
+    if !BMP::is_os2_header_size(dib_header.size) {
+      return Err(Error::DoesNotExist("halftoning, which is only in OS/2 2.x headers"));
+    }
+    let size1 = dib_header.Size1.unwrap_or(0);
+    let size2 = dib_header.Size2.unwrap_or(0);
+    match dib_header.Rendering.unwrap_or(0) {
+      0 => return Ok(Halftoning::None),
+      1 => return Ok(Halftoning::ErrorDiffusion { damping: size1 }),
+      2 => return Ok(Halftoning::Panda { x: size1, y: size2 }),
+      3 => return Ok(Halftoning::SuperCircle { x: size1, y: size2 }),
+      rendering => return Ok(Halftoning::Unknown(rendering)),
     }
-    return Ok(rows);
   }
-  //location here is told
-  //ICC color profile
-  fn get_color_profile(&self) -> Result<Vec<u8>, ErrorKind> {
-    //https://en.wikipedia.org/wiki/Color_management
-    //CIEXYZTRIPLE ?
-    //https://www.color.org/ICC_Minor_Revision_for_Web.pdf
-    //seems pretty complex, and niche. We'll check if it exists, if so, return raw bytes data, otherwise return error
+  //extra bit masks
+  fn get_extra_bit_masks(&self) -> Result<EXTRA_BIT_MASKS, Error> {
+    //should be mutable instead of redefined, maybe
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
     match dib_header.size {
-      124 => {
-        let cstype = dib_header.CSType.unwrap();
-        if cstype == "PROFILE_EMBEDDED" || cstype == "PROFILE_LINKED" {
-          return Ok(self.contents[dib_header.ProfileData.unwrap() as usize..].to_vec());
-          //dib_header.ProfileData.unwrap()..dib_header.ProfileData.unwrap()+dib_header.ProfileSize.unwrap()
+      40 => {
+        //see previous comment, should be mutable instead of redefined, maybe
+        //offset should be 14+40
+        let TOTAL_OFFSET = 54;
+        let compression = dib_header.compression.unwrap();
+        let length = if compression == Compression::BI_ALPHABITFIELDS { 16 } else { 12 };
+        if self.contents.len() < TOTAL_OFFSET+length {
+          return Err(Error::Truncated { offset: TOTAL_OFFSET, length, file_size: self.contents.len() });
+        }
+        if compression == Compression::BI_BITFIELDS {
+          return Ok(EXTRA_BIT_MASKS::BI_BITFIELDS_MASKS(BI_BITFIELDS_MASKS {
+            red: BMP::bytes_to_int(self.contents[TOTAL_OFFSET..TOTAL_OFFSET+4].try_into().unwrap()),
+            green: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+4..TOTAL_OFFSET+8].try_into().unwrap()),
+            blue: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+8..TOTAL_OFFSET+12].try_into().unwrap()),
+          }));
+        } else if compression == Compression::BI_ALPHABITFIELDS {
+          return Ok(EXTRA_BIT_MASKS::BI_ALPHABITFIELDS_MASKS(BI_ALPHABITFIELDS_MASKS {
+            red: BMP::bytes_to_int(self.contents[TOTAL_OFFSET..TOTAL_OFFSET+4].try_into().unwrap()),
+            green: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+4..TOTAL_OFFSET+8].try_into().unwrap()),
+            blue: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+8..TOTAL_OFFSET+12].try_into().unwrap()),
+            alpha: BMP::bytes_to_int(self.contents[TOTAL_OFFSET+12..TOTAL_OFFSET+16].try_into().unwrap()),
+          }));
         } else {
-          return Err(ErrorKind::DoesNotExist);
+          return Err(Error::DoesNotExist("extra bit masks"));
         }
       },
-      _ => return Err(ErrorKind::DoesNotExist),
+      _ => return Err(Error::DoesNotExist("extra bit masks")),
     }
-    //Intent: String,
-    //ProfileData: u16,
-    //ProfileSize: u16,
-    //CSType: String
   }
-  //interpret color data
-  //returns an array rgba (4 u8)
-  pub fn get_color_of_px(&self, x: usize, y: usize) -> Result<[u8; 4], ErrorKind> {
+  //color table
+  //in between pixel array and everything else, I guess?
+  //update: use the dib header's 'size' attribute - the actual size
+  //returns (start, bytes per entry, number of entries)
+  fn get_color_table_position(&self) -> Result<(usize, usize, usize), Error> {
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
-    //need to check if error
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let pixel: &Vec<u8> = &pixel_data[y][x];
-    let pixel: Vec<u8> = pixel.to_vec();
-    //TODO: incorporate masks
-    //if more than 12 bytes dib header, there are masks
-    //RedMask, GreenMask, BlueMask, AlphaMask
-    //if BI_BITFIELDS and 16 or 24 bits
-    //also for smaller dib header (info), check to see if there are extra bit masks
-    if dib_header.bitcount == 16 {
-      let compression = dib_header.compression.unwrap();
-      if compression == "BI_BITFIELDS" && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        let rgba: [u8; 4];
-        //these should be from extra bit masks!
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        let green_mask: u32 = dib_header.RedMask.unwrap();
-        let blue_mask: u32 = dib_header.RedMask.unwrap();
-        if red_mask < blue_mask {
-          //assume rgb
-          rgba = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), 255];
-        } else {
-          //assume brg
-          rgba = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), 255];
+    let mut start: usize = HEADER_OFFSET+dib_header.size as usize;
+    let entry_size: usize;
+    let mut count: usize;
+    //12, 40, 108, 124
+    match dib_header.size {
+      12 => {
+        //https://docs.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-bitmapcoreinfo
+        //RGBTRIPLE, 3 bytes. there is no biClrUsed, so the table is always full size
+        entry_size = 3;
+        count = if dib_header.bitcount <= 8 { 1 << dib_header.bitcount } else { 0 };
+      },
+      _ => {
+        //https://docs.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-bitmapinfo
+        //RGBQUAD, 4 bytes. the extra bit masks of a BITMAPINFOHEADER come first
+        entry_size = 4;
+        let compression = dib_header.compression.unwrap_or(Compression::BI_RGB);
+        if dib_header.size == 40 && compression == Compression::BI_BITFIELDS {
+          start += 12;
+        } else if dib_header.size == 40 && compression == Compression::BI_ALPHABITFIELDS {
+          start += 16;
         }
-        return Ok(rgba);
-      } else {
-        //compression is "BI_RGB"
-        //5 for each r,g,b (15 bits + 1 per pixel)
-        //currently placeholder
-        return Ok([0, 0, 0, 255]);
-      }
-    } else if dib_header.bitcount == 24 {
-      //if 24 bit, no need to look at color table because it is rgb.
-      //there is no alpha value, so it is 100 (nontransparent/opaque)
-      //order is BGR not RGB
-      let rgba: [u8; 4] = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), 255];
-      return Ok(rgba);
-    } else if dib_header.bitcount == 32 {
-      //32 means rgba
-      let compression = dib_header.compression.unwrap();
-      if (compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS") && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        //placeholder
-        //determine if alpha is in front or back. determine is rgb or brg
-        let rgba: [u8; 4];
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        let green_mask: u32 = dib_header.RedMask.unwrap();
-        let blue_mask: u32 = dib_header.RedMask.unwrap();
-        let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-        if alpha_mask < red_mask {
-          //println!("{} {}", alpha_mask, red_mask);
-          //alpha is in front
-          if red_mask < blue_mask {
-            //argb
-            rgba = [BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3]), BMP::byte_to_int(pixel[0])];
-          } else {
-            //abgr
-            rgba = [BMP::byte_to_int(pixel[3]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0])];
-          }
+        //size of array is biClrUsed, or the max amount of colors if it is 0
+        //for 16, 24 and 32 bit the table is optional and only used for optimizing the color palette on some devices
+        let clr_used = dib_header.ClrUsed.unwrap_or(0) as usize;
+        if clr_used != 0 && dib_header.bitcount <= 8 {
+          //entries past what the indexes can reach are never used
+          count = clr_used.min(1 << dib_header.bitcount);
+        } else if clr_used != 0 {
+          count = clr_used;
+        } else if dib_header.bitcount <= 8 {
+          count = 1 << dib_header.bitcount;
         } else {
-          //alpha is in back
-          if red_mask < blue_mask {
-            //rgba
-            rgba = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3])];
-          } else {
-            //bgra
-            rgba = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[3])];
-          }
+          count = 0;
         }
-        return Ok(rgba);
-      } else {
-        let rgba: [u8; 4] = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3])];
-        return Ok(rgba);
-      }
-    } else {
-      //otherwise look at color table for corresponding color. The bit (s) in the pixel data are indexes. We look up the index in the color table to find the color
-      let color_table = self.get_color_table();
-      let color_table = match color_table {
-        Ok(returned_color_table) => returned_color_table,
-        Err(e) => return Err(e),
-      };
-      //1, 2, 4 (half byte), 8 (1 bytes), 16 (2 bytes)
-      let index;
-      if dib_header.bitcount == 16 {
-        index = BMP::two_bytes_to_int(BMP::vec_to_2u8_array(pixel));
-      } else {
-        index = BMP::byte_to_int(pixel[0]) as u16;
-      }
-      let mut rgba: [u8; 4];
-      match color_table {
-        ColorTable::RGBTRIPLE(vec) => {
-          let rgb: [u8; 3] = vec[index as usize];
-          //the array is fixed size [u8; 3] we want to turn it into [u8; 4] with the 4th being 255
-          let mut rgb = rgb.to_vec();
-          rgb.push(255);
-          rgba = BMP::vec_to_4u8_array(rgb);
-        },
-        ColorTable::RGBQUAD(vec) => {
-          rgba = vec[index as usize];
-        }
-      }
-      return Ok(rgba);
+      },
     }
+    //where the actual pixel data starts, so the color table must end sometime before
+    let end = (self.get_offset() as usize).min(self.contents.len());
+    count = count.min(end.saturating_sub(start)/entry_size);
+    return Ok((start, entry_size, count));
   }
-  //edit color pixels, only supports 24 and 32 bit
-  pub fn change_color_of_pixel(&mut self, x: u16, mut y: u16, new_color: [u8; 4]) -> Result<(), ErrorKind> {
-    //NEW_COLOR IS FLIPPED! See get color from pixel and get the correct order like in get_color_of_px
-    //todo: top down or bottom down?
+  pub fn get_palette(&self) -> Result<Palette, Error> {
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
-    let header = self.get_header();
-    //bits per pixel
-    let bitcount = dib_header.bitcount;
-    //only 24 and 32 bit
-    if bitcount != 24 && bitcount != 32 {
-      //return error
-      return Err(ErrorKind::Unsupported);
-    }
-    //depending on if top down or bottom up, adjust  y
-    if dib_header.height > 0 {
-      //bottom up
-      y = dib_header.height as u16 - y;
-    }
-    //calculate row width (bytes)
-    let row_length = (f64::from(((bitcount/8) as u16*dib_header.width as u16/4)).ceil() as u32 * 4) as u16;
-    //amount of rows in front = y
-    //add offset bits: header.bfOffBits (actually bytes)
-    let start = y*row_length+header.bfOffBits+(bitcount/8)*x;
-    //get indexes to change
-    //self.contents
-    //change the contents
-    if bitcount == 24 {
-      //order is BGR not RGB
-      //3 bytes
-      self.contents[start as usize] = new_color[2];
-      self.contents[(start+1) as usize] = new_color[1];
-      self.contents[(start+2) as usize] = new_color[0];
-    } else if bitcount == 32 {
-      let red_mask: u32 = dib_header.RedMask.unwrap();
-      let green_mask: u32 = dib_header.RedMask.unwrap();
-      let blue_mask: u32 = dib_header.RedMask.unwrap();
-      let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-      //4 bytes
-      if alpha_mask < red_mask {
-        //alpha in front
-        if red_mask < blue_mask {
-          //argb
-          self.contents[start as usize] = new_color[3];
-          self.contents[(start+1) as usize] = new_color[0];
-          self.contents[(start+2) as usize] = new_color[1];
-          self.contents[(start+3) as usize] = new_color[2];
-        } else {
-          //abgr
-          self.contents[start as usize] = new_color[3];
-          self.contents[(start+1) as usize] = new_color[2];
-          self.contents[(start+2) as usize] = new_color[1];
-          self.contents[(start+3) as usize] = new_color[0];
-        }
-      } else {
-        //alpha in back
-        if red_mask < blue_mask {
-          //rgba
-          self.contents[start as usize] = new_color[0];
-          self.contents[(start+1) as usize] = new_color[1];
-          self.contents[(start+2) as usize] = new_color[2];
-          self.contents[(start+3) as usize] = new_color[3];
-        } else {
-          //bgra
-          self.contents[start as usize] = new_color[2];
-          self.contents[(start+1) as usize] = new_color[1];
-          self.contents[(start+2) as usize] = new_color[0];
-          self.contents[(start+3) as usize] = new_color[3];
-        }
+    let position = self.get_color_table_position();
+    let (start, entry_size, count) = match position {
+      Ok(returned_position) => returned_position,
+      Err(e) => return Err(e),
+    };
+    if count == 0 {
+      let compression = dib_header.compression.unwrap_or(Compression::BI_RGB);
+      if compression == Compression::BI_BITFIELDS || compression == Compression::BI_ALPHABITFIELDS {
+        //extra bit masks, not color table
+        return Err(Error::UseExtraBitMasks);
       }
+      return Err(Error::DoesNotExist("color table"));
     }
-    return Ok(());
-  }
-  //image editing functions
-  pub fn draw_image(&mut self, bmp2: BMP) {
-    //
-  }
-  pub fn filter(&mut self) {
-    //add/subtract to r,g,b for each pixel?
-    //masking
+    let mut colors: Vec<[u8; 3]> = Vec::with_capacity(count);
+    for i in 0..count {
+      let entry = start+i*entry_size;
+      //stored as blue, green, red
+      colors.push([self.contents[entry+2], self.contents[entry+1], self.contents[entry]]);
+    }
+    return Ok(Palette { colors, important: dib_header.ClrImportant.unwrap_or(0) });
   }
-  //shape, line making functions
-  pub fn draw_line(&mut self, fill: [u8; 4], p1: [u16; 2], p2: [u16; 2]) {
-    if p1[0] == p2[0] {
-      //x matches x, straight vertical line
-      for ay in 0..(p2[0] as i16 - p1[0] as i16).abs() as u16 {
-        //if p1 is to the left of p2
-        if p1[0] < p2[0] {
-          self.change_color_of_pixel(p1[0], p1[1]+ay, fill);
-        } else {
-          self.change_color_of_pixel(p2[0], p2[1]+ay, fill);
-        }
-      }
-    } else if p1[1] == p2[1] {
-      //y matches y, straight horizontal line
-      for ax in 0..(p2[1] as i16 - p1[1] as i16).abs() as u16 {
-        //if p1 is above p2
-        if p1[1] < p2[1] {
-          self.change_color_of_pixel(p1[0]+ax, p1[1], fill);
-        } else {
-          self.change_color_of_pixel(p2[0]+ax, p2[1], fill);
-        }
+  //replaces the color table. if the amount of colors changes, the pixel array (and anything after the table) is moved
+  //pixels keep their indexes
+  pub fn set_palette(&mut self, palette: &Palette) -> Result<(), Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    if dib_header.bitcount <= 8 && palette.len() > 1 << dib_header.bitcount {
+      return Err(Error::Unsupported("more colors than the bit count allows"));
+    }
+    let position = self.get_color_table_position();
+    let (start, entry_size, count) = match position {
+      Ok(returned_position) => returned_position,
+      Err(e) => return Err(e),
+    };
+    let mut table: Vec<u8> = Vec::with_capacity(palette.len()*entry_size);
+    for color in &palette.colors {
+      table.extend_from_slice(&[color[2], color[1], color[0]]);
+      if entry_size == 4 {
+        table.push(0);
       }
-    } else {
-      let vertical_diff: u16 = (p2[1] as i16 -p1[1] as i16).abs() as u16;
-      let horizontal_diff: u16 = (p2[0] as i16 - p1[0] as i16).abs() as u16;
-      //get left most point
-      let leftmost_p;
-      let rightmost_p;
-      if p1[0] < p2[0] {
-        leftmost_p = p1;
-        rightmost_p = p2;
-      } else {
-        leftmost_p = p2;
-        rightmost_p = p1;
-      }
-      let highest_p;
-      let lowest_p;
-      if p1[1] < p2[1] {
-        highest_p = p1;
-        lowest_p = p2;
-      } else {
-        highest_p = p2;
-        lowest_p = p1;
-      }
-      //if vertical equal or more than 2
-      if vertical_diff >= 2 {
-        // middle segments = floor horizontal/vertical
-        let middle_segment_length: u16 = f64::from(horizontal_diff/vertical_diff).floor() as u16;
-        // two ends = horizontal - (middle segments * (vertical-2))
-        let two_ends_combined_length = horizontal_diff - (middle_segment_length*(vertical_diff-2));
-        // each end should be two ends / 2
-        // if two ends = 1, make first end 1 and subtract 1 from last segment and give to last end
-        if two_ends_combined_length == 1 {
-          let end_segment_length = two_ends_combined_length/2;
-          //first segment
-          //leftmost_p
-          self.change_color_of_pixel(leftmost_p[0], leftmost_p[1], fill);
-          //middle segments
-          for j in 0..(vertical_diff-2) {
-            for ji in 0..middle_segment_length {
-              //if last pixel of last segment
-              if j == vertical_diff-3 && ji == middle_segment_length-1 {
-                continue;
-              }
-              if highest_p == leftmost_p {
-                self.change_color_of_pixel(leftmost_p[0]+ji+j*middle_segment_length, rightmost_p[1]+j, fill);
-              } else {
-                self.change_color_of_pixel(leftmost_p[0]+ji+j*middle_segment_length, rightmost_p[1]-j, fill);
-              }
-            }
-          }
-          //last segment
-          self.change_color_of_pixel(rightmost_p[0], rightmost_p[1], fill);
-        } else {
-          let end_segment_length = two_ends_combined_length/2;
-          //first segment
-          //leftmost_p
-          for i in 0..end_segment_length {
-            self.change_color_of_pixel(leftmost_p[0]+i, leftmost_p[1], fill);
-          }
-          //middle segments
-          for j in 0..(vertical_diff-2) {
-            for ji in 0..middle_segment_length {
-              if highest_p == leftmost_p {
-                self.change_color_of_pixel(leftmost_p[0]+ji+j*middle_segment_length, rightmost_p[1]+j, fill);
-              } else {
-                self.change_color_of_pixel(leftmost_p[0]+ji+j*middle_segment_length, rightmost_p[1]-j, fill);
-              }
-            }
-          }
-          //last segment
-          for k in 0..end_segment_length {
-            self.change_color_of_pixel(rightmost_p[0]-k, rightmost_p[1], fill);
-          }
-        }
-      } else {
-        //if vertical diff is 1, divide in half. if decimal, floor and ceil, those are the two segments. else, two segments are equal length
-        let first_segment: u16 = (f64::from(horizontal_diff/2)).floor() as u16;
-        let second_segment: u16 = (f64::from(horizontal_diff/2)).ceil() as u16;
-        for i in 0..first_segment {
-          self.change_color_of_pixel(leftmost_p[0]+i, leftmost_p[1], fill);
-        }
-        for j in 0..second_segment {
-          self.change_color_of_pixel(rightmost_p[0]-j, rightmost_p[1], fill);
-        }
+    }
+    let old_end = start+count*entry_size;
+    let old_length = count*entry_size;
+    let new_length = table.len();
+    let offset = self.get_offset() as usize;
+    //bfOffBits and bfSize move by the difference, and need to stay within 32 bits
+    let new_offset = (offset+new_length).saturating_sub(old_length);
+    let new_file_size = (self.contents.len()+new_length).saturating_sub(old_length);
+    if new_offset > u32::MAX as usize || new_file_size > u32::MAX as usize {
+      return Err(Error::Overflow { field: "bfSize" });
+    }
+    self.contents.splice(start..old_end, table);
+    BMP::write_int(&mut self.contents, 2, new_file_size as u32);
+    BMP::write_int(&mut self.contents, 10, new_offset as u32);
+    if dib_header.size >= 40 {
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+32, palette.len() as u32);
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+36, palette.important);
+    }
+    //the embedded profile is found relative to the dib header, so if it comes after the color table it moved too
+    if let Some(profile_data) = dib_header.ProfileData {
+      if profile_data != 0 && HEADER_OFFSET+profile_data as usize >= old_end {
+        let new_profile_data = (profile_data as usize+new_length).saturating_sub(old_length);
+        BMP::write_int(&mut self.contents, HEADER_OFFSET+112, new_profile_data as u32);
       }
     }
+    return Ok(());
   }
-  pub fn draw_rectangle(&mut self, fill: Option<[u8; 4]>, stroke: Option<[u8; 4]>, p1: [u16; 2], p2: [u16; 2]) {
-    //
+  pub fn set_palette_entry(&mut self, index: usize, color: [u8; 3]) -> Result<(), Error> {
+    let position = self.get_color_table_position();
+    let (start, entry_size, count) = match position {
+      Ok(returned_position) => returned_position,
+      Err(e) => return Err(e),
+    };
+    if index >= count {
+      return Err(Error::DoesNotExist("palette entry"));
+    }
+    let entry = start+index*entry_size;
+    self.contents[entry] = color[2];
+    self.contents[entry+1] = color[1];
+    self.contents[entry+2] = color[0];
+    return Ok(());
   }
-  pub fn draw_ellipse(&mut self, fill: Option<[u8; 4]>, stroke: Option<[u8; 4]>, center: [u16; 2], xlength: u16, ylength: u16) {
-    //
+  //how many pixels use each color table index
+  fn get_palette_usage(&self) -> Result<Vec<usize>, Error> {
+    let decoded = self.get_decoded_pixels();
+    let (pixels, layout) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    if layout.bitcount > 8 {
+      return Err(Error::Unsupported("only indexed images have a color table"));
+    }
+    let mut usage: Vec<usize> = vec![0; 1 << layout.bitcount];
+    for y in 0..layout.height {
+      let row_start = layout.row_start(y);
+      for x in 0..layout.width {
+        usage[layout.read_raw(|i| pixels[i], row_start, x) as usize] += 1;
+      }
+    }
+    return Ok(usage);
   }
-  //BUGGY
-  pub fn fill_bucket(&mut self, fill: [u8; 4], x: usize, y: usize) -> Result<Vec<[u16; 2]>, ErrorKind> {
-    //fill same color connected to the (x,y) with new paint
-    //check up, down, left, right. If same color as initial square, add to queue. Iterate through queue, after iterating add to visit and repeat
+  //changes every pixel's index from i to map[i]
+  fn remap_palette_indexes(&mut self, map: &[u8]) -> Result<(), Error> {
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
-    let replace_color = self.get_color_of_px(x as usize, y as usize);
-    let replace_color: [u8; 4] = match replace_color {
-      Ok(returned_replace_color) => returned_replace_color,
+    let compression = dib_header.compression.unwrap_or(Compression::BI_RGB);
+    if compression == Compression::BI_RLE8 || compression == Compression::BI_RLE4 {
+      let offset = (self.get_offset() as usize).min(self.contents.len());
+      BMP::remap_rle(&mut self.contents[offset..], dib_header.bitcount, map);
+      return Ok(());
+    }
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
       Err(e) => return Err(e),
     };
-    let mut visited: Vec<[u16; 2]> = Vec::new();
-    let mut queue: Vec<[u16; 2]> = Vec::new();
-    queue.push([x as u16, y as u16]);
-    while queue.len() > 0 {
-      if visited.contains(&queue[0]) {
-        queue.remove(0);
-        continue;
+    if layout.bitcount > 8 {
+      return Err(Error::Unsupported("only indexed images have a color table"));
+    }
+    let contents = Cell::from_mut(&mut self.contents[..]).as_slice_of_cells();
+    for y in 0..layout.height {
+      let row_start = layout.row_start(y);
+      for x in 0..layout.width {
+        let index = layout.read_raw(|i| contents[i].get(), row_start, x);
+        let new_index = *map.get(index as usize).unwrap_or(&(index as u8));
+        layout.write_raw(|i| contents[i].get(), |i, byte| contents[i].set(byte), row_start, x, new_index as u32);
       }
-      let x2: u16 = queue[0][0];
-      let y2: u16 = queue[0][1];
-      //turn current coords into fill color
-      //self.change_color_of_pixel(x2, y2, fill);
-      //check is surrounding (up, down, left, right) are same color
-      //check to make sure these coords exist. (get height, width)
-      //remember, indexes start at 0
-      if y2+1 < dib_header.height as u16 {
-        let down_color = self.get_color_of_px(x2 as usize, (y2+1) as usize);
-        let down_color: [u8; 4] = match down_color {
-          Ok(returned_down_color) => returned_down_color,
-          Err(e) => return Err(e),
-        };
-        if down_color == replace_color {
-          queue.push([x2 as u16, y2+1 as u16]);
-        }
+    }
+    return Ok(());
+  }
+  //same walk through the data as decode_rle, but changes the indexes in place
+  fn remap_rle(data: &mut [u8], bitcount: u16, map: &[u8]) {
+    let remap = |index: u8| *map.get(index as usize).unwrap_or(&index);
+    let remap_byte = |byte: u8| if bitcount == 4 { (remap(byte >> 4) << 4) | (remap(byte & 0b00001111) & 0b00001111) } else { remap(byte) };
+    let mut i: usize = 0;
+    while i+1 < data.len() {
+      let count = data[i] as usize;
+      let value = data[i+1];
+      i += 2;
+      if count > 0 {
+        data[i-1] = remap_byte(value);
+        continue;
       }
-      if y2-1 > 0 {
-        //does not go all the way to up color
-        let up_color = self.get_color_of_px(x2 as usize, (y2-1) as usize);
-        let up_color: [u8; 4] = match up_color {
-          Ok(returned_up_color) => returned_up_color,
-          Err(e) => return Err(e),
-        };
-        if up_color == replace_color {
-          queue.push([x2 as u16, y2-1 as u16]);
-        }
+      match value {
+        0 => {},
+        1 => break,
+        2 => i += 2,
+        _ => {
+          let pixels = value as usize;
+          let bytes = if bitcount == 4 { pixels.div_ceil(2) } else { pixels };
+          for byte in data.iter_mut().skip(i).take(bytes) {
+            *byte = remap_byte(*byte);
+          }
+          i += bytes+bytes % 2;
+        },
       }
-      if x2-1 > 0 {
-        let left_color = self.get_color_of_px((x2-1) as usize, y2 as usize);
-        let left_color: [u8; 4] = match left_color {
-          Ok(returned_left_color) => returned_left_color,
-          Err(e) => return Err(e),
-        };
-        if left_color == replace_color {
-          queue.push([x2-1 as u16, y2 as u16]);
-        }
+    }
+  }
+  //removes colors no pixel uses, and merges duplicate colors
+  pub fn compact_palette(&mut self) -> Result<(), Error> {
+    let palette = self.get_palette();
+    let palette = match palette {
+      Ok(returned_palette) => returned_palette,
+      Err(e) => return Err(e),
+    };
+    let usage = self.get_palette_usage();
+    let usage = match usage {
+      Ok(returned_usage) => returned_usage,
+      Err(e) => return Err(e),
+    };
+    let mut new_palette = Palette::new(Vec::new());
+    let mut map: Vec<u8> = vec![0; usage.len()];
+    for (index, color) in palette.colors.iter().enumerate() {
+      if usage[index] == 0 {
+        continue;
       }
-      if x2+1 < dib_header.width as u16 {
-        let right_color = self.get_color_of_px((x2+1) as usize, y2 as usize);
-        let right_color: [u8; 4] = match right_color {
-          Ok(returned_right_color) => returned_right_color,
-          Err(e) => return Err(e),
-        };
-        if right_color == replace_color {
-          queue.push([x2+1 as u16, y2 as u16]);
-        }
+      match new_palette.colors.iter().position(|c| c == color) {
+        Some(existing) => map[index] = existing as u8,
+        None => {
+          map[index] = new_palette.len() as u8;
+          new_palette.push(*color);
+        },
       }
-      //end
-      visited.push(queue[0]);
-      queue.remove(0);
     }
-    //loop through visited
-    for px in &visited {
-      //move the y up by one and things magically work. dunno why, but it works.
-      self.change_color_of_pixel(px[0], px[1]+1, fill);
+    //a color table can't be empty
+    if new_palette.is_empty() {
+      new_palette.push(palette.get(0).unwrap_or([0, 0, 0]));
     }
-    //&self.save_to_new("src/images/e2.bmp");
-    return Ok(visited);
+    match self.remap_palette_indexes(&map) {
+      Ok(()) => {},
+      Err(e) => return Err(e),
+    }
+    return self.set_palette(&new_palette);
   }
-  //save image functions
-  pub fn save_to_new(self, file_path: &str) {
-    let mut new_file = fs::File::create(&std::path::Path::new(file_path)).unwrap();
-    new_file.write_all(&self.contents);
+  //reorders the color table, pixels keep their colors
+  pub fn sort_palette_by<F: FnMut(&[u8; 3], &[u8; 3]) -> Ordering>(&mut self, mut compare: F) -> Result<(), Error> {
+    let palette = self.get_palette();
+    let palette = match palette {
+      Ok(returned_palette) => returned_palette,
+      Err(e) => return Err(e),
+    };
+    let mut order: Vec<usize> = (0..palette.len()).collect();
+    order.sort_by(|a, b| compare(&palette.colors[*a], &palette.colors[*b]));
+    return self.reorder_palette(&palette, &order, 0);
   }
-}
-
-//https://docs.microsoft.com/en-us/windows/win32/wcs/basic-color-management-concepts
-
-/*RGB to written color hash table*/
\ No newline at end of file
+  //most used colors first, and ClrImportant set to the amount of colors that are used
+  pub fn sort_palette_by_usage(&mut self) -> Result<(), Error> {
+    let palette = self.get_palette();
+    let palette = match palette {
+      Ok(returned_palette) => returned_palette,
+      Err(e) => return Err(e),
+    };
+    let usage = self.get_palette_usage();
+    let usage = match usage {
+      Ok(returned_usage) => returned_usage,
+      Err(e) => return Err(e),
+    };
+    let mut order: Vec<usize> = (0..palette.len()).collect();
+    order.sort_by(|a, b| usage[*b].cmp(&usage[*a]));
+    let used = order.iter().filter(|index| usage[**index] > 0).count();
+    return self.reorder_palette(&palette, &order, used as u32);
+  }
+  //order[new index] = old index
+  fn reorder_palette(&mut self, palette: &Palette, order: &[usize], important: u32) -> Result<(), Error> {
+    let mut map: Vec<u8> = vec![0; order.len()];
+    let mut new_palette = Palette::new(Vec::with_capacity(order.len()));
+    new_palette.important = important;
+    for (new_index, old_index) in order.iter().enumerate() {
+      map[*old_index] = new_index as u8;
+      new_palette.push(palette.colors[*old_index]);
+    }
+    match self.remap_palette_indexes(&map) {
+      Ok(()) => {},
+      Err(e) => return Err(e),
+    }
+    return self.set_palette(&new_palette);
+  }
+  //color table as rgba
+  fn get_rgba_palette(&self) -> Result<Vec<[u8; 4]>, Error> {
+    let palette = self.get_palette();
+    let palette = match palette {
+      Ok(returned_palette) => returned_palette,
+      Err(e) => return Err(e),
+    };
+    return Ok(palette.colors.iter().map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect());
+  }
+  //only for uncompressed pixel arrays, see get_decoded_pixels
+  fn get_pixel_layout(&self) -> Result<PixelLayout, Error> {
+    let layout = self.get_pixel_layout_without_palette();
+    let mut layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    if layout.bitcount <= 8 {
+      layout.palette = match self.get_rgba_palette() {
+        Ok(returned_palette) => returned_palette,
+        Err(e) => return Err(e),
+      };
+    }
+    return Ok(layout);
+  }
+  //the same, but with an empty palette, for reading single pixels without going through the whole color table
+  fn get_pixel_layout_without_palette(&self) -> Result<PixelLayout, Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    let compression = dib_header.compression.unwrap_or(Compression::BI_RGB);
+    //the pixel array is a whole JPEG or PNG file, see embedded_payload
+    if compression == Compression::BI_JPEG || compression == Compression::BI_PNG {
+      return Err(Error::UnsupportedCompression(compression));
+    }
+    let masks: [u32; 4];
+    match dib_header.bitcount {
+      1 | 2 | 4 | 8 => {
+        if compression != Compression::BI_RGB {
+          return Err(Error::UnsupportedCompression(compression));
+        }
+        masks = [0; 4];
+      },
+      16 | 24 | 32 => {
+        if compression == Compression::BI_BITFIELDS || compression == Compression::BI_ALPHABITFIELDS {
+          if dib_header.size == 40 {
+            match self.get_extra_bit_masks() {
+              Ok(EXTRA_BIT_MASKS::BI_BITFIELDS_MASKS(m)) => masks = [m.red, m.green, m.blue, 0],
+              Ok(EXTRA_BIT_MASKS::BI_ALPHABITFIELDS_MASKS(m)) => masks = [m.red, m.green, m.blue, m.alpha],
+              Err(e) => return Err(e),
+            }
+          } else {
+            masks = [dib_header.RedMask.unwrap_or(0), dib_header.GreenMask.unwrap_or(0), dib_header.BlueMask.unwrap_or(0), dib_header.AlphaMask.unwrap_or(0)];
+          }
+        } else if compression == Compression::BI_RGB {
+          if dib_header.bitcount == 16 {
+            //5 bits for each of r,g,b, top bit unused
+            masks = [0x7c00, 0x03e0, 0x001f, 0];
+          } else {
+            //blue, green, red, and for 32 bit an unused byte
+            masks = [0x00ff0000, 0x0000ff00, 0x000000ff, 0];
+          }
+        } else {
+          return Err(Error::UnsupportedCompression(compression));
+        }
+      },
+      _ => return Err(Error::InvalidHeaderField { field: "biBitCount", value: dib_header.bitcount as i64 }),
+    }
+    let width = dib_header.width as usize;
+    let height = dib_header.height.unsigned_abs() as usize;
+    let row_length = match BMP::get_row_length(dib_header.bitcount, dib_header.width) {
+      Ok(returned_row_length) => returned_row_length,
+      Err(e) => return Err(e),
+    };
+    let offset = self.get_offset() as usize;
+    //make sure every row is actually there, so the pixels can be read without checking each time
+    match BMP::checked_offset(offset, row_length, height) {
+      Ok(end) => {
+        if end > self.contents.len() {
+          return Err(Error::Truncated { offset, length: end-offset, file_size: self.contents.len() });
+        }
+      },
+      Err(e) => return Err(e),
+    }
+    return Ok(PixelLayout {
+      offset,
+      width,
+      height,
+      top_down: dib_header.height < 0,
+      bitcount: dib_header.bitcount,
+      row_length,
+      masks,
+      palette: Vec::new(),
+    });
+  }
+  //pixel array that can be read with the returned layout. uncompressed images are borrowed as is, RLE and huffman 1D images are decoded into 8 bit indexes (24 bit for RLE24)
+  //decoded pixels are kept until the contents change
+  fn get_decoded_pixels(&self) -> Result<(PixelData<'_>, PixelLayout), Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    let compression = dib_header.compression.unwrap_or(Compression::BI_RGB);
+    let compressed = compression == Compression::BI_PNG || matches!((compression, dib_header.bitcount), (Compression::BI_RLE8, 8) | (Compression::BI_RLE4, 4) | (Compression::BCA_RLE24, 24) | (Compression::BCA_HUFFMAN1D, 1));
+    if !compressed {
+      return match self.get_pixel_layout() {
+        Ok(layout) => Ok((PixelData::Stored(&self.contents[..]), layout)),
+        Err(e) => Err(e),
+      };
+    }
+    //a panic while decoding leaves nothing half written, so a poisoned cache is still fine to use
+    let mut cache = match self.decoded.lock() {
+      Ok(cache) => cache,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(cached) = cache.as_ref() {
+      if cached.source == self.contents {
+        return Ok((PixelData::Decoded(Arc::clone(&cached.pixels)), cached.layout.clone()));
+      }
+    }
+    let (pixels, layout) = match self.decode_pixels(&dib_header, compression) {
+      Ok(decoded) => decoded,
+      Err(e) => return Err(e),
+    };
+    let pixels = Arc::new(pixels);
+    *cache = Some(DecodedCache { source: self.contents.clone(), pixels: Arc::clone(&pixels), layout: layout.clone() });
+    return Ok((PixelData::Decoded(pixels), layout));
+  }
+  //RLE, huffman 1D and PNG pixels, see get_decoded_pixels
+  fn decode_pixels(&self, dib_header: &DIBHEADER, compression: Compression) -> Result<(Vec<u8>, PixelLayout), Error> {
+    if compression == Compression::BI_PNG {
+      //decoded to blue, green, red, alpha. PNG rows always go from the top
+      let payload = self.embedded_payload();
+      let image = match payload {
+        Ok((_, returned_payload)) => png::decode(returned_payload),
+        Err(e) => return Err(e),
+      };
+      let image = match image {
+        Ok(returned_image) => returned_image,
+        Err(e) => return Err(e),
+      };
+      let mut pixels = image.to_rgba8();
+      for pixel in pixels.chunks_mut(4) {
+        pixel.swap(0, 2);
+      }
+      return Ok((pixels, PixelLayout {
+        offset: 0,
+        width: image.width as usize,
+        height: image.height as usize,
+        top_down: true,
+        bitcount: 32,
+        row_length: image.width as usize*4,
+        masks: [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000],
+        palette: Vec::new(),
+      }));
+    }
+    let palette = if dib_header.bitcount <= 8 {
+      match self.get_rgba_palette() {
+        Ok(returned_palette) => returned_palette,
+        Err(e) => return Err(e),
+      }
+    } else {
+      Vec::new()
+    };
+    let width = dib_header.width as usize;
+    let height = dib_header.height.unsigned_abs() as usize;
+    let pixel_count = match width.checked_mul(height) {
+      Some(pixel_count) => pixel_count,
+      None => return Err(Error::Overflow { field: "pixel count" }),
+    };
+    let offset = self.get_offset() as usize;
+    //biSizeImage is the size of the compressed data
+    let mut end = self.contents.len();
+    if let Some(sizeimage) = dib_header.sizeimage {
+      if sizeimage != 0 {
+        end = end.min(offset.saturating_add(sizeimage as usize));
+      }
+    }
+    if offset > end {
+      return Err(Error::Truncated { offset, length: 0, file_size: self.contents.len() });
+    }
+    let data = &self.contents[offset..end];
+    //an rle run is at most 255 pixels for every 2 bytes, and a huffman code is at least 2 bits for at most 2560 pixels,
+    //so data this short can't fill the dimensions
+    let needed = if compression == Compression::BCA_HUFFMAN1D {
+      pixel_count.div_ceil(2560*4)
+    } else {
+      pixel_count.div_ceil(255)*2
+    };
+    if data.len() < needed {
+      return Err(Error::Truncated { offset, length: needed, file_size: self.contents.len() });
+    }
+    if compression == Compression::BCA_RLE24 {
+      let pixels = BMP::decode_rle(data, width, height, 24)?;
+      return Ok((pixels, PixelLayout {
+        offset: 0,
+        width,
+        height,
+        top_down: dib_header.height < 0,
+        bitcount: 24,
+        row_length: width*3,
+        masks: [0x00ff0000, 0x0000ff00, 0x000000ff, 0],
+        palette,
+      }));
+    }
+    let indexes = if compression == Compression::BCA_HUFFMAN1D {
+      //white runs are index 0, black runs index 1
+      huffman::decode(data, width, height)?
+    } else {
+      BMP::decode_rle(data, width, height, dib_header.bitcount)?
+    };
+    return Ok((indexes, PixelLayout {
+      offset: 0,
+      width,
+      height,
+      top_down: dib_header.height < 0,
+      bitcount: 8,
+      row_length: width,
+      masks: [0; 4],
+      palette,
+    }));
+  }
+  //https://docs.microsoft.com/en-us/windows/win32/gdi/bitmap-compression
+  //returns one byte per pixel (the color table index) for rle4 and rle8, and 3 bytes (blue, green, red) for OS/2 rle24
+  //rows are unpadded and in the same order as stored. pixels skipped by deltas are left as 0
+  fn decode_rle(data: &[u8], width: usize, height: usize, bitcount: u16) -> Result<Vec<u8>, Error> {
+    let pixel_size: usize = if bitcount == 24 { 3 } else { 1 };
+    let size = match width.checked_mul(height).and_then(|count| count.checked_mul(pixel_size)) {
+      Some(size) => size,
+      None => return Err(Error::Overflow { field: "pixel count" }),
+    };
+    let mut pixels: Vec<u8> = vec![0; size];
+    let mut x: usize = 0;
+    let mut y: usize = 0;
+    let mut i: usize = 0;
+    while i+1 < data.len() && y < height {
+      let count = data[i] as usize;
+      let value = data[i+1];
+      if count > 0 {
+        //encoded mode, repeat value count times. for rle4 the two halves of the byte alternate, for rle24 value is the first of 3 bytes
+        if i+1+pixel_size > data.len() {
+          break;
+        }
+        for j in 0..count {
+          if x < width {
+            let start = (y*width+x)*pixel_size;
+            match bitcount {
+              4 => pixels[start] = if j % 2 == 0 { value >> 4 } else { value & 0b00001111 },
+              24 => pixels[start..start+3].copy_from_slice(&data[i+1..i+4]),
+              _ => pixels[start] = value,
+            }
+          }
+          x += 1;
+        }
+        i += 1+pixel_size;
+        continue;
+      }
+      i += 2;
+      match value {
+        0 => {
+          //end of line
+          x = 0;
+          y += 1;
+        },
+        1 => {
+          //end of bitmap
+          break;
+        },
+        2 => {
+          //delta, move right and up (in stored order, down)
+          if i+1 >= data.len() {
+            break;
+          }
+          x += data[i] as usize;
+          y += data[i+1] as usize;
+          i += 2;
+        },
+        _ => {
+          //absolute mode, the next value pixels are stored as is, padded to a 2 byte boundary
+          let count = value as usize;
+          let bytes = if bitcount == 4 { count.div_ceil(2) } else { count*pixel_size };
+          for j in 0..count {
+            let byte_index = i+if bitcount == 4 { j/2 } else { j*pixel_size };
+            if byte_index+pixel_size > data.len() {
+              break;
+            }
+            if x < width {
+              let start = (y*width+x)*pixel_size;
+              let byte = data[byte_index];
+              match bitcount {
+                4 => pixels[start] = if j % 2 == 0 { byte >> 4 } else { byte & 0b00001111 },
+                24 => pixels[start..start+3].copy_from_slice(&data[byte_index..byte_index+3]),
+                _ => pixels[start] = byte,
+              }
+            }
+            x += 1;
+          }
+          i += bytes+bytes % 2;
+        },
+      }
+    }
+    return Ok(pixels);
+  }
+  //decodes every pixel into rgba, 4 bytes per pixel, starting at the top left
+  pub fn to_rgba8(&self) -> Result<(u32, u32, Vec<u8>), Error> {
+    let decoded = self.get_decoded_pixels();
+    let (pixels, layout) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    let mut rgba: Vec<u8> = Vec::with_capacity(layout.width*layout.height*4);
+    for y in 0..layout.height {
+      let row_start = layout.row_start(y);
+      for x in 0..layout.width {
+        rgba.extend_from_slice(&layout.raw_to_rgba(layout.read_raw(|i| pixels[i], row_start, x)));
+      }
+    }
+    return Ok((layout.width as u32, layout.height as u32, rgba));
+  }
+  //encodes rgba pixels (4 bytes per pixel, starting at the top left) into a new bottom up bmp
+  //indexed formats need the image to have few enough colors to fit in the color table
+  pub fn from_rgba8(width: u32, height: u32, rgba: &[u8], target_format: PixelFormat) -> Result<BMP, Error> {
+    let pixel_count = match (width as usize).checked_mul(height as usize) {
+      Some(pixel_count) => pixel_count,
+      None => return Err(Error::Overflow { field: "pixel count" }),
+    };
+    if pixel_count.checked_mul(4) != Some(rgba.len()) {
+      return Err(Error::WrongDimensions { expected: pixel_count.saturating_mul(4), actual: rgba.len() });
+    }
+    if let Some(max_colors) = target_format.max_colors() {
+      let mut palette: Vec<[u8; 3]> = Vec::new();
+      let mut palette_indexes: HashMap<[u8; 3], u8> = HashMap::new();
+      let mut indexes: Vec<u8> = Vec::with_capacity(pixel_count);
+      for pixel in rgba.chunks_exact(4) {
+        let rgb: [u8; 3] = [pixel[0], pixel[1], pixel[2]];
+        if let Entry::Vacant(entry) = palette_indexes.entry(rgb) {
+          if palette.len() == max_colors {
+            //too many colors, use quantize instead
+            return Err(Error::Unsupported("too many colors for the color table, quantize first"));
+          }
+          entry.insert(palette.len() as u8);
+          palette.push(rgb);
+        }
+        indexes.push(palette_indexes[&rgb]);
+      }
+      return BMP::from_palette_indexes(width, height, &indexes, &palette, target_format);
+    }
+    let bmp = BMP::new_blank(width, height, &target_format, &[]);
+    let mut bmp = match bmp {
+      Ok(returned_bmp) => returned_bmp,
+      Err(e) => return Err(e),
+    };
+    let layout = bmp.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    let contents = Cell::from_mut(&mut bmp.contents[..]).as_slice_of_cells();
+    for y in 0..layout.height {
+      let row_start = layout.row_start(y);
+      for x in 0..layout.width {
+        let i = (y*layout.width+x)*4;
+        let raw = layout.rgba_to_raw(rgba[i..i+4].try_into().unwrap());
+        layout.write_raw(|i| contents[i].get(), |i, byte| contents[i].set(byte), row_start, x, raw);
+      }
+    }
+    return Ok(bmp);
+  }
+  //one color table index per pixel, starting at the top left
+  fn from_palette_indexes(width: u32, height: u32, indexes: &[u8], palette: &[[u8; 3]], target_format: PixelFormat) -> Result<BMP, Error> {
+    let max_colors = match target_format.max_colors() {
+      Some(max_colors) => max_colors,
+      None => return Err(Error::Unsupported("target format is not indexed")),
+    };
+    if palette.len() > max_colors {
+      return Err(Error::Unsupported("more colors than the bit count allows"));
+    }
+    if (width as usize).checked_mul(height as usize) != Some(indexes.len()) {
+      return Err(Error::WrongDimensions { expected: (width as usize).saturating_mul(height as usize), actual: indexes.len() });
+    }
+    let rgba_palette: Vec<[u8; 4]> = palette.iter().map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect();
+    let bmp = BMP::new_blank(width, height, &target_format, &rgba_palette);
+    let mut bmp = match bmp {
+      Ok(returned_bmp) => returned_bmp,
+      Err(e) => return Err(e),
+    };
+    let layout = bmp.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    let contents = Cell::from_mut(&mut bmp.contents[..]).as_slice_of_cells();
+    for y in 0..layout.height {
+      let row_start = layout.row_start(y);
+      for x in 0..layout.width {
+        layout.write_raw(|i| contents[i].get(), |i, byte| contents[i].set(byte), row_start, x, indexes[y*layout.width+x] as u32);
+      }
+    }
+    return Ok(bmp);
+  }
+  //reduces the image to a color table of at most 2, 4, 16 or 256 colors, and returns it as a new indexed bmp
+  pub fn quantize(&self, target_format: PixelFormat, quantizer: &Quantizer) -> Result<BMP, Error> {
+    return self.quantize_dithered(target_format, quantizer, &Dither::new(DitherMethod::None));
+  }
+  //same as quantize, but dithers the pixels when mapping them to the color table
+  pub fn quantize_dithered(&self, target_format: PixelFormat, quantizer: &Quantizer, dither: &Dither) -> Result<BMP, Error> {
+    let max_colors = match target_format.max_colors() {
+      Some(max_colors) => max_colors,
+      None => return Err(Error::Unsupported("target format is not indexed")),
+    };
+    let decoded = self.to_rgba8();
+    let (width, height, rgba) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    let palette = match quantize::build_palette(&rgba, max_colors, quantizer) {
+      Some(palette) => palette,
+      //fixed color table is too big for the format
+      None => return Err(Error::Unsupported("fixed palette has more colors than the format allows")),
+    };
+    let indexes = dither::dither(&rgba, width as usize, height as usize, &palette, dither);
+    return BMP::from_palette_indexes(width, height, &indexes, &palette, target_format);
+  }
+  //headers, color table and a zeroed pixel array for a bottom up image
+  fn new_blank(width: u32, height: u32, format: &PixelFormat, palette: &[[u8; 4]]) -> Result<BMP, Error> {
+    if width > i32::MAX as u32 || height > i32::MAX as u32 {
+      return Err(Error::Overflow { field: "biWidth or biHeight" });
+    }
+    let bitcount = format.bitcount();
+    let row_length = match BMP::get_row_length(bitcount, width) {
+      Ok(returned_row_length) => returned_row_length,
+      Err(e) => return Err(e),
+    };
+    let image_size = match row_length.checked_mul(height as usize) {
+      Some(image_size) => image_size,
+      None => return Err(Error::Overflow { field: "biSizeImage" }),
+    };
+    let masks = format.masks();
+    //alpha needs a BITMAPV4HEADER, 565 needs masks after a BITMAPINFOHEADER, everything else is a plain BITMAPINFOHEADER
+    let dib_size: u32 = if masks.is_some_and(|m| m[3] != 0) { 108 } else { 40 };
+    let compression: u32 = if masks.is_some() { 3 } else { 0 };
+    let extra_masks_size: u32 = if masks.is_some() && dib_size == 40 { 12 } else { 0 };
+    let offset = HEADER_OFFSET as u32+dib_size+extra_masks_size+palette.len() as u32*4;
+    let file_size = match (offset as usize).checked_add(image_size) {
+      Some(file_size) if file_size <= u32::MAX as usize => file_size,
+      _ => return Err(Error::Overflow { field: "bfSize" }),
+    };
+    let mut contents: Vec<u8> = Vec::with_capacity(file_size);
+    //file header
+    contents.extend_from_slice(b"BM");
+    contents.extend_from_slice(&(file_size as u32).to_le_bytes());
+    contents.extend_from_slice(&[0; 4]);
+    contents.extend_from_slice(&offset.to_le_bytes());
+    //dib header
+    contents.extend_from_slice(&dib_size.to_le_bytes());
+    contents.extend_from_slice(&(width as i32).to_le_bytes());
+    contents.extend_from_slice(&(height as i32).to_le_bytes());
+    contents.extend_from_slice(&1u16.to_le_bytes());
+    contents.extend_from_slice(&bitcount.to_le_bytes());
+    contents.extend_from_slice(&compression.to_le_bytes());
+    contents.extend_from_slice(&(image_size as u32).to_le_bytes());
+    //72 dpi
+    contents.extend_from_slice(&2835u32.to_le_bytes());
+    contents.extend_from_slice(&2835u32.to_le_bytes());
+    contents.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    contents.extend_from_slice(&0u32.to_le_bytes());
+    if let Some(masks) = masks {
+      if dib_size == 108 {
+        for mask in masks {
+          contents.extend_from_slice(&mask.to_le_bytes());
+        }
+        //LCS_sRGB, endpoints and gamma are ignored
+        contents.extend_from_slice(b"BGRs");
+        contents.extend_from_slice(&[0; 48]);
+      } else {
+        for mask in &masks[..3] {
+          contents.extend_from_slice(&mask.to_le_bytes());
+        }
+      }
+    }
+    //color table, blue green red reserved
+    for color in palette {
+      contents.extend_from_slice(&[color[2], color[1], color[0], 0]);
+    }
+    contents.resize(file_size, 0);
+    return Ok(BMP::from_bytes(contents));
+  }
+  //BI_JPEG and BI_PNG bitmaps store a whole JPEG or PNG file as the pixel array, biSizeImage bytes long
+  //DoesNotExist for other compressions
+  pub fn embedded_payload(&self) -> Result<(EmbeddedFormat, &[u8]), Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    let format = match dib_header.compression {
+      Some(Compression::BI_JPEG) => EmbeddedFormat::Jpeg,
+      Some(Compression::BI_PNG) => EmbeddedFormat::Png,
+      _ => return Err(Error::DoesNotExist("JPEG or PNG payload")),
+    };
+    let offset = self.get_offset() as usize;
+    //biSizeImage is required, but if it is missing assume the payload goes to the end of the file
+    let length = match dib_header.sizeimage {
+      Some(sizeimage) if sizeimage != 0 => sizeimage as usize,
+      _ => self.contents.len().saturating_sub(offset),
+    };
+    if offset.saturating_add(length) > self.contents.len() {
+      return Err(Error::Truncated { offset, length, file_size: self.contents.len() });
+    }
+    return Ok((format, &self.contents[offset..offset+length]));
+  }
+  //wraps a JPEG or PNG file in a BITMAPINFOHEADER bitmap with BI_JPEG or BI_PNG compression, for printers that take those
+  //the width and height are read from the JPEG or PNG. WrongFileType if it is neither
+  pub fn new_embedded(bytes: &[u8]) -> Result<BMP, Error> {
+    let format = match EmbeddedFormat::detect(bytes) {
+      Some(format) => format,
+      None => return Err(Error::WrongFileType),
+    };
+    let (width, height) = match format.get_dimensions(bytes) {
+      Some(dimensions) => dimensions,
+      None => return Err(Error::Truncated { offset: 0, length: bytes.len(), file_size: bytes.len() }),
+    };
+    if width > i32::MAX as u32 || height > i32::MAX as u32 {
+      return Err(Error::Overflow { field: "biWidth or biHeight" });
+    }
+    let offset = HEADER_OFFSET as u32+40;
+    let file_size = match (offset as usize).checked_add(bytes.len()) {
+      Some(file_size) if file_size <= u32::MAX as usize => file_size,
+      _ => return Err(Error::Overflow { field: "bfSize" }),
+    };
+    let mut contents: Vec<u8> = Vec::with_capacity(file_size);
+    //file header
+    contents.extend_from_slice(b"BM");
+    contents.extend_from_slice(&(file_size as u32).to_le_bytes());
+    contents.extend_from_slice(&[0; 4]);
+    contents.extend_from_slice(&offset.to_le_bytes());
+    //dib header. biBitCount is 0 since the JPEG or PNG says how many bits there are, and the height has to be positive
+    contents.extend_from_slice(&40u32.to_le_bytes());
+    contents.extend_from_slice(&(width as i32).to_le_bytes());
+    contents.extend_from_slice(&(height as i32).to_le_bytes());
+    contents.extend_from_slice(&1u16.to_le_bytes());
+    contents.extend_from_slice(&0u16.to_le_bytes());
+    contents.extend_from_slice(&format.compression().to_u32().to_le_bytes());
+    contents.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    //72 dpi
+    contents.extend_from_slice(&2835u32.to_le_bytes());
+    contents.extend_from_slice(&2835u32.to_le_bytes());
+    contents.extend_from_slice(&0u32.to_le_bytes());
+    contents.extend_from_slice(&0u32.to_le_bytes());
+    contents.extend_from_slice(bytes);
+    return Ok(BMP::from_bytes(contents));
+  }
+  //decodes a PNG (any color type, bit depth and interlacing) into a new bottom up bmp
+  //palette and grayscale PNGs become indexed, anything with transparency becomes 32 bit with an alpha mask, and rgb becomes 24 bit
+  pub fn from_png(bytes: &[u8]) -> Result<BMP, Error> {
+    let image = png::decode(bytes);
+    let image = match image {
+      Ok(returned_image) => returned_image,
+      Err(e) => return Err(e),
+    };
+    let bmp = match image.to_indexed() {
+      Some((indexes, palette)) => {
+        let format = match image.bit_depth {
+          1 => PixelFormat::Indexed1,
+          2 => PixelFormat::Indexed2,
+          4 => PixelFormat::Indexed4,
+          _ => PixelFormat::Indexed8,
+        };
+        BMP::from_palette_indexes(image.width, image.height, &indexes, &palette, format)
+      },
+      None => {
+        let format = match image.color_type {
+          png::ColorType::Rgb | png::ColorType::Grayscale if image.transparent_color.is_none() => PixelFormat::Rgb24,
+          _ => PixelFormat::Bgra32,
+        };
+        BMP::from_rgba8(image.width, image.height, &image.to_rgba8(), format)
+      },
+    };
+    let mut bmp = match bmp {
+      Ok(returned_bmp) => returned_bmp,
+      Err(e) => return Err(e),
+    };
+    if let Some((x_pels_per_meter, y_pels_per_meter)) = image.pixels_per_meter {
+      BMP::write_int(&mut bmp.contents, HEADER_OFFSET+24, x_pels_per_meter);
+      BMP::write_int(&mut bmp.contents, HEADER_OFFSET+28, y_pels_per_meter);
+    }
+    return Ok(bmp);
+  }
+  //decodes a PBM, PGM, PPM or PAM file (ascii or binary, any maxval) into a new bottom up bmp
+  //PBM becomes 1 bit with a black and white color table, PGM becomes 8 bit with a gray color table, PPM becomes 24 bit and anything with alpha becomes 32 bit
+  pub fn from_netpbm(bytes: &[u8]) -> Result<BMP, Error> {
+    let image = netpbm::decode(bytes);
+    let image = match image {
+      Ok(returned_image) => returned_image,
+      Err(e) => return Err(e),
+    };
+    if image.depth == 1 && image.maxval == 1 {
+      let indexes: Vec<u8> = image.samples.iter().map(|sample| *sample as u8).collect();
+      return BMP::from_palette_indexes(image.width, image.height, &indexes, &[[0, 0, 0], [255, 255, 255]], PixelFormat::Indexed1);
+    }
+    let samples = image.to_8_bit();
+    if image.depth == 1 {
+      let palette: Vec<[u8; 3]> = (0..=255).map(|gray| [gray; 3]).collect();
+      return BMP::from_palette_indexes(image.width, image.height, &samples, &palette, PixelFormat::Indexed8);
+    }
+    //unknown tuple types are guessed from the depth: gray and alpha, rgb, or rgb and alpha
+    let (rgba, format): (Vec<u8>, PixelFormat) = match image.depth {
+      2 => (samples.chunks(2).flat_map(|tuple| [tuple[0], tuple[0], tuple[0], tuple[1]]).collect(), PixelFormat::Bgra32),
+      3 => (samples.chunks(3).flat_map(|tuple| [tuple[0], tuple[1], tuple[2], 255]).collect(), PixelFormat::Rgb24),
+      4 => (samples, PixelFormat::Bgra32),
+      _ => return Err(Error::Unsupported("PAM depth other than 1 to 4")),
+    };
+    return BMP::from_rgba8(image.width, image.height, &rgba, format);
+  }
+  //the format the pixels are stored in. RLE images are reported as the indexed format they decode to
+  pub fn get_pixel_format(&self) -> Result<PixelFormat, Error> {
+    let decoded = self.get_decoded_pixels();
+    let layout = match decoded {
+      Ok((_, returned_layout)) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    let bitcount = match self.get_dib_header() {
+      //biBitCount is 0, PNGs are decoded to 32 bit
+      Ok(dib_header) if dib_header.compression == Some(Compression::BI_PNG) => layout.bitcount,
+      Ok(dib_header) => dib_header.bitcount,
+      Err(e) => return Err(e),
+    };
+    match (bitcount, layout.masks) {
+      (1, _) => return Ok(PixelFormat::Indexed1),
+      (2, _) => return Ok(PixelFormat::Indexed2),
+      (4, _) => return Ok(PixelFormat::Indexed4),
+      (8, _) => return Ok(PixelFormat::Indexed8),
+      (16, [0x7c00, 0x03e0, 0x001f, 0]) => return Ok(PixelFormat::Rgb555),
+      (16, [0xf800, 0x07e0, 0x001f, 0]) => return Ok(PixelFormat::Rgb565),
+      (24, _) => return Ok(PixelFormat::Rgb24),
+      (32, [0x00ff0000, 0x0000ff00, 0x000000ff, 0]) => return Ok(PixelFormat::Bgrx32),
+      (32, [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000]) => return Ok(PixelFormat::Bgra32),
+      (32, [red, green, blue, alpha]) => return Ok(PixelFormat::Bitfields32 { red, green, blue, alpha }),
+      (bitcount, masks) => return Err(Error::NoPixelFormat { bitcount, masks }),
+    }
+  }
+  //rewrites the headers, color table or masks and pixel array to store the pixels in another format
+  //indexed formats get a color table made with median cut if there are too many colors. alpha is only kept if the new format has it
+  pub fn convert(&mut self, target_format: PixelFormat) -> Result<(), Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    let converted: Result<BMP, Error>;
+    if target_format.max_colors().is_some() {
+      converted = self.quantize(target_format, &Quantizer::MedianCut);
+    } else {
+      converted = match self.to_rgba8() {
+        Ok((width, height, rgba)) => BMP::from_rgba8(width, height, &rgba, target_format),
+        Err(e) => Err(e),
+      };
+    }
+    let mut converted = match converted {
+      Ok(returned_converted) => returned_converted,
+      Err(e) => return Err(e),
+    };
+    //keep the resolution
+    if let (Some(x_pels_per_meter), Some(y_pels_per_meter)) = (dib_header.XPelsPerMeter, dib_header.YPelsPerMeter) {
+      BMP::write_int(&mut converted.contents, HEADER_OFFSET+24, x_pels_per_meter);
+      BMP::write_int(&mut converted.contents, HEADER_OFFSET+28, y_pels_per_meter);
+    }
+    self.contents = converted.contents;
+    return Ok(());
+  }
+  //pixel iterators. these read straight from self.contents, starting at the top left and going row by row, no matter if the file is stored top down or bottom up
+  pub fn pixels(&self) -> Result<Pixels<'_>, Error> {
+    return match self.enumerate_pixels() {
+      Ok(enumerate_pixels) => Ok(Pixels { inner: enumerate_pixels }),
+      Err(e) => Err(e),
+    };
+  }
+  pub fn enumerate_pixels(&self) -> Result<EnumeratePixels<'_>, Error> {
+    let decoded = self.get_decoded_pixels();
+    let (contents, layout) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    return Ok(EnumeratePixels { contents, layout, x: 0, y: 0 });
+  }
+  pub fn rows(&self) -> Result<Rows<'_>, Error> {
+    let decoded = self.get_decoded_pixels();
+    let (contents, layout) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    return Ok(Rows { contents: Rc::new(contents), layout: Rc::new(layout), y: 0 });
+  }
+  pub fn pixels_mut(&mut self) -> Result<PixelsMut<'_>, Error> {
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    //compressed images can't be changed in place, get_pixel_layout errors for them
+    //several pixels can share a byte (1, 2 and 4 bit), so hand out cells instead of &mut u8
+    let contents = Cell::from_mut(&mut self.contents[..]).as_slice_of_cells();
+    return Ok(PixelsMut { contents, layout: Rc::new(layout), x: 0, y: 0 });
+  }
+  //location here is told
+  //ICC color profile
+  //https://en.wikipedia.org/wiki/Color_management
+  //(CSType, start, end) of the profile data of a V5 header. it is an ICC profile (PROFILE_EMBEDDED) or a file name (PROFILE_LINKED)
+  fn get_profile_data_position(&self) -> Result<(CSType, usize, usize), Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    if dib_header.size != 124 {
+      return Err(Error::DoesNotExist("profile data, which is only in V5 headers"));
+    }
+    let cs_type = dib_header.CSType.unwrap_or(CSType::LCS_sRGB);
+    if cs_type != CSType::PROFILE_EMBEDDED && cs_type != CSType::PROFILE_LINKED {
+      return Err(Error::DoesNotExist("profile data"));
+    }
+    //ProfileData is from the start of the dib header, not the file
+    let start = HEADER_OFFSET+dib_header.ProfileData.unwrap_or(0) as usize;
+    let size = dib_header.ProfileSize.unwrap_or(0) as usize;
+    if start+size > self.contents.len() {
+      return Err(Error::Truncated { offset: start, length: size, file_size: self.contents.len() });
+    }
+    return Ok((cs_type, start, start+size));
+  }
+  //raw bytes of the embedded ICC profile
+  pub fn get_color_profile(&self) -> Result<Vec<u8>, Error> {
+    let position = self.get_profile_data_position();
+    let (cs_type, start, end) = match position {
+      Ok(returned_position) => returned_position,
+      Err(e) => return Err(e),
+    };
+    if cs_type != CSType::PROFILE_EMBEDDED {
+      return Err(Error::DoesNotExist("embedded profile"));
+    }
+    return Ok(self.contents[start..end].to_vec());
+  }
+  pub fn get_icc_profile(&self) -> Result<IccProfile, Error> {
+    let profile = self.get_color_profile();
+    return match profile {
+      Ok(returned_profile) => IccProfile::parse(&returned_profile),
+      Err(e) => Err(e),
+    };
+  }
+  //file name of a PROFILE_LINKED profile, a null terminated string in the windows code page
+  pub fn linked_profile_path(&self) -> Result<String, Error> {
+    let position = self.get_profile_data_position();
+    let (cs_type, start, end) = match position {
+      Ok(returned_position) => returned_position,
+      Err(e) => return Err(e),
+    };
+    if cs_type != CSType::PROFILE_LINKED {
+      return Err(Error::DoesNotExist("linked profile"));
+    }
+    return Ok(icc::decode_windows_1252(&self.contents[start..end]));
+  }
+  //the embedded profile, or the linked profile from the loader, eg: a ProfileLoader or a closure
+  pub fn load_color_profile(&self, loader: &dyn ProfileSource) -> Result<IccProfile, Error> {
+    let linked_profile_path = self.linked_profile_path();
+    return match linked_profile_path {
+      Ok(returned_path) => loader.load_profile(&returned_path),
+      Err(_) => self.get_icc_profile(),
+    };
+  }
+  //embeds an ICC profile, replacing the current profile (or linked profile name). only BITMAPV5HEADER images can have one
+  //the profile goes at the end of the file, after the pixel array
+  pub fn set_color_profile(&mut self, profile: &[u8]) -> Result<(), Error> {
+    match IccProfile::parse(profile) {
+      Ok(_) => {},
+      Err(e) => return Err(e),
+    };
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    if dib_header.size != 124 {
+      return Err(Error::Unsupported("profiles can only be embedded in V5 headers"));
+    }
+    self.remove_profile_data();
+    let profile_data = self.contents.len()-HEADER_OFFSET;
+    if self.contents.len()+profile.len() > u32::MAX as usize {
+      return Err(Error::Overflow { field: "bfSize" });
+    }
+    self.contents.extend_from_slice(profile);
+    let file_size = self.contents.len() as u32;
+    BMP::write_int(&mut self.contents, 2, file_size);
+    BMP::write_int(&mut self.contents, HEADER_OFFSET+56, CSType::PROFILE_EMBEDDED.to_u32());
+    BMP::write_int(&mut self.contents, HEADER_OFFSET+112, profile_data as u32);
+    BMP::write_int(&mut self.contents, HEADER_OFFSET+116, profile.len() as u32);
+    return Ok(());
+  }
+  //takes out the profile data (if any), leaving ProfileData and ProfileSize for the caller to set
+  fn remove_profile_data(&mut self) {
+    if let Ok((_, start, end)) = self.get_profile_data_position() {
+      self.contents.drain(start..end);
+      //the pixel array moves back if the old profile was in front of it
+      let offset = self.get_offset() as usize;
+      if offset >= end {
+        BMP::write_int(&mut self.contents, 10, (offset-(end-start)) as u32);
+      }
+      let file_size = self.contents.len() as u32;
+      BMP::write_int(&mut self.contents, 2, file_size);
+    }
+  }
+  //calibrated rgb fields of V4 and V5 headers, DoesNotExist for older headers
+  pub fn get_endpoints(&self) -> Result<CIEXYZTRIPLE, Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    return match dib_header.Endpoints {
+      Some(endpoints) => Ok(CIEXYZTRIPLE::from_fxpt2dot30(endpoints)),
+      None => Err(Error::DoesNotExist("endpoints, which are only in V4 and V5 headers")),
+    };
+  }
+  //red, green, blue
+  pub fn get_gamma(&self) -> Result<[f64; 3], Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    return match (dib_header.GammaRed, dib_header.GammaGreen, dib_header.GammaBlue) {
+      //16.16 fixed point
+      (Some(red), Some(green), Some(blue)) => Ok([red, green, blue].map(|gamma| gamma as f64/65536.0)),
+      _ => Err(Error::DoesNotExist("gamma, which is only in V4 and V5 headers")),
+    };
+  }
+  //these don't change CSType, the endpoints and gammas are only used by LCS_CALIBRATED_RGB
+  pub fn set_endpoints(&mut self, endpoints: CIEXYZTRIPLE) -> Result<(), Error> {
+    let color_space = self.get_color_space_info();
+    let mut color_space = match color_space {
+      Ok(returned_color_space) => returned_color_space,
+      Err(e) => return Err(e),
+    };
+    match color_space.set_endpoints(endpoints) {
+      Ok(_) => {},
+      Err(e) => return Err(e),
+    };
+    return self.write_color_space_info(&color_space);
+  }
+  pub fn set_gamma(&mut self, gamma: [f64; 3]) -> Result<(), Error> {
+    let color_space = self.get_color_space_info();
+    let mut color_space = match color_space {
+      Ok(returned_color_space) => returned_color_space,
+      Err(e) => return Err(e),
+    };
+    match color_space.set_gamma(gamma) {
+      Ok(_) => {},
+      Err(e) => return Err(e),
+    };
+    return self.write_color_space_info(&color_space);
+  }
+  //writes the endpoints and gammas back into a V4 or V5 header
+  fn write_color_space_info(&mut self, color_space: &ColorSpaceInfo) -> Result<(), Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    if dib_header.size < 108 {
+      return Err(Error::Unsupported("color space info needs a V4 or V5 header"));
+    }
+    for (i, value) in color_space.endpoints.iter().flatten().enumerate() {
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+60+i*4, *value as u32);
+    }
+    for (i, value) in color_space.gamma.iter().enumerate() {
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+96+i*4, *value);
+    }
+    return Ok(());
+  }
+  //color management
+  //the color space the pixels are in. headers older than V4 are sRGB, linked profiles are found with the loader
+  pub fn get_rgb_color_space(&self, loader: &dyn ProfileSource) -> Result<RgbColorSpace, Error> {
+    let color_space = self.get_color_space_info();
+    let color_space = match color_space {
+      Ok(returned_color_space) => returned_color_space,
+      Err(e) => return Err(e),
+    };
+    match color_space.cs_type {
+      CSType::LCS_CALIBRATED_RGB => {
+        return RgbColorSpace::from_calibrated(color_space.get_endpoints(), color_space.get_gamma());
+      },
+      CSType::PROFILE_EMBEDDED | CSType::PROFILE_LINKED => {
+        return match self.load_color_profile(loader) {
+          Ok(profile) => RgbColorSpace::from_icc(&profile),
+          Err(e) => Err(e),
+        };
+      },
+      //LCS_sRGB, LCS_WINDOWS_COLOR_SPACE and anything unknown
+      _ => return Ok(RgbColorSpace::srgb()),
+    }
+  }
+  //changes every color (the color table for indexed images), alpha is left alone
+  fn apply_color_transform(&mut self, transform: &ColorTransform) -> Result<(), Error> {
+    let mut cache: HashMap<[u8; 3], [u8; 3]> = HashMap::new();
+    let mut convert = |rgb: [u8; 3]| *cache.entry(rgb).or_insert_with(|| transform.apply(rgb));
+    let format = self.get_pixel_format();
+    let format = match format {
+      Ok(returned_format) => returned_format,
+      Err(e) => return Err(e),
+    };
+    if format.max_colors().is_some() {
+      let mut palette = match self.get_palette() {
+        Ok(returned_palette) => returned_palette,
+        Err(e) => return Err(e),
+      };
+      for i in 0..palette.len() {
+        let converted = convert(palette.get(i).unwrap());
+        palette.set(i, converted).unwrap();
+      }
+      return self.set_palette(&palette);
+    }
+    let pixels = self.pixels_mut();
+    let pixels = match pixels {
+      Ok(returned_pixels) => returned_pixels,
+      Err(e) => return Err(e),
+    };
+    for pixel in pixels {
+      let [red, green, blue, alpha] = pixel.get();
+      let [red, green, blue] = convert([red, green, blue]);
+      pixel.set([red, green, blue, alpha]);
+    }
+    return Ok(());
+  }
+  //converts the pixels from the file's color space (calibrated rgb or a matrix/TRC ICC profile) to sRGB, and marks the file as LCS_sRGB
+  pub fn convert_to_srgb(&mut self, loader: &dyn ProfileSource) -> Result<(), Error> {
+    let color_space = self.get_rgb_color_space(loader);
+    let transform = match color_space {
+      Ok(returned_color_space) => ColorTransform::new(&returned_color_space, &RgbColorSpace::srgb()),
+      Err(e) => return Err(e),
+    };
+    let transform = match transform {
+      Ok(returned_transform) => returned_transform,
+      Err(e) => return Err(e),
+    };
+    match self.apply_color_transform(&transform) {
+      Ok(_) => {},
+      Err(e) => return Err(e),
+    };
+    let dib_header = match self.get_dib_header() {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    if dib_header.size >= 124 {
+      self.remove_profile_data();
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+112, 0);
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+116, 0);
+    }
+    if dib_header.size >= 108 {
+      BMP::write_int(&mut self.contents, HEADER_OFFSET+56, CSType::LCS_sRGB.to_u32());
+    }
+    return Ok(());
+  }
+  //the other way, converts sRGB pixels into the file's color space. the header is left as it is
+  pub fn convert_from_srgb(&mut self, loader: &dyn ProfileSource) -> Result<(), Error> {
+    let color_space = self.get_rgb_color_space(loader);
+    let transform = match color_space {
+      Ok(returned_color_space) => ColorTransform::new(&RgbColorSpace::srgb(), &returned_color_space),
+      Err(e) => return Err(e),
+    };
+    let transform = match transform {
+      Ok(returned_transform) => returned_transform,
+      Err(e) => return Err(e),
+    };
+    return self.apply_color_transform(&transform);
+  }
+  //interpret color data
+  //returns an array rgba (4 u8)
+  pub fn get_color_of_px(&self, x: usize, y: usize) -> Result<[u8; 4], Error> {
+    return self.get_pixel_checked(x, y);
+  }
+  //same as get_color_of_px
+  pub fn get_pixel_checked(&self, x: usize, y: usize) -> Result<[u8; 4], Error> {
+    let decoded = self.get_decoded_pixels();
+    let (pixels, layout) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    if x >= layout.width || y >= layout.height {
+      return Err(Error::OutOfBounds { x, y, width: layout.width, height: layout.height });
+    }
+    return Ok(layout.get(&pixels, x, y));
+  }
+  //coordinates aren't checked, out of range ones read some other pixel or panic
+  //uncompressed pixels, and their color table entry, are read straight from contents. compressed ones come from the decoded pixels, and panic if they can't be decoded
+  pub fn get_pixel_unchecked(&self, x: usize, y: usize) -> [u8; 4] {
+    let layout = match self.get_pixel_layout_without_palette() {
+      Ok(returned_layout) => returned_layout,
+      Err(_) => {
+        return match self.get_decoded_pixels() {
+          Ok((pixels, layout)) => layout.get(&pixels, x, y),
+          Err(e) => panic!("image could not be decoded: {}", e),
+        };
+      },
+    };
+    let raw = layout.read_raw(|i| self.contents[i], layout.row_start(y), x);
+    if layout.bitcount > 8 {
+      return layout.raw_to_rgba(raw);
+    }
+    //indexes not in the color table are treated as black, like raw_to_rgba
+    match self.get_color_table_position() {
+      Ok((start, entry_size, count)) if (raw as usize) < count => {
+        let entry = start+raw as usize*entry_size;
+        return [self.contents[entry+2], self.contents[entry+1], self.contents[entry], 255];
+      },
+      _ => return [0, 0, 0, 255],
+    }
+  }
+  //edit color pixels. new_color is rgba, and y is counted from the top whether the image is top down or bottom up
+  //for images with a color table, the closest color in the table is used
+  pub fn change_color_of_pixel(&mut self, x: usize, y: usize, new_color: [u8; 4]) -> Result<(), Error> {
+    //compressed images can't be changed in place, get_pixel_layout errors for them
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    if x >= layout.width || y >= layout.height {
+      return Err(Error::OutOfBounds { x, y, width: layout.width, height: layout.height });
+    }
+    let raw = layout.rgba_to_raw(new_color);
+    let contents = Cell::from_mut(&mut self.contents[..]).as_slice_of_cells();
+    layout.write_raw(|i| contents[i].get(), |i, byte| contents[i].set(byte), layout.row_start(y), x, raw);
+    return Ok(());
+  }
+  //image editing functions
+  pub fn draw_image(&mut self, _bmp2: BMP) {
+    //
+  }
+  pub fn filter(&mut self) {
+    //add/subtract to r,g,b for each pixel?
+    //masking
+  }
+  //shape, line making functions
+  //parts of shapes outside of the image are clipped, only images that can't be edited return errors
+  //https://en.wikipedia.org/wiki/Bresenham%27s_line_algorithm
+  pub fn draw_line(&mut self, fill: [u8; 4], p1: [usize; 2], p2: [usize; 2]) -> Result<(), Error> {
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    let raw = layout.rgba_to_raw(fill);
+    let contents = Cell::from_mut(&mut self.contents[..]).as_slice_of_cells();
+    let (mut x, mut y) = (p1[0] as i64, p1[1] as i64);
+    let (x2, y2) = (p2[0] as i64, p2[1] as i64);
+    let dx = (x2-x).abs();
+    let dy = -(y2-y).abs();
+    let step_x = if x < x2 { 1 } else { -1 };
+    let step_y = if y < y2 { 1 } else { -1 };
+    let mut error = dx+dy;
+    loop {
+      layout.set_clipped(contents, x, y, raw);
+      if x == x2 && y == y2 {
+        break;
+      }
+      let doubled_error = error*2;
+      if doubled_error >= dy {
+        error += dy;
+        x += step_x;
+      }
+      if doubled_error <= dx {
+        error += dx;
+        y += step_y;
+      }
+    }
+    return Ok(());
+  }
+  //p1 and p2 are opposite corners, both included
+  pub fn draw_rectangle(&mut self, fill: Option<[u8; 4]>, stroke: Option<[u8; 4]>, p1: [usize; 2], p2: [usize; 2]) -> Result<(), Error> {
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    let contents = Cell::from_mut(&mut self.contents[..]).as_slice_of_cells();
+    let (left, right) = (p1[0].min(p2[0]) as i64, p1[0].max(p2[0]) as i64);
+    let (top, bottom) = (p1[1].min(p2[1]) as i64, p1[1].max(p2[1]) as i64);
+    if let Some(fill) = fill {
+      let raw = layout.rgba_to_raw(fill);
+      for y in top.max(0)..=bottom.min(layout.height as i64-1) {
+        layout.fill_span_clipped(contents, left, right, y, raw);
+      }
+    }
+    if let Some(stroke) = stroke {
+      let raw = layout.rgba_to_raw(stroke);
+      layout.fill_span_clipped(contents, left, right, top, raw);
+      layout.fill_span_clipped(contents, left, right, bottom, raw);
+      for y in top.max(0)..=bottom.min(layout.height as i64-1) {
+        layout.set_clipped(contents, left, y, raw);
+        layout.set_clipped(contents, right, y, raw);
+      }
+    }
+    return Ok(());
+  }
+  //xlength and ylength are the distances from the center to the edge (radii)
+  pub fn draw_ellipse(&mut self, fill: Option<[u8; 4]>, stroke: Option<[u8; 4]>, center: [usize; 2], xlength: usize, ylength: usize) -> Result<(), Error> {
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    let contents = Cell::from_mut(&mut self.contents[..]).as_slice_of_cells();
+    let (center_x, center_y) = (center[0] as i64, center[1] as i64);
+    let (rx, ry) = (xlength as i64, ylength as i64);
+    //half of the width of each row, from the top of the ellipse to the bottom
+    let half_widths: Vec<i64> = (-ry..=ry).map(|dy| {
+      if ry == 0 {
+        return rx;
+      }
+      let t = dy as f64/ry as f64;
+      return (rx as f64*(1.0-t*t).max(0.0).sqrt()).round() as i64;
+    }).collect();
+    let fill = fill.map(|fill| layout.rgba_to_raw(fill));
+    let stroke = stroke.map(|stroke| layout.rgba_to_raw(stroke));
+    for (i, half_width) in half_widths.iter().enumerate() {
+      let y = center_y-ry+i as i64;
+      if y < 0 || y >= layout.height as i64 {
+        continue;
+      }
+      if let Some(raw) = fill {
+        layout.fill_span_clipped(contents, center_x-half_width, center_x+half_width, y, raw);
+      }
+      if let Some(raw) = stroke {
+        //the outline goes from the edge of this row in to the edge of the narrower row next to it, so steep parts have no gaps
+        //the top and bottom rows are all outline
+        let inner = if i == 0 || i == half_widths.len()-1 {
+          -1
+        } else {
+          half_widths[i-1].min(half_widths[i+1]).min(*half_width-1)
+        };
+        layout.fill_span_clipped(contents, center_x-half_width, center_x-inner-1, y, raw);
+        layout.fill_span_clipped(contents, center_x+inner+1, center_x+half_width, y, raw);
+      }
+    }
+    return Ok(());
+  }
+  pub fn fill_bucket(&mut self, fill: [u8; 4], x: usize, y: usize) -> Result<Vec<[usize; 2]>, Error> {
+    //fill same color connected to the (x,y) with new paint
+    //check up, down, left, right. If same color as initial square, add to queue. Iterate through queue, after iterating add to visit and repeat
+    //worked out once and read directly, instead of going through get_color_of_px for every neighbour
+    //compressed images can't be changed in place, get_pixel_layout errors for them
+    let layout = self.get_pixel_layout();
+    let layout = match layout {
+      Ok(returned_layout) => returned_layout,
+      Err(e) => return Err(e),
+    };
+    if x >= layout.width || y >= layout.height {
+      return Err(Error::OutOfBounds { x, y, width: layout.width, height: layout.height });
+    }
+    let replace_color: [u8; 4] = layout.get(&self.contents, x, y);
+    let mut visited: Vec<[usize; 2]> = Vec::new();
+    let mut queue: Vec<[usize; 2]> = Vec::new();
+    queue.push([x, y]);
+    while !queue.is_empty() {
+      if visited.contains(&queue[0]) {
+        queue.remove(0);
+        continue;
+      }
+      let x2: usize = queue[0][0];
+      let y2: usize = queue[0][1];
+      //turn current coords into fill color
+      //self.change_color_of_pixel(x2, y2, fill);
+      //check is surrounding (up, down, left, right) are same color
+      //check to make sure these coords exist. (get height, width)
+      //remember, indexes start at 0
+      if y2+1 < layout.height {
+        let down_color: [u8; 4] = layout.get(&self.contents, x2, y2+1);
+        if down_color == replace_color {
+          queue.push([x2, y2+1]);
+        }
+      }
+      if y2 > 0 {
+        let up_color: [u8; 4] = layout.get(&self.contents, x2, y2-1);
+        if up_color == replace_color {
+          queue.push([x2, y2-1]);
+        }
+      }
+      if x2 > 0 {
+        let left_color: [u8; 4] = layout.get(&self.contents, x2-1, y2);
+        if left_color == replace_color {
+          queue.push([x2-1, y2]);
+        }
+      }
+      if x2+1 < layout.width {
+        let right_color: [u8; 4] = layout.get(&self.contents, x2+1, y2);
+        if right_color == replace_color {
+          queue.push([x2+1, y2]);
+        }
+      }
+      //end
+      visited.push(queue[0]);
+      queue.remove(0);
+    }
+    //loop through visited
+    for px in &visited {
+      match self.change_color_of_pixel(px[0], px[1], fill) {
+        Ok(_) => {},
+        Err(e) => return Err(e),
+      };
+    }
+    //&self.save_to_new("src/images/e2.bmp");
+    return Ok(visited);
+  }
+  //save image functions
+  pub fn save_to_new(&self, file_path: &str) -> Result<(), Error> {
+    return self.save(file_path);
+  }
+  //writes to a temporary file next to file_path and renames it over, so a failed save never leaves half a file
+  pub fn save(&self, file_path: &str) -> Result<(), Error> {
+    return BMP::write_file_atomically(file_path, &self.contents);
+  }
+  pub fn save_to_new_with_options(&self, file_path: &str, options: &SaveOptions) -> Result<(), Error> {
+    let encoded = self.encode(options);
+    let encoded = match encoded {
+      Ok(returned_encoded) => returned_encoded,
+      Err(e) => return Err(e),
+    };
+    return BMP::write_file_atomically(file_path, &encoded);
+  }
+  //for sockets, in memory buffers, etc
+  pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+    writer.write_all(&self.contents)?;
+    writer.flush()?;
+    return Ok(());
+  }
+  //PNG export. indexed images keep their color table, and alpha is kept if any pixel isn't opaque
+  //XPelsPerMeter and YPelsPerMeter go in the pHYs chunk
+  fn to_png_image(&self) -> Result<png::PngImage, Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    //bit masks with no PixelFormat are direct color, so they go through to_rgba8 like the rest
+    let indexed_format = match self.get_pixel_format() {
+      Ok(format) if format.max_colors().is_some() => Some(format),
+      Ok(_) | Err(Error::NoPixelFormat { .. }) => None,
+      Err(e) => return Err(e),
+    };
+    let pixels_per_meter = match (dib_header.XPelsPerMeter, dib_header.YPelsPerMeter) {
+      (Some(x), Some(y)) if x > 0 && y > 0 => Some((x, y)),
+      _ => None,
+    };
+    if let Some(format) = indexed_format {
+      let decoded = self.get_decoded_pixels();
+      let (pixels, layout) = match decoded {
+        Ok(returned_decoded) => returned_decoded,
+        Err(e) => return Err(e),
+      };
+      let mut palette = match self.get_palette() {
+        Ok(returned_palette) => returned_palette.colors,
+        Err(e) => return Err(e),
+      };
+      let mut samples: Vec<u16> = Vec::with_capacity(layout.width*layout.height);
+      for y in 0..layout.height {
+        let row_start = layout.row_start(y);
+        for x in 0..layout.width {
+          samples.push(layout.read_raw(|i| pixels[i], row_start, x) as u16);
+        }
+      }
+      //PNG indexes have to be in the palette, bitmaps show missing colors as black
+      let highest = samples.iter().copied().max().unwrap_or(0) as usize;
+      if palette.len() <= highest {
+        palette.resize(highest+1, [0; 3]);
+      }
+      return Ok(png::PngImage { width: layout.width as u32, height: layout.height as u32, bit_depth: format.bitcount() as u8, color_type: png::ColorType::Indexed, interlaced: false, palette, palette_alpha: Vec::new(), transparent_color: None, pixels_per_meter, samples });
+    }
+    let (width, height, rgba) = match self.to_rgba8() {
+      Ok(returned_rgba) => returned_rgba,
+      Err(e) => return Err(e),
+    };
+    let has_alpha = rgba.chunks(4).any(|pixel| pixel[3] != 255);
+    let samples: Vec<u16> = if has_alpha {
+      rgba.iter().map(|sample| *sample as u16).collect()
+    } else {
+      rgba.chunks(4).flat_map(|pixel| [pixel[0] as u16, pixel[1] as u16, pixel[2] as u16]).collect()
+    };
+    let color_type = if has_alpha { png::ColorType::Rgba } else { png::ColorType::Rgb };
+    return Ok(png::PngImage { width, height, bit_depth: 8, color_type, interlaced: false, palette: Vec::new(), palette_alpha: Vec::new(), transparent_color: None, pixels_per_meter, samples });
+  }
+  pub fn encode_png(&self) -> Result<Vec<u8>, Error> {
+    return match self.to_png_image() {
+      Ok(image) => Ok(image.encode()),
+      Err(e) => Err(e),
+    };
+  }
+  pub fn save_png(&self, file_path: &str) -> Result<(), Error> {
+    return match self.encode_png() {
+      Ok(bytes) => BMP::write_file_atomically(file_path, &bytes),
+      Err(e) => Err(e),
+    };
+  }
+  pub fn write_png<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+    let bytes = match self.encode_png() {
+      Ok(returned_bytes) => returned_bytes,
+      Err(e) => return Err(e),
+    };
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    return Ok(());
+  }
+  //Netpbm export. without a format, 1 bit black and white images are PBM, anything with alpha is PAM, gray images are PGM and the rest PPM
+  //PGM is the luma of each pixel, PBM is black if the luma is under 128, and PPM drops alpha
+  fn to_netpbm_image(&self, options: &NetpbmOptions) -> Result<(NetpbmFormat, netpbm::NetpbmImage), Error> {
+    let format = self.get_pixel_format();
+    let format = match format {
+      Ok(returned_format) => returned_format,
+      Err(e) => return Err(e),
+    };
+    let (width, height, rgba) = match self.to_rgba8() {
+      Ok(returned_rgba) => returned_rgba,
+      Err(e) => return Err(e),
+    };
+    let has_alpha = rgba.chunks(4).any(|pixel| pixel[3] != 255);
+    let is_gray = rgba.chunks(4).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let is_black_and_white = format == PixelFormat::Indexed1 && rgba.chunks(4).all(|pixel| pixel[0..3] == [0; 3] || pixel[0..3] == [255; 3]);
+    let netpbm_format = match options.format {
+      Some(netpbm_format) => netpbm_format,
+      None if has_alpha => NetpbmFormat::Pam,
+      None if is_black_and_white => NetpbmFormat::Pbm,
+      None if is_gray => NetpbmFormat::Pgm,
+      None => NetpbmFormat::Ppm,
+    };
+    let maxval = if netpbm_format == NetpbmFormat::Pbm || (netpbm_format == NetpbmFormat::Pam && is_black_and_white) { 1 } else { options.maxval };
+    if maxval == 0 {
+      return Err(Error::Unsupported("maxval of 0"));
+    }
+    let scale = |sample: u8| ((sample as u32*maxval as u32+127)/255) as u16;
+    let luma = |pixel: &[u8]| ((pixel[0] as u32*299+pixel[1] as u32*587+pixel[2] as u32*114+500)/1000) as u8;
+    let (depth, tuple_type) = match netpbm_format {
+      NetpbmFormat::Pbm => (1, "BLACKANDWHITE"),
+      NetpbmFormat::Pgm => (1, "GRAYSCALE"),
+      NetpbmFormat::Ppm => (3, "RGB"),
+      NetpbmFormat::Pam => match (is_black_and_white, is_gray, has_alpha) {
+        (true, _, false) => (1, "BLACKANDWHITE"),
+        (true, _, true) => (2, "BLACKANDWHITE_ALPHA"),
+        (_, true, false) => (1, "GRAYSCALE"),
+        (_, true, true) => (2, "GRAYSCALE_ALPHA"),
+        (_, false, false) => (3, "RGB"),
+        (_, false, true) => (4, "RGB_ALPHA"),
+      },
+    };
+    let mut samples: Vec<u16> = Vec::with_capacity(rgba.len()/4*depth);
+    for pixel in rgba.chunks(4) {
+      match (netpbm_format, depth) {
+        (NetpbmFormat::Pbm, _) => samples.push((luma(pixel) >= 128) as u16),
+        (_, 1) => samples.push(scale(luma(pixel))),
+        (_, 2) => samples.extend_from_slice(&[scale(luma(pixel)), scale(pixel[3])]),
+        (_, 3) => samples.extend(pixel[0..3].iter().map(|sample| scale(*sample))),
+        _ => samples.extend(pixel.iter().map(|sample| scale(*sample))),
+      }
+    }
+    return Ok((netpbm_format, netpbm::NetpbmImage { width, height, depth, maxval, tuple_type: tuple_type.to_string(), samples }));
+  }
+  //Unsupported for ascii PAM, which doesn't exist
+  pub fn encode_netpbm(&self, options: &NetpbmOptions) -> Result<Vec<u8>, Error> {
+    return match self.to_netpbm_image(options) {
+      Ok((format, image)) => image.encode(format, options.ascii),
+      Err(e) => Err(e),
+    };
+  }
+  pub fn save_netpbm(&self, file_path: &str, options: &NetpbmOptions) -> Result<(), Error> {
+    return match self.encode_netpbm(options) {
+      Ok(bytes) => BMP::write_file_atomically(file_path, &bytes),
+      Err(e) => Err(e),
+    };
+  }
+  pub fn write_netpbm<W: Write>(&self, mut writer: W, options: &NetpbmOptions) -> Result<(), Error> {
+    let bytes = match self.encode_netpbm(options) {
+      Ok(returned_bytes) => returned_bytes,
+      Err(e) => return Err(e),
+    };
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    return Ok(());
+  }
+  fn write_file_atomically(file_path: &str, bytes: &[u8]) -> Result<(), Error> {
+    let path = Path::new(file_path);
+    let file_name = match path.file_name() {
+      Some(file_name) => file_name.to_string_lossy(),
+      None => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))),
+    };
+    //same directory, so the rename doesn't cross filesystems
+    //the process id and a counter keep saves from other processes and threads apart, and create_new makes sure the temp file is only ours
+    let (temp_path, mut temp_file) = loop {
+      let temp_path = path.with_file_name(format!(".{}.{}.{}.tmp", file_name, std::process::id(), TEMP_FILE_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)));
+      match fs::OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+        Ok(temp_file) => break (temp_path, temp_file),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+        Err(e) => return Err(Error::Io(e)),
+      }
+    };
+    let written = temp_file.write_all(bytes).and_then(|_| temp_file.sync_all());
+    //closed before the rename, windows can't rename open files
+    drop(temp_file);
+    let written = written.and_then(|_| fs::rename(&temp_path, path));
+    match written {
+      Ok(_) => return Ok(()),
+      Err(e) => {
+        let _ = fs::remove_file(&temp_path);
+        return Err(Error::Io(e));
+      },
+    };
+  }
+  //color space of the current header, or sRGB if it doesn't have one
+  pub fn get_color_space_info(&self) -> Result<ColorSpaceInfo, Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    //LCS_sRGB and LCS_GM_IMAGES
+    let mut color_space = ColorSpaceInfo { cs_type: CSType::LCS_sRGB, endpoints: [[0; 3]; 3], gamma: [0; 3], intent: Intent::LCS_GM_IMAGES };
+    if dib_header.size >= 108 {
+      color_space.cs_type = dib_header.CSType.unwrap_or(CSType::LCS_sRGB);
+      color_space.endpoints = dib_header.Endpoints.unwrap_or([[0; 3]; 3]);
+      color_space.gamma = [dib_header.GammaRed.unwrap_or(0), dib_header.GammaGreen.unwrap_or(0), dib_header.GammaBlue.unwrap_or(0)];
+    }
+    if dib_header.size >= 124 {
+      color_space.intent = dib_header.Intent.unwrap_or(Intent::LCS_GM_IMAGES);
+    }
+    return Ok(color_space);
+  }
+  //writes the image into a new file's bytes, with the header version, orientation, compression, resolution and color space from options
+  //bfSize, bfOffBits and biSizeImage are worked out again
+  pub fn encode(&self, options: &SaveOptions) -> Result<Vec<u8>, Error> {
+    let dib_header = self.get_dib_header();
+    let dib_header = match dib_header {
+      Ok(returned_dib_header) => returned_dib_header,
+      Err(e) => return Err(e),
+    };
+    //bit masks with no PixelFormat (like 16 bit RGB444) can still be read, so their pixels are written as 32 bit, or 24 bit without an alpha mask
+    let fallback: Option<BMP> = match self.get_pixel_format() {
+      Ok(_) => None,
+      Err(Error::NoPixelFormat { masks, .. }) => {
+        let has_alpha = masks[3] != 0;
+        let (width, height, rgba) = match self.to_rgba8() {
+          Ok(returned_rgba) => returned_rgba,
+          Err(e) => return Err(e),
+        };
+        match BMP::from_rgba8(width, height, &rgba, if has_alpha { PixelFormat::Bgra32 } else { PixelFormat::Rgb24 }) {
+          Ok(converted) => Some(converted),
+          Err(e) => return Err(e),
+        }
+      },
+      Err(e) => return Err(e),
+    };
+    let source = fallback.as_ref().unwrap_or(self);
+    let format = source.get_pixel_format();
+    let format = match format {
+      Ok(returned_format) => returned_format,
+      Err(e) => return Err(e),
+    };
+    let decoded = source.get_decoded_pixels();
+    let (pixels, layout) = match decoded {
+      Ok(returned_decoded) => returned_decoded,
+      Err(e) => return Err(e),
+    };
+    let palette: Option<Palette> = if format.max_colors().is_some() {
+      match source.get_palette() {
+        Ok(palette) => Some(palette),
+        Err(e) => return Err(e),
+      }
+    } else {
+      None
+    };
+    let source_compression = dib_header.compression.unwrap_or(Compression::BI_RGB);
+    let header = options.header.unwrap_or(match dib_header.size {
+      12 => HeaderVersion::Core,
+      108 => HeaderVersion::V4,
+      124 => HeaderVersion::V5,
+      //BITMAPINFOHEADER, and OS/2 2.x headers which can't be written
+      _ => HeaderVersion::Info,
+    });
+    let top_down = options.top_down.unwrap_or(dib_header.height < 0);
+    //formats that can't be described without masks have to use BI_BITFIELDS
+    let needs_masks = matches!(format, PixelFormat::Rgb565 | PixelFormat::Bgra32 | PixelFormat::Bitfields32 { .. });
+    let compression = match options.compression {
+      Some(compression) => compression,
+      None if needs_masks => SaveCompression::Bitfields,
+      None if source_compression == Compression::BI_RLE8 || source_compression == Compression::BI_RLE4 => SaveCompression::Rle,
+      None => SaveCompression::Rgb,
+    };
+    let bitcount = format.bitcount();
+    let masks: [u32; 4] = layout.masks;
+    //work out the biCompression value, and check the combination can be written
+    let compression_code: Compression;
+    match compression {
+      SaveCompression::Rgb => {
+        if needs_masks {
+          return Err(Error::Unsupported("BI_RGB can't store these bit masks"));
+        }
+        compression_code = Compression::BI_RGB;
+      },
+      SaveCompression::Bitfields => {
+        if bitcount != 16 && bitcount != 32 {
+          return Err(Error::Unsupported("BI_BITFIELDS is only for 16 and 32 bit images"));
+        }
+        //BITMAPINFOHEADER only has room for 3 masks after it, unless BI_ALPHABITFIELDS is used
+        compression_code = if header == HeaderVersion::Info && masks[3] != 0 { Compression::BI_ALPHABITFIELDS } else { Compression::BI_BITFIELDS };
+      },
+      SaveCompression::Rle => {
+        //rle bitmaps have to be bottom up
+        if top_down {
+          return Err(Error::Unsupported("RLE bitmaps have to be bottom up"));
+        }
+        compression_code = match bitcount {
+          8 => Compression::BI_RLE8,
+          4 => Compression::BI_RLE4,
+          _ => return Err(Error::Unsupported("RLE is only for 4 and 8 bit images")),
+        };
+      },
+    }
+    if header == HeaderVersion::Core {
+      //OS/2 1.x style, no compression, no negative height and 16 bit dimensions
+      if compression_code != Compression::BI_RGB || top_down || ![1, 4, 8, 24].contains(&bitcount) || layout.width > u16::MAX as usize || layout.height > u16::MAX as usize {
+        return Err(Error::Unsupported("core headers only hold uncompressed bottom up 1, 4, 8 or 24 bit images up to 65535 pixels wide and high"));
+      }
+    } else if layout.width > i32::MAX as usize || layout.height > i32::MAX as usize {
+      return Err(Error::Overflow { field: "biWidth or biHeight" });
+    }
+    //pixel array
+    let image: Vec<u8>;
+    if compression == SaveCompression::Rle {
+      let mut indexes: Vec<u8> = Vec::with_capacity(layout.width*layout.height);
+      //rle rows are always stored bottom up
+      for y in (0..layout.height).rev() {
+        let row_start = layout.row_start(y);
+        for x in 0..layout.width {
+          indexes.push(layout.read_raw(|i| pixels[i], row_start, x) as u8);
+        }
+      }
+      image = BMP::encode_rle(&indexes, layout.width, layout.height, bitcount);
+    } else {
+      let row_length = match BMP::get_row_length(bitcount, layout.width as u32) {
+        Ok(returned_row_length) => returned_row_length,
+        Err(e) => return Err(e),
+      };
+      let image_size = match row_length.checked_mul(layout.height) {
+        Some(image_size) => image_size,
+        None => return Err(Error::Overflow { field: "biSizeImage" }),
+      };
+      let mut new_image: Vec<u8> = vec![0; image_size];
+      let new_layout = PixelLayout { offset: 0, width: layout.width, height: layout.height, top_down, bitcount, row_length, masks, palette: Vec::new() };
+      let cells = Cell::from_mut(&mut new_image[..]).as_slice_of_cells();
+      for y in 0..layout.height {
+        let row_start = layout.row_start(y);
+        let new_row_start = new_layout.row_start(y);
+        for x in 0..layout.width {
+          //same format, so the stored value can be copied as is
+          let raw = layout.read_raw(|i| pixels[i], row_start, x);
+          new_layout.write_raw(|i| cells[i].get(), |i, byte| cells[i].set(byte), new_row_start, x, raw);
+        }
+      }
+      image = new_image;
+    }
+    //headers
+    let dib_size: u32 = match header {
+      HeaderVersion::Core => 12,
+      HeaderVersion::Info => 40,
+      HeaderVersion::V4 => 108,
+      HeaderVersion::V5 => 124,
+    };
+    let extra_masks_size: u32 = match (header, compression_code) {
+      (HeaderVersion::Info, Compression::BI_BITFIELDS) => 12,
+      (HeaderVersion::Info, Compression::BI_ALPHABITFIELDS) => 16,
+      _ => 0,
+    };
+    let entry_size: u32 = if header == HeaderVersion::Core { 3 } else { 4 };
+    let palette_length = palette.as_ref().map_or(0, |palette| palette.len() as u32);
+    let offset = HEADER_OFFSET as u32+dib_size+extra_masks_size+palette_length*entry_size;
+    let (x_pels_per_meter, y_pels_per_meter) = options.resolution.unwrap_or((dib_header.XPelsPerMeter.unwrap_or(2835), dib_header.YPelsPerMeter.unwrap_or(2835)));
+    let mut color_space = match options.color_space {
+      Some(color_space) => color_space,
+      None => match self.get_color_space_info() {
+        Ok(color_space) => color_space,
+        Err(e) => return Err(e),
+      },
+    };
+    //profile data (an ICC profile, or a linked profile's file name) only fits in a V5 header
+    let mut profile: Option<Vec<u8>> = None;
+    if let Some(icc_profile) = &options.icc_profile {
+      if header != HeaderVersion::V5 {
+        return Err(Error::Unsupported("ICC profiles can only be embedded in V5 headers"));
+      }
+      match IccProfile::parse(icc_profile) {
+        Ok(_) => {},
+        Err(e) => return Err(e),
+      };
+      color_space.cs_type = CSType::PROFILE_EMBEDDED;
+      profile = Some(icc_profile.clone());
+    } else if header == HeaderVersion::V5 {
+      //keep the image's own
+      if let Ok((cs_type, start, end)) = self.get_profile_data_position() {
+        if cs_type == color_space.cs_type {
+          profile = Some(self.contents[start..end].to_vec());
+        }
+      }
+    }
+    //without profile data, PROFILE_LINKED and PROFILE_EMBEDDED fall back to LCS_sRGB
+    if profile.is_none() && (color_space.cs_type == CSType::PROFILE_LINKED || color_space.cs_type == CSType::PROFILE_EMBEDDED) {
+      color_space.cs_type = CSType::LCS_sRGB;
+    }
+    let profile_length = profile.as_ref().map_or(0, |profile| profile.len());
+    let file_size = match (offset as usize).checked_add(image.len()).and_then(|size| size.checked_add(profile_length)) {
+      Some(file_size) if file_size <= u32::MAX as usize => file_size,
+      _ => return Err(Error::Overflow { field: "bfSize" }),
+    };
+    let mut contents: Vec<u8> = Vec::with_capacity(file_size);
+    //file header
+    contents.extend_from_slice(b"BM");
+    contents.extend_from_slice(&(file_size as u32).to_le_bytes());
+    contents.extend_from_slice(&[0; 4]);
+    contents.extend_from_slice(&offset.to_le_bytes());
+    //dib header
+    contents.extend_from_slice(&dib_size.to_le_bytes());
+    if header == HeaderVersion::Core {
+      contents.extend_from_slice(&(layout.width as u16).to_le_bytes());
+      contents.extend_from_slice(&(layout.height as u16).to_le_bytes());
+      contents.extend_from_slice(&1u16.to_le_bytes());
+      contents.extend_from_slice(&bitcount.to_le_bytes());
+    } else {
+      let height: i32 = if top_down { -(layout.height as i32) } else { layout.height as i32 };
+      contents.extend_from_slice(&(layout.width as i32).to_le_bytes());
+      contents.extend_from_slice(&height.to_le_bytes());
+      contents.extend_from_slice(&1u16.to_le_bytes());
+      contents.extend_from_slice(&bitcount.to_le_bytes());
+      contents.extend_from_slice(&compression_code.to_u32().to_le_bytes());
+      contents.extend_from_slice(&(image.len() as u32).to_le_bytes());
+      contents.extend_from_slice(&x_pels_per_meter.to_le_bytes());
+      contents.extend_from_slice(&y_pels_per_meter.to_le_bytes());
+      contents.extend_from_slice(&palette_length.to_le_bytes());
+      contents.extend_from_slice(&palette.as_ref().map_or(0, |palette| palette.important).to_le_bytes());
+    }
+    if header == HeaderVersion::V4 || header == HeaderVersion::V5 {
+      //masks are only meaningful with BI_BITFIELDS
+      let header_masks: [u32; 4] = if compression_code == Compression::BI_BITFIELDS { masks } else { [0; 4] };
+      for mask in header_masks {
+        contents.extend_from_slice(&mask.to_le_bytes());
+      }
+      contents.extend_from_slice(&color_space.cs_type.to_u32().to_le_bytes());
+      for endpoint in color_space.endpoints {
+        for coordinate in endpoint {
+          contents.extend_from_slice(&coordinate.to_le_bytes());
+        }
+      }
+      for gamma in color_space.gamma {
+        contents.extend_from_slice(&gamma.to_le_bytes());
+      }
+    }
+    if header == HeaderVersion::V5 {
+      contents.extend_from_slice(&color_space.intent.to_u32().to_le_bytes());
+      //ProfileData (from the start of the dib header), ProfileSize, Reserved. the profile goes after the pixel array
+      let profile_data: u32 = if profile.is_some() { offset+image.len() as u32-HEADER_OFFSET as u32 } else { 0 };
+      contents.extend_from_slice(&profile_data.to_le_bytes());
+      contents.extend_from_slice(&(profile_length as u32).to_le_bytes());
+      contents.extend_from_slice(&[0; 4]);
+    }
+    //extra bit masks
+    if extra_masks_size > 0 {
+      for mask in &masks[..extra_masks_size as usize/4] {
+        contents.extend_from_slice(&mask.to_le_bytes());
+      }
+    }
+    //color table
+    if let Some(palette) = &palette {
+      for color in &palette.colors {
+        contents.extend_from_slice(&[color[2], color[1], color[0]]);
+        if entry_size == 4 {
+          contents.push(0);
+        }
+      }
+    }
+    contents.extend_from_slice(&image);
+    if let Some(profile) = &profile {
+      contents.extend_from_slice(profile);
+    }
+    return Ok(contents);
+  }
+  //https://docs.microsoft.com/en-us/windows/win32/gdi/bitmap-compression
+  //indexes are one byte per pixel, rows bottom up
+  fn encode_rle(indexes: &[u8], width: usize, height: usize, bitcount: u16) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    for y in 0..height {
+      let row = &indexes[y*width..(y+1)*width];
+      //how many times the pixel at i repeats, up to 255
+      let run_at = |i: usize| row[i..].iter().take(255).take_while(|index| **index == row[i]).count();
+      let mut x: usize = 0;
+      while x < width {
+        let run = run_at(x);
+        if run >= 3 {
+          data.push(run as u8);
+          data.push(if bitcount == 4 { (row[x] << 4) | row[x] } else { row[x] });
+          x += run;
+          continue;
+        }
+        //collect pixels that don't repeat enough, until a run starts
+        let mut end = x;
+        while end < width && end-x < 255 && run_at(end) < 3 {
+          end += 1;
+        }
+        let literal = &row[x..end];
+        if literal.len() >= 3 {
+          //absolute mode, padded to 2 bytes
+          data.push(0);
+          data.push(literal.len() as u8);
+          let start = data.len();
+          if bitcount == 4 {
+            for pair in literal.chunks(2) {
+              data.push((pair[0] << 4) | pair.get(1).copied().unwrap_or(0));
+            }
+          } else {
+            data.extend_from_slice(literal);
+          }
+          if (data.len()-start) % 2 == 1 {
+            data.push(0);
+          }
+        } else {
+          for index in literal {
+            data.push(1);
+            data.push(if bitcount == 4 { index << 4 } else { *index });
+          }
+        }
+        x = end;
+      }
+      //end of line
+      data.extend_from_slice(&[0, 0]);
+    }
+    //end of bitmap
+    data.extend_from_slice(&[0, 1]);
+    return data;
+  }
+}
+
+//iterators
+//yields rgba colors
+pub struct Pixels<'a> {
+  inner: EnumeratePixels<'a>,
+}
+
+impl Iterator for Pixels<'_> {
+  type Item = [u8; 4];
+  fn next(&mut self) -> Option<[u8; 4]> {
+    return self.inner.next().map(|(_, _, color)| color);
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    return self.inner.size_hint();
+  }
+}
+
+impl ExactSizeIterator for Pixels<'_> {}
+
+//yields (x, y, rgba color)
+pub struct EnumeratePixels<'a> {
+  contents: PixelData<'a>,
+  layout: PixelLayout,
+  x: usize,
+  y: usize,
+}
+
+impl Iterator for EnumeratePixels<'_> {
+  type Item = (usize, usize, [u8; 4]);
+  fn next(&mut self) -> Option<(usize, usize, [u8; 4])> {
+    if self.layout.width == 0 || self.y >= self.layout.height {
+      return None;
+    }
+    let (x, y) = (self.x, self.y);
+    let color = self.layout.get(&self.contents, x, y);
+    self.x += 1;
+    if self.x == self.layout.width {
+      self.x = 0;
+      self.y += 1;
+    }
+    return Some((x, y, color));
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = if self.layout.width == 0 || self.y >= self.layout.height { 0 } else { (self.layout.height-self.y)*self.layout.width-self.x };
+    return (remaining, Some(remaining));
+  }
+}
+
+impl ExactSizeIterator for EnumeratePixels<'_> {}
+
+//yields one Row per line of the image, top first
+pub struct Rows<'a> {
+  contents: Rc<PixelData<'a>>,
+  layout: Rc<PixelLayout>,
+  y: usize,
+}
+
+impl<'a> Iterator for Rows<'a> {
+  type Item = Row<'a>;
+  fn next(&mut self) -> Option<Row<'a>> {
+    if self.y >= self.layout.height {
+      return None;
+    }
+    let row = Row { y: self.y, start: self.layout.row_start(self.y), contents: Rc::clone(&self.contents), layout: Rc::clone(&self.layout) };
+    self.y += 1;
+    return Some(row);
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = self.layout.height.saturating_sub(self.y);
+    return (remaining, Some(remaining));
+  }
+}
+
+impl ExactSizeIterator for Rows<'_> {}
+
+//a view of a single row of pixels
+pub struct Row<'a> {
+  pub y: usize,
+  start: usize,
+  contents: Rc<PixelData<'a>>,
+  layout: Rc<PixelLayout>,
+}
+
+impl Row<'_> {
+  pub fn len(&self) -> usize {
+    return self.layout.width;
+  }
+  pub fn is_empty(&self) -> bool {
+    return self.layout.width == 0;
+  }
+  pub fn get(&self, x: usize) -> Option<[u8; 4]> {
+    if x >= self.layout.width {
+      return None;
+    }
+    return Some(self.layout.raw_to_rgba(self.layout.read_raw(|i| self.contents[i], self.start, x)));
+  }
+  //raw bytes of the row, including padding. for RLE images these are the decoded indexes
+  pub fn as_bytes(&self) -> &[u8] {
+    return &self.contents[self.start..self.start+self.layout.row_length];
+  }
+  pub fn iter(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+    return (0..self.layout.width).map(move |x| self.layout.raw_to_rgba(self.layout.read_raw(|i| self.contents[i], self.start, x)));
+  }
+}
+
+//yields a PixelMut for every pixel, which can be read and changed
+pub struct PixelsMut<'a> {
+  contents: &'a [Cell<u8>],
+  layout: Rc<PixelLayout>,
+  x: usize,
+  y: usize,
+}
+
+impl<'a> Iterator for PixelsMut<'a> {
+  type Item = PixelMut<'a>;
+  fn next(&mut self) -> Option<PixelMut<'a>> {
+    if self.layout.width == 0 || self.y >= self.layout.height {
+      return None;
+    }
+    let pixel = PixelMut { x: self.x, y: self.y, row_start: self.layout.row_start(self.y), contents: self.contents, layout: Rc::clone(&self.layout) };
+    self.x += 1;
+    if self.x == self.layout.width {
+      self.x = 0;
+      self.y += 1;
+    }
+    return Some(pixel);
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = if self.layout.width == 0 || self.y >= self.layout.height { 0 } else { (self.layout.height-self.y)*self.layout.width-self.x };
+    return (remaining, Some(remaining));
+  }
+}
+
+impl ExactSizeIterator for PixelsMut<'_> {}
+
+pub struct PixelMut<'a> {
+  pub x: usize,
+  pub y: usize,
+  row_start: usize,
+  contents: &'a [Cell<u8>],
+  layout: Rc<PixelLayout>,
+}
+
+impl PixelMut<'_> {
+  //rgba
+  pub fn get(&self) -> [u8; 4] {
+    return self.layout.raw_to_rgba(self.layout.read_raw(|i| self.contents[i].get(), self.row_start, self.x));
+  }
+  //rgba. for images with a color table, the closest color in the table is used
+  pub fn set(&self, color: [u8; 4]) {
+    let raw = self.layout.rgba_to_raw(color);
+    self.layout.write_raw(|i| self.contents[i].get(), |i, byte| self.contents[i].set(byte), self.row_start, self.x, raw);
+  }
+}
+
+//https://docs.microsoft.com/en-us/windows/win32/wcs/basic-color-management-concepts
+
+/*RGB to written color hash table*/
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //width*height pixels using the given amount of colors, with an alpha ramp if has_alpha
+  fn test_rgba(width: usize, height: usize, colors: usize, has_alpha: bool) -> Vec<u8> {
+    let mut rgba: Vec<u8> = Vec::with_capacity(width*height*4);
+    for y in 0..height {
+      for x in 0..width {
+        let color = (x*3+y*5) % colors;
+        let alpha = if has_alpha { (x*255/width) as u8 } else { 255 };
+        rgba.extend_from_slice(&[(color*37) as u8, (color*91+20) as u8, (color*13+5) as u8, alpha]);
+      }
+    }
+    rgba
+  }
+
+  #[test]
+  fn png_round_trip() {
+    let formats = [
+      PixelFormat::Indexed1,
+      PixelFormat::Indexed2,
+      PixelFormat::Indexed4,
+      PixelFormat::Indexed8,
+      PixelFormat::Rgb555,
+      PixelFormat::Rgb565,
+      PixelFormat::Rgb24,
+      PixelFormat::Bgrx32,
+      PixelFormat::Bgra32,
+      PixelFormat::Bitfields32 { red: 0x3ff00000, green: 0x000ffc00, blue: 0x000003ff, alpha: 0xc0000000 },
+      PixelFormat::Bitfields32 { red: 0x000000ff, green: 0x0000ff00, blue: 0x00ff0000, alpha: 0 },
+    ];
+    let (width, height) = (13, 7);
+    for format in formats {
+      let colors = format.max_colors().unwrap_or(256).min(200);
+      let has_alpha = matches!(format, PixelFormat::Bgra32 | PixelFormat::Bitfields32 { alpha: 1.., .. });
+      let bmp = BMP::from_rgba8(width, height, &test_rgba(width as usize, height as usize, colors, has_alpha), format).unwrap();
+      let from_png = BMP::from_png(&bmp.encode_png().unwrap()).unwrap();
+      assert_eq!(from_png.to_rgba8().unwrap(), bmp.to_rgba8().unwrap(), "{:?}", format);
+      //indexed images stay indexed with the same bit count
+      if format.max_colors().is_some() {
+        assert_eq!(from_png.get_pixel_format().unwrap(), format);
+      }
+    }
+  }
+
+  fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i+2], 16).unwrap()).collect()
+  }
+
+  //(color type, bit depth, interlaced, file) of 5x3 PNGs made with python's zlib, every row with the next filter type
+  //sample i (counting samples, not pixels, from the top left) is (i*73+11) % levels, times 257 for 16 bit
+  //indexed images have min(2^depth, 16) palette entries, entry k is [k, 255-k, k*7]
+  const PNG_FIXTURES: [(u8, u8, bool, &str); 30] = [
+    (0, 1, false, "89504e470d0a1a0a0000000d4948445200000005000000030100000000734df8550000000e4944415478da6358c118c0140100049e0154f7ec57d30000000049454e44ae426082"),
+    (0, 1, true, "89504e470d0a1a0a0000000d4948445200000005000000030100000001044ac8c3000000164944415478da6368606c606a607ec0c2c0c0c0180000175002bc017ebbde0000000049454e44ae426082"),
+    (0, 2, false, "89504e470d0a1a0a0000000d494844520000000500000003020000000034ed8285000000114944415478da6338768051fa2953a003001082031bbb6704d40000000049454e44ae426082"),
+    (0, 2, true, "89504e470d0a1a0a0000000d494844520000000500000003020000000143eab213000000174944415478da6338c07880c981b9844581a18151fa29001de703e0ce30809c0000000049454e44ae426082"),
+    (0, 4, false, "89504e470d0a1a0a0000000d4948445200000005000000030400000000bbad7725000000144944415478da63d872ed0363a3922cd3ddb31700228905b80c3dc7670000000049454e44ae426082"),
+    (0, 4, true, "89504e470d0a1a0a0000000d4948445200000005000000030400000001ccaa47b3000000194944415478da63d8c0f881e90273782a8b1bc303c6462559003180051e75054c9f0000000049454e44ae426082"),
+    (0, 8, false, "89504e470d0a1a0a0000000d49484452000000050000000308000000007e5d9a24000000164944415478da63e00e99fb4c9fb1c213089872410000338c05d2e58181620000000049454e44ae426082"),
+    (0, 8, true, "89504e470d0a1a0a0000000d4948445200000005000000030800000001095aaab20000001d4944415478da63e066d4679acbfc94f51c4bc82406bd038c159e4000003cde060bc0fc6e8a0000000049454e44ae426082"),
+    (0, 16, false, "89504e470d0a1a0a0000000d49484452000000050000000310000000002ecd46670000001c4944415478da63e0e60e09993bf7d9337d7dc68a0a4f2860ca850300bc8b0ba090d34b4e0000000049454e44ae426082"),
+    (0, 16, true, "89504e470d0a1a0a0000000d494844520000000500000003100000000159ca76f1000000284944415478da63e0e666d4d7679a3b97f9e95356d673e7584242264d62d0d33b7080b1a2c2130a00d4db0c0a601dc5870000000049454e44ae426082"),
+    (4, 8, false, "89504e470d0a1a0a0000000d4948445200000005000000030804000000f13f0d730000001d4944415478da63e00e99fb4cbfe22057f01cc6a77a93a080e9161c00000077126e6ffaab7b0000000049454e44ae426082"),
+    (4, 8, true, "89504e470d0a1a0a0000000d494844520000000500000003080400000186383de5000000284944415478da63e00e610c9ec3a45fc1bc9fa345639a15cbdc672a2a0c81b34af7313ed59b040500d4fa0dd03a5984470000000049454e44ae426082"),
+    (4, 16, false, "89504e470d0a1a0a0000000d4948445200000005000000031004000000a1afd1300000002a4944415478da63e0e60e09993bf7d9337dfd8a8a8307b9b88283e7cc617cfa544f6f121a60ba85050000d27024d8a506e7380000000049454e44ae426082"),
+    (4, 16, true, "89504e470d0a1a0a0000000d4948445200000005000000031004000001d6a8e1a60000003e4944415478da63e0e60e09610c0e9e3387495fbfa28279ff7e0e8e96160d8d69d3acac58e6ce7df64c050818020367cd2a2dddb78ff1e9533dbd4968000017b51b94c87d6d710000000049454e44ae426082"),
+    (3, 1, false, "89504e470d0a1a0a0000000d494844520000000500000003010300000061f857bb00000006504c544500ff0001fe077a9a4de40000000e4944415478da6358c118c0140100049e0154f7ec57d30000000049454e44ae426082"),
+    (3, 1, true, "89504e470d0a1a0a0000000d494844520000000500000003010300000116ff672d00000006504c544500ff0001fe077a9a4de4000000164944415478da6368606c606a607ec0c2c0c0c0180000175002bc017ebbde0000000049454e44ae426082"),
+    (3, 2, false, "89504e470d0a1a0a0000000d494844520000000500000003020300000026582d6b0000000c504c544500ff0001fe0702fd0e03fc15ddce3e5e000000114944415478da6338768051fa2953a003001082031bbb6704d40000000049454e44ae426082"),
+    (3, 2, true, "89504e470d0a1a0a0000000d4948445200000005000000030203000001515f1dfd0000000c504c544500ff0001fe0702fd0e03fc15ddce3e5e000000174944415478da6338c07880c981b9844581a18151fa29001de703e0ce30809c0000000049454e44ae426082"),
+    (3, 4, false, "89504e470d0a1a0a0000000d4948445200000005000000030403000000a918d8cb00000030504c544500ff0001fe0702fd0e03fc1504fb1c05fa2306f92a07f83108f73809f63f0af5460bf44d0cf3540df25b0ef1620ff069d0724434000000144944415478da63d872ed0363a3922cd3ddb31700228905b80c3dc7670000000049454e44ae426082"),
+    (3, 4, true, "89504e470d0a1a0a0000000d4948445200000005000000030403000001de1fe85d00000030504c544500ff0001fe0702fd0e03fc1504fb1c05fa2306f92a07f83108f73809f63f0af5460bf44d0cf3540df25b0ef1620ff069d0724434000000194944415478da63d8c0f881e90273782a8b1bc303c6462559003180051e75054c9f0000000049454e44ae426082"),
+    (3, 8, false, "89504e470d0a1a0a0000000d49484452000000050000000308030000006ce835ca00000030504c544500ff0001fe0702fd0e03fc1504fb1c05fa2306f92a07f83108f73809f63f0af5460bf44d0cf3540df25b0ef1620ff069d07244340000001a4944415478da63e066e165e367e4f8c9f99393e92fefdfbf7f01207c06429f99157d0000000049454e44ae426082"),
+    (3, 8, true, "89504e470d0a1a0a0000000d49484452000000050000000308030000011bef055c00000030504c544500ff0001fe0702fd0e03fc1504fb1c05fa2306f92a07f83108f73809f63f0af5460bf44d0cf3540df25b0ef1620ff069d07244340000001e4944415478da63e066e467e26566656563616162e06360e4f8c9f99313000b460263c7095c390000000049454e44ae426082"),
+    (2, 8, false, "89504e470d0a1a0a0000000d4948445200000005000000030802000000d45452af000000234944415478da63e00e99fb4cbfe22057f09ca77ae507381983663fb98d0498dc510100455416d87ac87ce90000000049454e44ae426082"),
+    (2, 8, true, "89504e470d0a1a0a0000000d4948445200000005000000030802000001a3536239000000344944415478da63e00e99cb587e8093e9205730f3cc47daccea67ee31a9b33cd3afd8b66d1b43c95e36ade23d8c41b39fdc4602000bad195da070fef90000000049454e44ae426082"),
+    (2, 16, false, "89504e470d0a1a0a0000000d494844520000000500000003100200000084c48eec000000364944415478da63e0e60e09993bf7d9337dfd8a8a8307b9b88283e7cc79fa544fafbcfcc0014e4ec6a0a0d9b39f3cb98d0330b9e30500cb132dacd38cc3450000000049454e44ae426082"),
+    (2, 16, true, "89504e470d0a1a0a0000000d4948445200000005000000031002000001f3c3be7a000000534944415478da63e0e60e09993b97b1bcfcc0014e4ea68307b9b882839967ce7cf4485b9b99595dfdcc997bf79898d4d5599e3dd3d7afa8d806060c25257bf7b2b169691517efd9c31814347bf69327b7710000c6be32aeeea256350000000049454e44ae426082"),
+    (6, 8, false, "89504e470d0a1a0a0000000d49484452000000050000000308060000005b36c5f8000000294944415478da63e00e99fb4cbfe22057f09ca77ae5073883663fd12d63dccf11384b050d306dc10200f1af1b108f9828ac0000000049454e44ae426082"),
+    (6, 8, true, "89504e470d0a1a0a0000000d49484452000000050000000308060000012c31f56e0000003e4944415478da63e00e99fb8c71f613dd32a6e0394ff5988bf7b0fa352df3ce5fe6953f9945bfe220970710304c7fa059745fa37017e37e8ec0592a680000527b1630c189b05a0000000049454e44ae426082"),
+    (6, 16, false, "89504e470d0a1a0a0000000d49484452000000050000000310060000000ba619bb000000434944415478da63e0e60e09993bf7d9337dfd8a8a8307b9b88283e7cc79fa544fafbcfcc0014ecea0a0d9b39f3cd1d52d2b63dcbf9f83233070d62c150280690b9100007ea4361c4ce3161f0000000049454e44ae426082"),
+    (6, 16, true, "89504e470d0a1a0a0000000d49484452000000050000000310060000017ca1292d000000684944415478da63e0e60e09993bf7d933c6d9b39f3cd1d52d2b630a0e9e33e7e9533d3de6e2e23d7b5859fdfc9a9a962df3f6cecf5fb6cccb2b3f7ff264167dfd8a8a8307b9b83ca08061faf4070f34358b8aeedfd7d0282cdcb58b71ff7e0e8ec0c059b354080000c0482c54f09c52610000000049454e44ae426082"),
+  ];
+
+  #[test]
+  fn from_png_fixtures() {
+    for (color_type, bit_depth, interlaced, hex) in PNG_FIXTURES {
+      let bmp = BMP::from_png(&from_hex(hex)).unwrap();
+      let channels: usize = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        _ => 4,
+      };
+      let levels: usize = if color_type == 3 { (1 << bit_depth).min(16) } else if bit_depth == 16 { 256 } else { 1 << bit_depth };
+      let sample = |i: usize| (i*73+11) % levels;
+      let to_8_bit = |i: usize| (sample(i)*255/(levels-1)) as u8;
+      let mut expected: Vec<u8> = Vec::with_capacity(5*3*4);
+      for pixel in 0..5*3 {
+        let i = pixel*channels;
+        expected.extend_from_slice(&match color_type {
+          0 => [to_8_bit(i), to_8_bit(i), to_8_bit(i), 255],
+          4 => [to_8_bit(i), to_8_bit(i), to_8_bit(i), to_8_bit(i+1)],
+          3 => [sample(i) as u8, 255-sample(i) as u8, (sample(i)*7) as u8, 255],
+          2 => [to_8_bit(i), to_8_bit(i+1), to_8_bit(i+2), 255],
+          _ => [to_8_bit(i), to_8_bit(i+1), to_8_bit(i+2), to_8_bit(i+3)],
+        });
+      }
+      let name = format!("color type {} at {} bits, interlaced: {}", color_type, bit_depth, interlaced);
+      assert_eq!(bmp.to_rgba8().unwrap(), (5, 3, expected), "{}", name);
+      //gray without alpha becomes a gray color table, 16 bit cut down to 8
+      let format = match (color_type, bit_depth) {
+        (0 | 3, 1) => PixelFormat::Indexed1,
+        (0 | 3, 2) => PixelFormat::Indexed2,
+        (0 | 3, 4) => PixelFormat::Indexed4,
+        (0 | 3, _) => PixelFormat::Indexed8,
+        (2, _) => PixelFormat::Rgb24,
+        _ => PixelFormat::Bgra32,
+      };
+      assert_eq!(bmp.get_pixel_format().unwrap(), format, "{}", name);
+    }
+  }
+
+  #[test]
+  fn decoded_pixels_are_cached() {
+    let (width, height) = (6, 4);
+    let indexed = BMP::from_rgba8(width, height, &test_rgba(width as usize, height as usize, 5, false), PixelFormat::Indexed8).unwrap();
+    let mut rle = BMP::from_bytes(indexed.encode(&SaveOptions::new().compression(SaveCompression::Rle)).unwrap());
+    let mut png = BMP::new_embedded(&indexed.encode_png().unwrap()).unwrap();
+    assert_eq!(rle.get_pixel_checked(1, 2).unwrap(), indexed.get_pixel_checked(1, 2).unwrap());
+    assert!(rle.decoded.lock().unwrap().is_some());
+    //changing contents directly, here the color table entry of the top left pixel, is seen by the next read
+    let entry = HEADER_OFFSET+40+rle.get_palette().unwrap().colors.iter().position(|color| color[..] == indexed.get_pixel_checked(0, 0).unwrap()[..3]).unwrap()*4;
+    rle.contents[entry..entry+3].copy_from_slice(&[1, 2, 3]);
+    assert_eq!(rle.get_pixel_checked(0, 0).unwrap(), [3, 2, 1, 255]);
+    assert_eq!(png.get_pixel_checked(4, 3).unwrap(), indexed.get_pixel_checked(4, 3).unwrap());
+    assert!(png.decoded.lock().unwrap().is_some());
+    assert_eq!(png.to_rgba8().unwrap(), indexed.to_rgba8().unwrap());
+    //a different image put in contents directly is decoded again
+    let flipped: Vec<u8> = test_rgba(width as usize, height as usize, 5, false).chunks(4).rev().flatten().copied().collect();
+    png.contents = BMP::new_embedded(&BMP::from_rgba8(width, height, &flipped, PixelFormat::Rgb24).unwrap().encode_png().unwrap()).unwrap().contents;
+    assert_eq!(png.to_rgba8().unwrap(), (width, height, flipped));
+  }
+
+  //a BI_BITFIELDS bitmap with an info header and 3 masks after it, rows of 16 bit pixels from the bottom
+  fn bitfields16(width: u32, rows: &[&[u16]], masks: [u32; 3]) -> BMP {
+    let row_length = (width as usize*2).div_ceil(4)*4;
+    let offset = 14+40+12;
+    let size = offset+row_length*rows.len();
+    let mut contents: Vec<u8> = Vec::with_capacity(size);
+    contents.extend_from_slice(b"BM");
+    contents.extend_from_slice(&(size as u32).to_le_bytes());
+    contents.extend_from_slice(&[0; 4]);
+    contents.extend_from_slice(&(offset as u32).to_le_bytes());
+    for int in [40, width, rows.len() as u32] {
+      contents.extend_from_slice(&int.to_le_bytes());
+    }
+    contents.extend_from_slice(&1u16.to_le_bytes());
+    contents.extend_from_slice(&16u16.to_le_bytes());
+    for int in [3, (row_length*rows.len()) as u32, 2835, 2835, 0, 0, masks[0], masks[1], masks[2]] {
+      contents.extend_from_slice(&int.to_le_bytes());
+    }
+    for row in rows.iter().rev() {
+      let start = contents.len();
+      for pixel in row.iter() {
+        contents.extend_from_slice(&pixel.to_le_bytes());
+      }
+      contents.resize(start+row_length, 0);
+    }
+    BMP::from_bytes(contents)
+  }
+
+  #[test]
+  fn masks_with_no_pixel_format() {
+    let rgb444 = bitfields16(3, &[&[0xf00, 0x0f0, 0x00f], &[0xfff, 0x000, 0x888]], [0xf00, 0x0f0, 0x00f]);
+    assert!(matches!(rgb444.get_pixel_format(), Err(Error::NoPixelFormat { bitcount: 16, masks: [0xf00, 0x0f0, 0x00f, 0] })));
+    let expected = rgb444.to_rgba8().unwrap();
+    assert_eq!(&expected.2[..8], &[255, 0, 0, 255, 0, 255, 0, 255]);
+    //written as 24 bit since there is no alpha mask
+    let encoded = BMP::from_bytes(rgb444.encode(&SaveOptions::new()).unwrap());
+    assert_eq!(encoded.get_pixel_format().unwrap(), PixelFormat::Rgb24);
+    assert_eq!(encoded.to_rgba8().unwrap(), expected);
+    assert_eq!(BMP::from_png(&rgb444.encode_png().unwrap()).unwrap().to_rgba8().unwrap(), expected);
+  }
+
+  #[test]
+  fn read_concatenated_bitmaps() {
+    let first = BMP::from_rgba8(3, 2, &test_rgba(3, 2, 6, false), PixelFormat::Rgb24).unwrap();
+    let second = BMP::from_rgba8(5, 1, &test_rgba(5, 1, 2, false), PixelFormat::Indexed1).unwrap();
+    let mut stream: Vec<u8> = Vec::new();
+    first.write_to(&mut stream).unwrap();
+    second.write_to(&mut stream).unwrap();
+    let mut reader = io::Cursor::new(stream);
+    assert_eq!(BMP::read_from(&mut reader).unwrap().contents, first.contents);
+    assert_eq!(BMP::read_from(&mut reader).unwrap().contents, second.contents);
+    assert!(matches!(BMP::read_from(&mut reader), Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+    //a bitmap cut short
+    let mut reader = io::Cursor::new(&first.contents[..first.contents.len()-1]);
+    assert!(matches!(BMP::read_from(&mut reader), Err(Error::Truncated { offset: 0, .. })));
+    assert!(matches!(BMP::read_from(io::Cursor::new(b"GIF89a\0\0\0\0\0\0\0\0")), Err(Error::WrongFileType)));
+  }
+
+  #[test]
+  fn read_to_end_without_a_size() {
+    //bfSize of 0, so everything after it is taken as part of the image
+    let mut unsized_bitmap = BMP::from_rgba8(2, 2, &test_rgba(2, 2, 4, false), PixelFormat::Rgb24).unwrap().contents;
+    unsized_bitmap[2..6].copy_from_slice(&[0; 4]);
+    let mut stream = unsized_bitmap.clone();
+    stream.extend_from_slice(b"trailing");
+    assert_eq!(BMP::read_from(io::Cursor::new(&stream)).unwrap().contents, stream);
+    //a bitmap array's bfSize is only the size of its first header
+    let mut array: Vec<u8> = b"BA".to_vec();
+    array.extend_from_slice(&14u32.to_le_bytes());
+    array.extend_from_slice(&[0; 8]);
+    array.extend_from_slice(&unsized_bitmap);
+    assert_eq!(BMP::read_from(io::Cursor::new(&array)).unwrap().contents, array);
+  }
+
+  #[test]
+  fn bottom_up_pixel_offsets() {
+    let (width, height) = (3, 4);
+    let mut bmp = BMP::from_rgba8(width, height, &vec![0; (width*height*4) as usize], PixelFormat::Rgb24).unwrap();
+    assert!(bmp.get_dib_header().unwrap().height > 0);
+    let offset = bmp.get_offset() as usize;
+    //3 pixels of 3 bytes, padded to 12
+    let row_length = 12;
+    bmp.change_color_of_pixel(2, 0, [10, 20, 30, 255]).unwrap();
+    bmp.change_color_of_pixel(2, height as usize-1, [40, 50, 60, 255]).unwrap();
+    //the top row is stored last and the bottom row first
+    assert_eq!(bmp.contents[offset+3*row_length+6..offset+3*row_length+9], [30, 20, 10]);
+    assert_eq!(bmp.contents[offset+6..offset+9], [60, 50, 40]);
+    assert_eq!(bmp.contents.iter().filter(|byte| **byte != 0).count(), bmp.contents[..offset].iter().filter(|byte| **byte != 0).count()+6);
+    assert_eq!(bmp.get_pixel_unchecked(2, 0), [10, 20, 30, 255]);
+    assert_eq!(bmp.get_pixel_unchecked(2, height as usize-1), [40, 50, 60, 255]);
+    assert_eq!(bmp.get_pixel_checked(2, height as usize-1).unwrap(), [40, 50, 60, 255]);
+  }
+
+  #[test]
+  fn unchecked_reads_match_checked_ones() {
+    let (width, height) = (7, 5);
+    let indexed = BMP::from_rgba8(width, height, &test_rgba(width as usize, height as usize, 12, false), PixelFormat::Indexed4).unwrap();
+    let rle = BMP::from_bytes(indexed.encode(&SaveOptions::new().compression(SaveCompression::Rle)).unwrap());
+    let rgb565 = BMP::from_rgba8(width, height, &test_rgba(width as usize, height as usize, 30, false), PixelFormat::Rgb565).unwrap();
+    for bmp in [&indexed, &rle, &rgb565] {
+      for (x, y, color) in bmp.enumerate_pixels().unwrap() {
+        assert_eq!(bmp.get_pixel_unchecked(x, y), color);
+        assert_eq!(bmp.get_pixel_checked(x, y).unwrap(), color);
+      }
+    }
+  }
+
+  //a bitmap with an OS/2 2.x header cut to header_size bytes, its bfOffBits counted from base
+  fn os2_bitmap(base: usize, header_size: u32, bitcount: u16, compression: u32, (width, height): (u32, i32), palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    let offset = base+14+header_size as usize+palette.len()*4;
+    let mut contents: Vec<u8> = b"BM".to_vec();
+    contents.extend_from_slice(&((offset-base+pixels.len()) as u32).to_le_bytes());
+    contents.extend_from_slice(&[0; 4]);
+    contents.extend_from_slice(&(offset as u32).to_le_bytes());
+    contents.extend_from_slice(&header_size.to_le_bytes());
+    contents.extend_from_slice(&width.to_le_bytes());
+    contents.extend_from_slice(&height.to_le_bytes());
+    contents.extend_from_slice(&1u16.to_le_bytes());
+    contents.extend_from_slice(&bitcount.to_le_bytes());
+    //16 byte headers end before biCompression
+    contents.extend_from_slice(&compression.to_le_bytes());
+    contents.resize(14+header_size as usize, 0);
+    for color in palette {
+      contents.extend_from_slice(&[color[2], color[1], color[0], 0]);
+    }
+    contents.extend_from_slice(pixels);
+    contents
+  }
+
+  #[test]
+  fn os2_header_sizes() {
+    //2x1, 24 bit, padded to 8 bytes
+    let pixels = [0, 0, 255, 255, 0, 0, 0, 0];
+    for size in [16, 20, 24, 36, 42, 64] {
+      let bmp = BMP::from_bytes(os2_bitmap(0, size, 24, 0, (2, 1), &[], &pixels));
+      let dib_header = bmp.get_dib_header().unwrap();
+      assert_eq!((dib_header.size, dib_header.width, dib_header.height, dib_header.bitcount), (size, 2, 1, 24));
+      //fields past the end of the header are missing
+      assert_eq!(dib_header.compression.is_some(), size >= 20, "{}", size);
+      assert_eq!(dib_header.Identifier.is_some(), size == 64, "{}", size);
+      assert_eq!(bmp.to_rgba8().unwrap(), (2, 1, vec![255, 0, 0, 255, 0, 0, 255, 255]), "{}", size);
+    }
+  }
+
+  #[test]
+  fn os2_huffman() {
+    //white 2, black 3, then white 0, black 5. the first row stored is the bottom one
+    let data = [0b01111000, 0b11010100, 0b11000000];
+    let mut contents = os2_bitmap(0, 64, 1, 3, (5, 2), &[[255, 255, 255], [0, 0, 0]], &data);
+    let bmp = BMP::from_bytes(contents.clone());
+    assert_eq!(bmp.get_dib_header().unwrap().compression, Some(Compression::BCA_HUFFMAN1D));
+    let colors: Vec<u8> = bmp.pixels().unwrap().map(|color| color[0]).collect();
+    assert_eq!(colors, vec![0, 0, 0, 0, 0, 255, 255, 0, 0, 0]);
+    //13 zeros after the first row is no code at all
+    let end = contents.len();
+    contents[end-2..].copy_from_slice(&[0, 0]);
+    let bmp = BMP::from_bytes(contents);
+    assert!(matches!(bmp.get_pixel_checked(0, 0), Err(Error::InvalidHuffmanCode { bit: 6 })));
+  }
+
+  #[test]
+  fn bitmap_arrays() {
+    let first = os2_bitmap(14, 16, 24, 0, (1, 1), &[], &[1, 2, 3, 0]);
+    let second_start = 14+first.len();
+    let mut second = os2_bitmap(second_start+14, 40, 8, 0, (2, 1), &[[9, 8, 7], [6, 5, 4]], &[1, 0, 0, 0]);
+    //biClrUsed, otherwise the color table is 256 entries
+    BMP::write_int(&mut second, 14+32, 2);
+    //BITMAPARRAYFILEHEADER: usType, cbSize, offNext, cxDisplay, cyDisplay
+    let array_header = |next: usize, display: u16| -> Vec<u8> {
+      let mut header: Vec<u8> = b"BA".to_vec();
+      header.extend_from_slice(&14u32.to_le_bytes());
+      header.extend_from_slice(&(next as u32).to_le_bytes());
+      header.extend_from_slice(&display.to_le_bytes());
+      header.extend_from_slice(&display.to_le_bytes());
+      header
+    };
+    let mut contents = array_header(second_start, 640);
+    contents.extend_from_slice(&first);
+    contents.extend_from_slice(&array_header(0, 1024));
+    contents.extend_from_slice(&second);
+    let bmp = BMP::from_bytes(contents.clone());
+    let entries = bmp.get_bitmap_array().unwrap();
+    assert_eq!(entries.iter().map(|entry| (entry.offset, entry.display_width, entry.width, entry.bitcount)).collect::<Vec<_>>(), vec![(0, 640, 1, 24), (second_start, 1024, 2, 8)]);
+    assert_eq!(bmp.extract_from_bitmap_array(0).unwrap().to_rgba8().unwrap(), (1, 1, vec![3, 2, 1, 255]));
+    assert_eq!(bmp.extract_from_bitmap_array(1).unwrap().to_rgba8().unwrap(), (2, 1, vec![6, 5, 4, 255, 9, 8, 7, 255]));
+    assert!(matches!(bmp.extract_from_bitmap_array(2), Err(Error::DoesNotExist(_))));
+    //offNext pointing back at itself would loop forever
+    BMP::write_int(&mut contents, second_start+6, second_start as u32);
+    assert!(matches!(BMP::from_bytes(contents).get_bitmap_array(), Err(Error::InvalidHeaderField { field: "offNext", .. })));
+  }
+
+  #[test]
+  fn calibrated_with_zero_endpoints() {
+    let rgba = test_rgba(3, 2, 6, false);
+    let bmp = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Rgb24).unwrap();
+    let mut bmp = BMP::from_bytes(bmp.encode(&SaveOptions::default().header(HeaderVersion::V4)).unwrap());
+    //LCS_CALIBRATED_RGB with the endpoints and gammas left as 0 is sRGB
+    BMP::write_int(&mut bmp.contents, HEADER_OFFSET+56, 0u32);
+    let info = bmp.get_color_space_info().unwrap();
+    assert_eq!((info.cs_type, info.endpoints, info.gamma), (CSType::LCS_CALIBRATED_RGB, [[0; 3]; 3], [0; 3]));
+    bmp.convert_to_srgb(&|_: &str| None).unwrap();
+    assert_eq!(bmp.to_rgba8().unwrap(), (3, 2, rgba));
+    assert_eq!(bmp.get_color_space_info().unwrap().cs_type, CSType::LCS_sRGB);
+  }
+
+  #[test]
+  fn iterator_row_order() {
+    let rgba = test_rgba(3, 3, 9, false);
+    let bottom_up = BMP::from_rgba8(3, 3, &rgba, PixelFormat::Rgb24).unwrap();
+    let top_down = BMP::from_bytes(bottom_up.encode(&SaveOptions::new().top_down(true)).unwrap());
+    assert!(bottom_up.get_dib_header().unwrap().height > 0);
+    assert!(top_down.get_dib_header().unwrap().height < 0);
+    for (bmp, first_stored_row) in [(&bottom_up, 2), (&top_down, 0)] {
+      assert_eq!(bmp.pixels().unwrap().flatten().collect::<Vec<u8>>(), rgba);
+      let coordinates = bmp.enumerate_pixels().unwrap().map(|(x, y, color)| {
+        assert_eq!(color, rgba[(y*3+x)*4..(y*3+x)*4+4]);
+        (x, y)
+      }).collect::<Vec<(usize, usize)>>();
+      assert_eq!(coordinates, vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1), (0, 2), (1, 2), (2, 2)]);
+      let offset = BMP::bytes_to_int(bmp.contents[10..14].try_into().unwrap()) as usize;
+      for row in bmp.rows().unwrap() {
+        assert_eq!(row.iter().flatten().collect::<Vec<u8>>(), rgba[row.y*12..row.y*12+12]);
+        //3 pixels and 3 bytes of padding
+        let stored_row = if first_stored_row == 0 { row.y } else { 2-row.y };
+        assert_eq!(row.as_bytes(), &bmp.contents[offset+stored_row*12..offset+stored_row*12+12]);
+      }
+      assert_eq!(bmp.rows().unwrap().map(|row| row.y).collect::<Vec<usize>>(), vec![0, 1, 2]);
+    }
+  }
+
+  #[test]
+  fn pixels_mut_changes_contents() {
+    let mut bmp = BMP::from_rgba8(3, 2, &test_rgba(3, 2, 6, false), PixelFormat::Rgb24).unwrap();
+    let offset = BMP::bytes_to_int(bmp.contents[10..14].try_into().unwrap()) as usize;
+    //read first, so the cached pixels have to follow the change
+    assert_eq!(bmp.pixels().unwrap().count(), 6);
+    for pixel in bmp.pixels_mut().unwrap() {
+      if (pixel.x, pixel.y) == (1, 0) {
+        pixel.set([1, 2, 3, 255]);
+      }
+    }
+    //the top row is stored last, 12 bytes per row, and pixels are bgr
+    assert_eq!(bmp.contents[offset+12+3..offset+12+6], [3, 2, 1]);
+    assert_eq!(bmp.get_pixel_checked(1, 0).unwrap(), [1, 2, 3, 255]);
+    assert_eq!(bmp.pixels().unwrap().nth(1).unwrap(), [1, 2, 3, 255]);
+    //4 bit: pixel (2, 1) takes the color of pixel (0, 1), both in the first stored row
+    let mut bmp = BMP::from_rgba8(3, 2, &test_rgba(3, 2, 4, false), PixelFormat::Indexed4).unwrap();
+    let offset = BMP::bytes_to_int(bmp.contents[10..14].try_into().unwrap()) as usize;
+    let nibbles = |contents: &[u8]| (contents[offset] >> 4, contents[offset+1] >> 4);
+    let (left, right) = nibbles(&bmp.contents);
+    assert_ne!(left, right);
+    let color = bmp.get_pixel_checked(0, 1).unwrap();
+    for pixel in bmp.pixels_mut().unwrap() {
+      if (pixel.x, pixel.y) == (2, 1) {
+        pixel.set(color);
+        assert_eq!(pixel.get(), color);
+      }
+    }
+    assert_eq!(nibbles(&bmp.contents), (left, left));
+    //the low nibble of the second byte is padding and stays 0
+    assert_eq!(bmp.contents[offset+1] & 0x0f, 0);
+    assert_eq!(bmp.pixels().unwrap().nth(5).unwrap(), color);
+  }
+
+  #[test]
+  fn rgba8_round_trip() {
+    //(format, bits per channel, alpha bits)
+    let formats = [
+      (PixelFormat::Indexed1, 8, 0),
+      (PixelFormat::Indexed2, 8, 0),
+      (PixelFormat::Indexed4, 8, 0),
+      (PixelFormat::Indexed8, 8, 0),
+      (PixelFormat::Rgb555, 5, 0),
+      (PixelFormat::Rgb565, 5, 0),
+      (PixelFormat::Rgb24, 8, 0),
+      (PixelFormat::Bgrx32, 8, 0),
+      (PixelFormat::Bgra32, 8, 8),
+      (PixelFormat::Bitfields32 { red: 0x3ff00000, green: 0x000ffc00, blue: 0x000003ff, alpha: 0xc0000000 }, 8, 2),
+      (PixelFormat::Bitfields32 { red: 0x000000ff, green: 0x0000ff00, blue: 0x00ff0000, alpha: 0 }, 8, 0),
+    ];
+    for (format, bits, alpha_bits) in formats {
+      let colors = format.max_colors().unwrap_or(7).min(7);
+      let rgba = test_rgba(5, 3, colors, alpha_bits > 0);
+      let (width, height, decoded) = BMP::from_rgba8(5, 3, &rgba, format).unwrap().to_rgba8().unwrap();
+      assert_eq!((width, height, decoded.len()), (5, 3, rgba.len()), "{:?}", format);
+      for (i, (value, expected)) in decoded.iter().zip(rgba.iter()).enumerate() {
+        let bits = if i % 4 == 3 { if alpha_bits == 0 { 8 } else { alpha_bits } } else { bits };
+        assert!((*value as i32-*expected as i32).abs() < 1 << (8-bits), "{:?} byte {}: {} {}", format, i, value, expected);
+      }
+      //anything lost is lost the first time
+      assert_eq!(BMP::from_rgba8(5, 3, &decoded, format).unwrap().to_rgba8().unwrap().2, decoded, "{:?}", format);
+    }
+  }
+
+  #[test]
+  fn rle_runs() {
+    let rle8: Vec<u8> = vec![
+      //3 5s, then 3 in absolute mode, which is padded to an even length
+      3, 5, 0, 3, 1, 2, 3, 0, 0, 0,
+      //skip 2 right, 2 7s
+      0, 2, 2, 0, 2, 7, 0, 0,
+      //a 4, then skip to the third pixel of the next row
+      1, 4, 0, 2, 1, 1, 1, 6,
+      0, 1,
+    ];
+    assert_eq!(BMP::decode_rle(&rle8, 6, 4, 8).unwrap(), vec![
+      5, 5, 5, 1, 2, 3,
+      0, 0, 7, 7, 0, 0,
+      4, 0, 0, 0, 0, 0,
+      0, 0, 6, 0, 0, 0,
+    ]);
+    let rle4: Vec<u8> = vec![
+      //the halves of the byte alternate, then 3 in absolute mode (2 bytes, no padding)
+      3, 0x12, 0, 3, 0x34, 0x50, 1, 0x60, 0, 0,
+      //5 in absolute mode, 3 bytes padded to 4, then skip a pixel
+      0, 5, 0x9a, 0xbc, 0xd0, 0, 0, 2, 1, 0, 1, 0xf0,
+      0, 1,
+    ];
+    assert_eq!(BMP::decode_rle(&rle4, 7, 2, 4).unwrap(), vec![
+      1, 2, 1, 3, 4, 5, 6,
+      9, 10, 11, 12, 13, 0, 15,
+    ]);
+    //runs past the end of a row are cut off, and a missing end of bitmap leaves the rest as 0
+    assert_eq!(BMP::decode_rle(&[4, 9, 0, 0, 0, 3, 1, 2], 2, 3, 8).unwrap(), vec![9, 9, 1, 2, 0, 0]);
+  }
+
+  //the smallest profile IccProfile::parse accepts, no tags
+  fn test_profile() -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0; 132];
+    bytes[0..4].copy_from_slice(&132u32.to_be_bytes());
+    bytes[36..40].copy_from_slice(b"acsp");
+    bytes
+  }
+
+  #[test]
+  fn set_palette_moves_pixels_and_profile() {
+    let rgba = test_rgba(5, 2, 4, false);
+    let mut bmp = BMP::from_bytes(BMP::from_rgba8(5, 2, &rgba, PixelFormat::Indexed8).unwrap().encode(&SaveOptions::new().header(HeaderVersion::V5)).unwrap());
+    bmp.set_color_profile(&test_profile()).unwrap();
+    let palette = bmp.get_palette().unwrap();
+    assert_eq!(palette.len(), 4);
+    let pixels = bmp.contents[bmp.get_offset() as usize..bmp.contents.len()-132].to_vec();
+    for (colors, important) in [(2, 1), (10, 0), (4, 0)] {
+      let old_offset = bmp.get_offset() as usize;
+      let old_profile_data = bmp.get_dib_header().unwrap().ProfileData.unwrap() as usize;
+      let old_colors = bmp.get_palette().unwrap().len();
+      let mut new_palette = Palette::new((0..colors).map(|i| palette.get(i).unwrap_or([i as u8, 0, 0])).collect());
+      new_palette.important = important;
+      bmp.set_palette(&new_palette).unwrap();
+      let moved = |old: usize| old+colors*4-old_colors*4;
+      assert_eq!(bmp.get_offset() as usize, moved(old_offset));
+      assert_eq!(bmp.get_size(true), bmp.contents.len());
+      let dib_header = bmp.get_dib_header().unwrap();
+      assert_eq!((dib_header.ClrUsed, dib_header.ClrImportant), (Some(colors as u32), Some(important)));
+      assert_eq!(dib_header.ProfileData.unwrap() as usize, moved(old_profile_data));
+      assert_eq!(dib_header.ProfileSize, Some(132));
+      assert_eq!(bmp.get_palette().unwrap(), new_palette);
+      assert_eq!(bmp.contents[bmp.get_offset() as usize..bmp.contents.len()-132], pixels);
+      assert_eq!(bmp.get_icc_profile().unwrap().bytes, test_profile());
+    }
+    assert_eq!(bmp.to_rgba8().unwrap().2, rgba);
+    assert!(matches!(bmp.set_palette(&Palette::new(vec![[0, 0, 0]; 257])), Err(Error::Unsupported(_))));
+  }
+
+  #[test]
+  fn compact_palette() {
+    let colors: [[u8; 4]; 3] = [[10, 20, 30, 255], [40, 50, 60, 255], [70, 80, 90, 255]];
+    let rgba: Vec<u8> = [0, 1, 2, 1, 2, 0, 1, 0].iter().flat_map(|i| colors[*i]).collect();
+    let mut bmp = BMP::from_rgba8(4, 2, &rgba, PixelFormat::Indexed4).unwrap();
+    let palette = bmp.get_palette().unwrap();
+    assert_eq!(palette.len(), 3);
+    //a copy of the first color, and one nothing uses
+    let mut padded = palette.clone();
+    padded.push(palette.get(0).unwrap());
+    padded.push([1, 2, 3]);
+    bmp.set_palette(&padded).unwrap();
+    //the top left pixel is the high half of the first byte of the last stored row
+    let offset = bmp.get_offset() as usize;
+    let index = bmp.contents[offset+4] >> 4;
+    assert_eq!(palette.get(index as usize), Some([10, 20, 30]));
+    bmp.contents[offset+4] = (bmp.contents[offset+4] & 0x0f) | (3+index) << 4;
+    assert_eq!(bmp.to_rgba8().unwrap().2, rgba);
+    bmp.compact_palette().unwrap();
+    assert_eq!(bmp.get_palette().unwrap(), palette);
+    assert_eq!(bmp.get_dib_header().unwrap().ClrUsed, Some(3));
+    assert_eq!(bmp.contents[bmp.get_offset() as usize+4] >> 4, index);
+    assert_eq!(bmp.to_rgba8().unwrap().2, rgba);
+  }
+
+  #[test]
+  fn sort_palette_remaps_indexes() {
+    //3 of the first color, 2 of the second, 1 of the third, and a 4th nothing uses
+    let colors: [[u8; 4]; 3] = [[200, 0, 0, 255], [0, 200, 0, 255], [0, 0, 200, 255]];
+    let rgba: Vec<u8> = [2, 0, 1, 0, 1, 0].iter().flat_map(|i| colors[*i]).collect();
+    for format in [PixelFormat::Indexed4, PixelFormat::Indexed8] {
+      for compression in [SaveCompression::Rgb, SaveCompression::Rle] {
+        let mut bmp = BMP::from_bytes(BMP::from_rgba8(3, 2, &rgba, format).unwrap().encode(&SaveOptions::new().compression(compression)).unwrap());
+        let mut palette = bmp.get_palette().unwrap();
+        assert_eq!(palette.len(), 3);
+        palette.push([9, 9, 9]);
+        //put the unused color first, which moves every pixel's index up by one
+        bmp.reorder_palette(&palette, &[3, 0, 1, 2], 0).unwrap();
+        assert_eq!(bmp.get_palette().unwrap().get(0), Some([9, 9, 9]));
+        assert_eq!(bmp.get_palette_usage().unwrap()[0], 0);
+        assert_eq!(bmp.to_rgba8().unwrap().2, rgba, "{:?} {:?}", format, compression);
+        bmp.sort_palette_by_usage().unwrap();
+        let sorted = bmp.get_palette().unwrap();
+        assert_eq!(sorted.colors, vec![[200, 0, 0], [0, 200, 0], [0, 0, 200], [9, 9, 9]]);
+        assert_eq!(sorted.important, 3);
+        assert_eq!(bmp.get_palette_usage().unwrap()[..4], [3, 2, 1, 0]);
+        assert_eq!(bmp.to_rgba8().unwrap().2, rgba, "{:?} {:?}", format, compression);
+        let expected_compression = if compression == SaveCompression::Rle { if format == PixelFormat::Indexed4 { Compression::BI_RLE4 } else { Compression::BI_RLE8 } } else { Compression::BI_RGB };
+        assert_eq!(bmp.get_dib_header().unwrap().compression, Some(expected_compression));
+        //and back the other way
+        bmp.sort_palette_by(|a, b| a.cmp(b)).unwrap();
+        assert_eq!(bmp.get_palette().unwrap().colors, vec![[0, 0, 200], [0, 200, 0], [9, 9, 9], [200, 0, 0]]);
+        assert_eq!(bmp.to_rgba8().unwrap().2, rgba, "{:?} {:?}", format, compression);
+      }
+    }
+  }
+
+  #[test]
+  fn core_header_palette() {
+    let rgba = test_rgba(3, 2, 5, false);
+    let info = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Indexed4).unwrap();
+    let mut core = BMP::from_bytes(info.encode(&SaveOptions::new().header(HeaderVersion::Core)).unwrap());
+    assert_eq!(core.get_dib_header().unwrap().size, 12);
+    //RGBTRIPLEs. only the colors in use are written, the table ends at bfOffBits
+    let palette = core.get_palette().unwrap();
+    assert_eq!(palette.colors, info.get_palette().unwrap().colors);
+    assert_eq!(core.get_offset() as usize, HEADER_OFFSET+12+palette.len()*3);
+    core.set_palette_entry(1, [1, 2, 3]).unwrap();
+    assert_eq!(core.contents[HEADER_OFFSET+12+3..HEADER_OFFSET+12+6], [3, 2, 1]);
+    assert!(matches!(core.set_palette_entry(palette.len(), [0, 0, 0]), Err(Error::DoesNotExist(_))));
+    let mut palette = core.get_palette().unwrap();
+    palette.set(1, [9, 8, 7]).unwrap();
+    palette.push([0, 0, 0]);
+    core.set_palette(&palette).unwrap();
+    //no biClrUsed to update, the table grows by one RGBTRIPLE
+    assert_eq!(core.get_offset() as usize, HEADER_OFFSET+12+palette.len()*3);
+    assert_eq!(core.get_size(true), core.contents.len());
+    assert_eq!(core.get_palette().unwrap(), palette);
+    let mut expected = rgba.clone();
+    let old = info.get_palette().unwrap().get(1).unwrap();
+    for pixel in expected.chunks_mut(4) {
+      if pixel[..3] == old {
+        pixel[..3].copy_from_slice(&[9, 8, 7]);
+      }
+    }
+    assert_eq!(core.to_rgba8().unwrap().2, expected);
+  }
+
+  #[test]
+  fn quantize_web_safe() {
+    let bmp = BMP::from_rgba8(4, 2, &test_rgba(4, 2, 8, false), PixelFormat::Rgb24).unwrap();
+    assert!(matches!(bmp.quantize(PixelFormat::Indexed4, &Quantizer::Fixed(quantize::FixedPalette::WebSafe)), Err(Error::Unsupported(_))));
+    let quantized = bmp.quantize(PixelFormat::Indexed8, &Quantizer::Fixed(quantize::FixedPalette::WebSafe)).unwrap();
+    assert_eq!(quantized.get_palette().unwrap().len(), 216);
+  }
+
+  #[test]
+  fn convert_between_every_format() {
+    let formats = [
+      PixelFormat::Indexed1,
+      PixelFormat::Indexed2,
+      PixelFormat::Indexed4,
+      PixelFormat::Indexed8,
+      PixelFormat::Rgb555,
+      PixelFormat::Rgb565,
+      PixelFormat::Rgb24,
+      PixelFormat::Bgrx32,
+      PixelFormat::Bgra32,
+      PixelFormat::Bitfields32 { red: 0x3ff00000, green: 0x000ffc00, blue: 0x000003ff, alpha: 0xc0000000 },
+      PixelFormat::Bitfields32 { red: 0x000000ff, green: 0x0000ff00, blue: 0x00ff0000, alpha: 0 },
+    ];
+    //2 colors every format can hold exactly, on an odd width so every row has padding
+    let colors: [[u8; 4]; 2] = [[0, 255, 0, 255], [255, 0, 255, 255]];
+    let rgba: Vec<u8> = (0..15).flat_map(|i| colors[(i*7/3) % 2]).collect();
+    for from in formats {
+      for to in formats {
+        let mut bmp = BMP::from_rgba8(5, 3, &rgba, from).unwrap();
+        BMP::write_int(&mut bmp.contents, HEADER_OFFSET+24, 2835u32);
+        BMP::write_int(&mut bmp.contents, HEADER_OFFSET+28, 3780u32);
+        bmp.convert(to).unwrap();
+        let converted = BMP::from_bytes(bmp.contents.clone());
+        let dib_header = converted.get_dib_header().unwrap();
+        assert_eq!(converted.get_pixel_format().unwrap(), to, "{:?} to {:?}", from, to);
+        assert_eq!((dib_header.width, dib_header.height, dib_header.bitcount), (5, 3, to.bitcount()));
+        assert_eq!((dib_header.XPelsPerMeter, dib_header.YPelsPerMeter), (Some(2835), Some(3780)));
+        let compression = dib_header.compression.unwrap();
+        assert_eq!(compression == Compression::BI_RGB, to.masks().is_none(), "{:?} to {:?}", from, to);
+        let masks_size = match compression {
+          Compression::BI_BITFIELDS if dib_header.size == 40 => 12,
+          Compression::BI_ALPHABITFIELDS if dib_header.size == 40 => 16,
+          _ => 0,
+        };
+        let palette_size = match to.max_colors() {
+          Some(max_colors) => {
+            let palette = converted.get_palette().unwrap();
+            assert!(palette.len() <= max_colors);
+            palette.len()*4
+          },
+          None => 0,
+        };
+        let offset = converted.get_offset() as usize;
+        assert_eq!(offset, HEADER_OFFSET+dib_header.size as usize+masks_size+palette_size, "{:?} to {:?}", from, to);
+        let row_length = BMP::get_row_length(to.bitcount(), 5).unwrap();
+        assert_eq!(converted.contents.len(), offset+row_length*3);
+        assert_eq!(converted.get_size(true), converted.contents.len());
+        //whole bytes past the last pixel are padding, and are 0
+        let used = (to.bitcount() as usize*5).div_ceil(8);
+        for row in converted.contents[offset..].chunks(row_length) {
+          assert!(row[used..].iter().all(|byte| *byte == 0), "{:?} to {:?}", from, to);
+        }
+        assert_eq!(converted.to_rgba8().unwrap(), (5, 3, rgba.clone()), "{:?} to {:?}", from, to);
+      }
+    }
+  }
+
+  #[test]
+  fn encode_headers() {
+    let rgba = test_rgba(5, 3, 7, false);
+    for format in [PixelFormat::Indexed4, PixelFormat::Indexed8, PixelFormat::Rgb24, PixelFormat::Bgra32] {
+      let bmp = BMP::from_rgba8(5, 3, &test_rgba(5, 3, 7, format == PixelFormat::Bgra32), format).unwrap();
+      let expected = bmp.to_rgba8().unwrap();
+      for (header, size) in [(HeaderVersion::Core, 12), (HeaderVersion::Info, 40), (HeaderVersion::V4, 108), (HeaderVersion::V5, 124)] {
+        for top_down in [false, true] {
+          let options = SaveOptions::new().header(header).top_down(top_down).resolution(1000, 2000);
+          if header == HeaderVersion::Core && (top_down || format == PixelFormat::Bgra32) {
+            assert!(matches!(bmp.encode(&options), Err(Error::Unsupported(_))), "{:?} {:?} {}", format, header, top_down);
+            continue;
+          }
+          let encoded = BMP::from_bytes(bmp.encode(&options).unwrap());
+          let dib_header = encoded.get_dib_header().unwrap();
+          assert_eq!(dib_header.size, size);
+          assert_eq!(dib_header.height < 0, top_down, "{:?} {:?} {}", format, header, top_down);
+          assert_eq!(encoded.get_pixel_format().unwrap(), format);
+          assert_eq!(encoded.to_rgba8().unwrap(), expected, "{:?} {:?} {}", format, header, top_down);
+          assert_eq!(encoded.get_size(true), encoded.contents.len());
+          if header == HeaderVersion::Core {
+            assert_eq!(dib_header.XPelsPerMeter, None);
+          } else {
+            assert_eq!((dib_header.XPelsPerMeter, dib_header.YPelsPerMeter), (Some(1000), Some(2000)));
+          }
+          //by default the header and row order are kept
+          assert_eq!(encoded.encode(&SaveOptions::new()).unwrap(), encoded.contents);
+        }
+      }
+    }
+    let bmp = BMP::from_rgba8(5, 3, &rgba, PixelFormat::Rgb24).unwrap();
+    //65536 pixels doesn't fit in a core header
+    let wide = BMP::from_rgba8(65536, 1, &vec![0; 65536*4], PixelFormat::Rgb24).unwrap();
+    assert!(matches!(wide.encode(&SaveOptions::new().header(HeaderVersion::Core)), Err(Error::Unsupported(_))));
+    assert!(matches!(bmp.encode(&SaveOptions::new().icc_profile(test_profile())), Err(Error::Unsupported(_))));
+  }
+
+  #[test]
+  fn encode_rle() {
+    //long runs, so RLE is smaller
+    let rgba: Vec<u8> = (0..40*4).flat_map(|i| if i % 40 < 25 { [10, 20, 30, 255] } else { [200, 100, 0, 255] }).collect();
+    for (format, compression) in [(PixelFormat::Indexed4, Compression::BI_RLE4), (PixelFormat::Indexed8, Compression::BI_RLE8)] {
+      let bmp = BMP::from_rgba8(40, 4, &rgba, format).unwrap();
+      let rle = BMP::from_bytes(bmp.encode(&SaveOptions::new().compression(SaveCompression::Rle)).unwrap());
+      assert_eq!(rle.get_dib_header().unwrap().compression, Some(compression));
+      assert!(rle.contents.len() < bmp.contents.len());
+      assert_eq!(rle.to_rgba8().unwrap().2, rgba);
+      assert_eq!(rle.get_palette().unwrap(), bmp.get_palette().unwrap());
+      //RLE stays RLE unless something else is asked for
+      assert_eq!(rle.encode(&SaveOptions::new()).unwrap(), rle.contents);
+      let uncompressed = BMP::from_bytes(rle.encode(&SaveOptions::new().compression(SaveCompression::Rgb)).unwrap());
+      assert_eq!(uncompressed.contents, bmp.contents);
+      assert!(matches!(bmp.encode(&SaveOptions::new().compression(SaveCompression::Rle).top_down(true)), Err(Error::Unsupported(_))));
+      assert!(matches!(bmp.encode(&SaveOptions::new().compression(SaveCompression::Rle).header(HeaderVersion::Core)), Err(Error::Unsupported(_))));
+    }
+    let rgb = BMP::from_rgba8(40, 4, &rgba, PixelFormat::Rgb24).unwrap();
+    assert!(matches!(rgb.encode(&SaveOptions::new().compression(SaveCompression::Rle)), Err(Error::Unsupported(_))));
+  }
+
+  #[test]
+  fn encode_masks() {
+    let rgba = test_rgba(3, 2, 5, true);
+    let bgra = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Bgra32).unwrap();
+    //a BITMAPINFOHEADER needs BI_ALPHABITFIELDS for the alpha mask, 16 bytes of masks after the header
+    let info = BMP::from_bytes(bgra.encode(&SaveOptions::new().header(HeaderVersion::Info)).unwrap());
+    assert_eq!(info.get_dib_header().unwrap().compression, Some(Compression::BI_ALPHABITFIELDS));
+    assert_eq!(info.get_offset() as usize, HEADER_OFFSET+40+16);
+    assert_eq!(info.contents[HEADER_OFFSET+40..HEADER_OFFSET+56], [0, 0, 0xff, 0, 0, 0xff, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0xff]);
+    assert_eq!(info.to_rgba8().unwrap().2, rgba);
+    //V4 and up have room for all 4 masks in the header
+    let v4 = BMP::from_bytes(bgra.encode(&SaveOptions::new().header(HeaderVersion::V4)).unwrap());
+    assert_eq!(v4.get_dib_header().unwrap().compression, Some(Compression::BI_BITFIELDS));
+    assert_eq!(v4.get_offset() as usize, HEADER_OFFSET+108);
+    assert_eq!(BMP::bytes_to_int(v4.contents[HEADER_OFFSET+52..HEADER_OFFSET+56].try_into().unwrap()), 0xff000000);
+    assert_eq!(v4.to_rgba8().unwrap().2, rgba);
+    //no alpha mask, so plain BI_BITFIELDS with 12 bytes of masks
+    let rgb565 = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Rgb565).unwrap();
+    let info = BMP::from_bytes(rgb565.encode(&SaveOptions::new().header(HeaderVersion::Info)).unwrap());
+    assert_eq!(info.get_dib_header().unwrap().compression, Some(Compression::BI_BITFIELDS));
+    assert_eq!(info.get_offset() as usize, HEADER_OFFSET+40+12);
+    assert_eq!(info.get_pixel_format().unwrap(), PixelFormat::Rgb565);
+    //default masks can be written either way
+    let rgb555 = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Rgb555).unwrap();
+    let bitfields = BMP::from_bytes(rgb555.encode(&SaveOptions::new().compression(SaveCompression::Bitfields)).unwrap());
+    assert_eq!(bitfields.get_dib_header().unwrap().compression, Some(Compression::BI_BITFIELDS));
+    assert_eq!(bitfields.to_rgba8().unwrap(), rgb555.to_rgba8().unwrap());
+    assert!(matches!(bgra.encode(&SaveOptions::new().compression(SaveCompression::Rgb)), Err(Error::Unsupported(_))));
+    let rgb24 = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Rgb24).unwrap();
+    assert!(matches!(rgb24.encode(&SaveOptions::new().compression(SaveCompression::Bitfields)), Err(Error::Unsupported(_))));
+  }
+
+  #[test]
+  fn encode_masks_with_no_pixel_format() {
+    //16 bit ARGB4444, which has no PixelFormat, as BI_ALPHABITFIELDS
+    let mut argb4444 = bitfields16(2, &[&[0xff00, 0x80f0]], [0x0f00, 0x00f0, 0x000f]);
+    let contents = &mut argb4444.contents;
+    BMP::write_int(contents, HEADER_OFFSET+16, 6u32);
+    contents.splice(HEADER_OFFSET+52..HEADER_OFFSET+52, 0xf000u32.to_le_bytes());
+    BMP::write_int(contents, 10, (HEADER_OFFSET+56) as u32);
+    let size = contents.len() as u32;
+    BMP::write_int(contents, 2, size);
+    assert!(matches!(argb4444.get_pixel_format(), Err(Error::NoPixelFormat { bitcount: 16, masks: [0x0f00, 0x00f0, 0x000f, 0xf000] })));
+    let expected = argb4444.to_rgba8().unwrap();
+    assert_eq!(expected.2, vec![255, 0, 0, 255, 0, 255, 0, 136]);
+    //written as 32 bit with alpha
+    let encoded = BMP::from_bytes(argb4444.encode(&SaveOptions::new()).unwrap());
+    assert_eq!(encoded.get_pixel_format().unwrap(), PixelFormat::Bgra32);
+    assert_eq!(encoded.to_rgba8().unwrap(), expected);
+  }
+
+  #[test]
+  fn save_replaces_atomically() {
+    let directory = std::env::temp_dir().join(format!("bmp-manipulation-save-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    let files = || {
+      let mut names: Vec<String> = fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+      names.sort();
+      names
+    };
+    let target = directory.join("image.bmp");
+    let target_path = target.to_str().unwrap();
+    fs::write(&target, b"old contents").unwrap();
+    let bmp = BMP::from_rgba8(3, 2, &test_rgba(3, 2, 4, false), PixelFormat::Rgb24).unwrap();
+    bmp.save(target_path).unwrap();
+    assert_eq!(fs::read(&target).unwrap(), bmp.contents);
+    assert_eq!(files(), vec!["image.bmp"]);
+    let opened = BMP::open(target_path).unwrap();
+    assert_eq!(opened.contents, bmp.contents);
+    bmp.save_to_new_with_options(target_path, &SaveOptions::new().top_down(true)).unwrap();
+    assert!(BMP::open(target_path).unwrap().get_dib_header().unwrap().height < 0);
+    assert_eq!(files(), vec!["image.bmp"]);
+    //a directory in the way makes the rename fail, and the temp file is removed
+    let blocked = directory.join("blocked.bmp");
+    fs::create_dir(&blocked).unwrap();
+    fs::write(blocked.join("inside"), b"").unwrap();
+    assert!(matches!(bmp.save(blocked.to_str().unwrap()), Err(Error::Io(_))));
+    assert_eq!(files(), vec!["blocked.bmp", "image.bmp"]);
+    assert!(matches!(bmp.save(directory.join("missing").join("image.bmp").to_str().unwrap()), Err(Error::Io(_))));
+    //open
+    match BMP::open(directory.join("missing.bmp").to_str().unwrap()) {
+      Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+      _ => panic!("opening a missing file should be an io error"),
+    }
+    fs::write(directory.join("text.txt"), b"not a bitmap").unwrap();
+    assert!(matches!(BMP::open(directory.join("text.txt").to_str().unwrap()), Err(Error::WrongFileType)));
+    fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn error_messages() {
+    let io = Error::Io(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+    assert_eq!(io.to_string(), "Could not read or write the file: no such file");
+    assert_eq!(std::error::Error::source(&io).unwrap().to_string(), "no such file");
+    let errors: [(Error, &str); 6] = [
+      (Error::Truncated { offset: 14, length: 40, file_size: 30 }, "File is truncated: expected 40 bytes at offset 14, but the file is only 30 bytes"),
+      (Error::InvalidHeaderField { field: "biBitCount", value: 7 }, "Invalid or unsupported value for biBitCount: 7"),
+      (Error::Overflow { field: "bfSize" }, "bfSize is too large"),
+      (Error::WrongDimensions { expected: 12, actual: 10 }, "Pixel data is 10 bytes, but the dimensions need 12"),
+      (Error::NoPixelFormat { bitcount: 16, masks: [0xf00, 0xf0, 0xf, 0] }, "16 bit pixels with masks [\n    0xf00,\n    0xf0,\n    0xf,\n    0x0,\n] have no PixelFormat"),
+      (Error::InvalidHuffmanCode { bit: 9 }, "Invalid huffman code at bit 9 of the pixel array"),
+    ];
+    for (error, message) in errors {
+      assert_eq!(error.to_string(), message);
+      //only io errors wrap another error
+      assert!(std::error::Error::source(&error).is_none());
+    }
+    let from: Error = io::Error::new(io::ErrorKind::UnexpectedEof, "eof").into();
+    assert!(matches!(from, Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+  }
+
+  #[test]
+  fn truncated_headers() {
+    let bmp = BMP::from_rgba8(3, 2, &test_rgba(3, 2, 4, false), PixelFormat::Rgb24).unwrap();
+    let truncated = |length: usize| BMP::from_bytes(bmp.contents[..length].to_vec());
+    assert!(matches!(truncated(1).get_signature(), Err(Error::Truncated { offset: 0, length: 2, file_size: 1 })));
+    assert!(matches!(truncated(16).get_dib_header(), Err(Error::Truncated { offset: 0, length: 18, file_size: 16 })));
+    //the dib header says 40 bytes, only 16 of them are there
+    assert!(matches!(truncated(30).get_dib_header(), Err(Error::Truncated { offset: 14, length: 40, file_size: 30 })));
+    //headers are all there, the last row isn't
+    let offset = bmp.get_offset() as usize;
+    assert!(matches!(truncated(offset+20).to_rgba8(), Err(Error::Truncated { offset: o, length: 24, file_size }) if o == offset && file_size == offset+20));
+    let mut contents = bmp.contents.clone();
+    BMP::write_int(&mut contents, HEADER_OFFSET, 200u32);
+    assert!(matches!(BMP::from_bytes(contents.clone()).get_dib_header(), Err(Error::InvalidHeaderField { field: "biSize", value: 200 })));
+    BMP::write_int(&mut contents, HEADER_OFFSET, 40u32);
+    contents[HEADER_OFFSET+14] = 7;
+    assert!(matches!(BMP::from_bytes(contents).to_rgba8(), Err(Error::InvalidHeaderField { field: "biBitCount", value: 7 })));
+  }
+
+  #[test]
+  fn compression_codes() {
+    let windows = [
+      Compression::BI_RGB, Compression::BI_RLE8, Compression::BI_RLE4, Compression::BI_BITFIELDS, Compression::BI_JPEG, Compression::BI_PNG, Compression::BI_ALPHABITFIELDS,
+      Compression::Unknown(7), Compression::Unknown(8), Compression::Unknown(9), Compression::Unknown(10),
+      Compression::BI_CMYK, Compression::BI_CMYKRLE8, Compression::BI_CMYKRLE4,
+    ];
+    for (code, compression) in windows.iter().enumerate() {
+      assert_eq!(Compression::from_u32(code as u32, false), *compression);
+      assert_eq!(compression.to_u32(), code as u32);
+    }
+    //3 and 4 mean something else in OS/2 2.x headers, the rest are the same
+    assert_eq!(Compression::from_u32(3, true), Compression::BCA_HUFFMAN1D);
+    assert_eq!(Compression::from_u32(4, true), Compression::BCA_RLE24);
+    assert_eq!((Compression::BCA_HUFFMAN1D.to_u32(), Compression::BCA_RLE24.to_u32()), (3, 4));
+    for code in [0, 1, 2, 5, 6, 11, 99] {
+      assert_eq!(Compression::from_u32(code, true), Compression::from_u32(code, false));
+    }
+    //read from the header, the size decides which one it is
+    let rle24 = BMP::from_bytes(os2_bitmap(0, 64, 24, 4, (2, 1), &[], &[2, 3, 2, 1, 0, 1]));
+    assert_eq!(rle24.get_dib_header().unwrap().compression, Some(Compression::BCA_RLE24));
+    assert_eq!(rle24.to_rgba8().unwrap().2, vec![1, 2, 3, 255, 1, 2, 3, 255]);
+    let jpeg = BMP::from_bytes(os2_bitmap(0, 40, 24, 4, (2, 1), &[], &[0; 8]));
+    assert_eq!(jpeg.get_dib_header().unwrap().compression, Some(Compression::BI_JPEG));
+    assert_eq!(Compression::from_u32(u32::MAX, false), Compression::Unknown(u32::MAX));
+    assert_eq!(Compression::Unknown(u32::MAX).to_u32(), u32::MAX);
+    assert_eq!(Compression::Unknown(42).to_string(), "unknown (42)");
+    assert_eq!(Compression::BI_RLE8.to_string(), "BI_RLE8");
+    //unknown codes are an error when reading pixels, not a panic
+    let mut bmp = BMP::from_rgba8(2, 2, &test_rgba(2, 2, 3, false), PixelFormat::Rgb24).unwrap();
+    BMP::write_int(&mut bmp.contents, HEADER_OFFSET+16, 42u32);
+    assert_eq!(bmp.get_dib_header().unwrap().compression, Some(Compression::Unknown(42)));
+    assert!(matches!(bmp.to_rgba8(), Err(Error::UnsupportedCompression(Compression::Unknown(42)))));
+    assert!(matches!(bmp.get_pixel_checked(0, 0), Err(Error::UnsupportedCompression(Compression::Unknown(42)))));
+  }
+
+  #[test]
+  fn signatures() {
+    let signatures = [
+      (b"BM", Signature::Bitmap),
+      (b"BA", Signature::BitmapArray),
+      (b"CI", Signature::ColorIcon),
+      (b"CP", Signature::ColorPointer),
+      (b"IC", Signature::Icon),
+      (b"PT", Signature::Pointer),
+    ];
+    let bmp = BMP::from_rgba8(2, 2, &test_rgba(2, 2, 3, false), PixelFormat::Rgb24).unwrap();
+    for (bytes, signature) in signatures {
+      assert_eq!(Signature::from_bytes(*bytes), Some(signature));
+      assert_eq!(signature.to_bytes(), *bytes);
+      let mut contents = bmp.contents.clone();
+      contents[0..2].copy_from_slice(bytes);
+      let bmp = BMP::from_bytes(contents.clone());
+      assert_eq!(bmp.get_signature().unwrap(), signature);
+      assert_eq!(BMP::read_from(&contents[..]).unwrap().contents, contents);
+    }
+    //icons and pointers have the same headers as a bitmap
+    let mut icon = bmp.contents.clone();
+    icon[0..2].copy_from_slice(b"IC");
+    assert_eq!(BMP::from_bytes(icon).to_rgba8().unwrap(), bmp.to_rgba8().unwrap());
+    for bytes in [b"bm", b"MB", b"GI", b"\x89P", b"\0\0"] {
+      assert_eq!(Signature::from_bytes(*bytes), None);
+      let mut contents = bmp.contents.clone();
+      contents[0..2].copy_from_slice(bytes);
+      assert!(matches!(BMP::from_bytes(contents.clone()).get_signature(), Err(Error::WrongFileType)));
+      assert!(matches!(BMP::from_bytes(contents.clone()).get_dib_header(), Err(Error::WrongFileType)));
+      assert!(matches!(BMP::read_from(&contents[..]), Err(Error::WrongFileType)));
+    }
+  }
+
+  #[test]
+  fn embedded_profiles() {
+    let rgba = test_rgba(3, 2, 4, false);
+    let info = BMP::from_rgba8(3, 2, &rgba, PixelFormat::Rgb24).unwrap();
+    let mut bmp = BMP::from_bytes(info.encode(&SaveOptions::new().header(HeaderVersion::V5)).unwrap());
+    assert!(matches!(bmp.get_icc_profile(), Err(Error::DoesNotExist(_))));
+    let pixels_end = bmp.contents.len();
+    bmp.set_color_profile(&test_profile()).unwrap();
+    let dib_header = bmp.get_dib_header().unwrap();
+    assert_eq!(dib_header.CSType, Some(CSType::PROFILE_EMBEDDED));
+    //ProfileData is from the start of the dib header
+    assert_eq!((dib_header.ProfileData, dib_header.ProfileSize), (Some((pixels_end-HEADER_OFFSET) as u32), Some(132)));
+    assert_eq!(bmp.get_size(true), pixels_end+132);
+    assert_eq!(bmp.get_icc_profile().unwrap().bytes, test_profile());
+    assert_eq!(bmp.load_color_profile(&|_: &str| None).unwrap().bytes, test_profile());
+    //replacing it takes the old one out first
+    let mut bigger = test_profile();
+    bigger.extend_from_slice(&[0; 8]);
+    bigger[0..4].copy_from_slice(&140u32.to_be_bytes());
+    bmp.set_color_profile(&bigger).unwrap();
+    let dib_header = bmp.get_dib_header().unwrap();
+    assert_eq!((dib_header.ProfileData, dib_header.ProfileSize), (Some((pixels_end-HEADER_OFFSET) as u32), Some(140)));
+    assert_eq!(bmp.contents.len(), pixels_end+140);
+    assert_eq!(bmp.get_icc_profile().unwrap().bytes, bigger);
+    assert_eq!(bmp.to_rgba8().unwrap().2, rgba);
+    //and it survives encoding, but not for headers that can't hold it
+    let encoded = BMP::from_bytes(bmp.encode(&SaveOptions::new()).unwrap());
+    assert_eq!(encoded.get_icc_profile().unwrap().bytes, bigger);
+    let v4 = BMP::from_bytes(bmp.encode(&SaveOptions::new().header(HeaderVersion::V4)).unwrap());
+    assert_eq!(v4.get_color_space_info().unwrap().cs_type, CSType::LCS_sRGB);
+    assert!(matches!(bmp.set_color_profile(&[0; 20]), Err(Error::InvalidProfile(_))));
+    assert_eq!(bmp.get_icc_profile().unwrap().bytes, bigger);
+    let mut info = info;
+    assert!(matches!(info.set_color_profile(&test_profile()), Err(Error::Unsupported(_))));
+  }
+
+  #[test]
+  fn fixed_point_endpoints_and_gamma() {
+    let xyz = |x: f64, y: f64, z: f64| CIEXYZ { x, y, z };
+    //FXPT2DOT30: 1 is 1 << 30, and the range is -2 up to just under 2
+    let known = CIEXYZTRIPLE::from_fxpt2dot30([[1 << 30, 1 << 29, 0], [-(1 << 30), i32::MIN, i32::MAX], [442811128, 1, -1]]);
+    assert_eq!((known.red, known.green.x, known.green.y), (xyz(1.0, 0.5, 0.0), -1.0, -2.0));
+    assert!((known.green.z-2.0).abs() < 1e-9 && known.green.z < 2.0);
+    assert!((known.blue.x-0.4124).abs() < 1e-9);
+    assert_eq!((known.blue.y, known.blue.z), (1.0/(1u64 << 30) as f64, -1.0/(1u64 << 30) as f64));
+    assert_eq!(known.to_fxpt2dot30().unwrap(), [[1 << 30, 1 << 29, 0], [-(1 << 30), i32::MIN, i32::MAX], [442811128, 1, -1]]);
+    let srgb = CIEXYZTRIPLE { red: xyz(0.4124, 0.2126, 0.0193), green: xyz(0.3576, 0.7152, 0.1192), blue: xyz(0.1805, 0.0722, 0.9505) };
+    let fixed = srgb.to_fxpt2dot30().unwrap();
+    assert_eq!((fixed[0][0], fixed[2][2]), (442811128, 1020591604));
+    let round_trip = CIEXYZTRIPLE::from_fxpt2dot30(fixed);
+    for (values, expected) in round_trip.to_array().iter().zip(srgb.to_array().iter()) {
+      for (value, expected) in values.iter().zip(expected.iter()) {
+        //within half a step
+        assert!((value-expected).abs() <= 0.5/(1u64 << 30) as f64);
+      }
+    }
+    assert_eq!(CIEXYZTRIPLE::from_fxpt2dot30(fixed).to_fxpt2dot30().unwrap(), fixed);
+    for out_of_range in [2.0, -2.000001] {
+      let endpoints = CIEXYZTRIPLE { red: xyz(out_of_range, 0.0, 0.0), ..srgb };
+      assert!(matches!(endpoints.to_fxpt2dot30(), Err(Error::Overflow { field: "endpoint" })));
+    }
+    //gamma is unsigned 16.16
+    let mut info = ColorSpaceInfo { cs_type: CSType::LCS_CALIBRATED_RGB, endpoints: [[0; 3]; 3], gamma: [0; 3], intent: Intent::LCS_GM_IMAGES };
+    info.set_gamma([1.0, 2.2, 1.0/65536.0]).unwrap();
+    assert_eq!(info.gamma, [65536, 144179, 1]);
+    assert_eq!(info.get_gamma()[0], 1.0);
+    assert!((info.get_gamma()[1]-2.2).abs() <= 0.5/65536.0);
+    info.gamma = [u32::MAX, 0, 0x8000];
+    assert_eq!(info.get_gamma(), [u32::MAX as f64/65536.0, 0.0, 0.5]);
+    for out_of_range in [-1.0, 65536.0] {
+      assert!(matches!(info.set_gamma([out_of_range, 1.0, 1.0]), Err(Error::Overflow { field: "gamma" })));
+    }
+    //through a V4 header
+    let rgb = BMP::from_rgba8(1, 1, &[1, 2, 3, 255], PixelFormat::Rgb24).unwrap();
+    let mut bmp = BMP::from_bytes(rgb.encode(&SaveOptions::new().header(HeaderVersion::V4)).unwrap());
+    bmp.set_endpoints(srgb).unwrap();
+    bmp.set_gamma([2.2, 1.8, 1.0]).unwrap();
+    assert_eq!(BMP::bytes_to_int(bmp.contents[HEADER_OFFSET+60..HEADER_OFFSET+64].try_into().unwrap()), 442811128);
+    assert_eq!(BMP::bytes_to_int(bmp.contents[HEADER_OFFSET+96..HEADER_OFFSET+100].try_into().unwrap()), 144179);
+    assert_eq!(bmp.get_endpoints().unwrap(), round_trip);
+    assert_eq!(bmp.get_gamma().unwrap(), [144179.0/65536.0, 117965.0/65536.0, 1.0]);
+    assert!(matches!(rgb.get_gamma(), Err(Error::DoesNotExist(_))));
+    let mut rgb = rgb;
+    assert!(matches!(rgb.set_gamma([2.2; 3]), Err(Error::Unsupported(_))));
+  }
+
+  #[test]
+  fn color_space_codes() {
+    let cs_types = [
+      (0, CSType::LCS_CALIBRATED_RGB, "LCS_CALIBRATED_RGB"),
+      (u32::from_be_bytes(*b"sRGB"), CSType::LCS_sRGB, "LCS_sRGB"),
+      (u32::from_be_bytes(*b"Win "), CSType::LCS_WINDOWS_COLOR_SPACE, "LCS_WINDOWS_COLOR_SPACE"),
+      (u32::from_be_bytes(*b"LINK"), CSType::PROFILE_LINKED, "PROFILE_LINKED"),
+      (u32::from_be_bytes(*b"MBED"), CSType::PROFILE_EMBEDDED, "PROFILE_EMBEDDED"),
+      (1, CSType::Unknown(1), "unknown (0x00000001)"),
+      (u32::from_be_bytes(*b"srgb"), CSType::Unknown(0x73726762), "unknown (0x73726762)"),
+    ];
+    for (int, cs_type, name) in cs_types {
+      assert_eq!(CSType::from_u32(int), cs_type);
+      assert_eq!(cs_type.to_u32(), int);
+      assert_eq!(cs_type.to_string(), name);
+    }
+    let intents = [
+      (1, Intent::LCS_GM_BUSINESS, "LCS_GM_BUSINESS"),
+      (2, Intent::LCS_GM_GRAPHICS, "LCS_GM_GRAPHICS"),
+      (4, Intent::LCS_GM_IMAGES, "LCS_GM_IMAGES"),
+      (8, Intent::LCS_GM_ABS_COLORIMETRIC, "LCS_GM_ABS_COLORIMETRIC"),
+      (0, Intent::Unknown(0), "unknown (0)"),
+      (3, Intent::Unknown(3), "unknown (3)"),
+      (u32::MAX, Intent::Unknown(u32::MAX), "unknown (4294967295)"),
+    ];
+    for (int, intent, name) in intents {
+      assert_eq!(Intent::from_u32(int), intent);
+      assert_eq!(intent.to_u32(), int);
+      assert_eq!(intent.to_string(), name);
+    }
+    //read from and written to a V5 header as is, unknown values included
+    let rgb = BMP::from_rgba8(1, 1, &[1, 2, 3, 255], PixelFormat::Rgb24).unwrap();
+    let mut bmp = BMP::from_bytes(rgb.encode(&SaveOptions::new().header(HeaderVersion::V5)).unwrap());
+    BMP::write_int(&mut bmp.contents, HEADER_OFFSET+56, 0x12345678u32);
+    BMP::write_int(&mut bmp.contents, HEADER_OFFSET+108, 3u32);
+    let info = bmp.get_color_space_info().unwrap();
+    assert_eq!((info.cs_type, info.intent), (CSType::Unknown(0x12345678), Intent::Unknown(3)));
+    assert_eq!(bmp.encode(&SaveOptions::new()).unwrap(), bmp.contents);
+  }
+
+  //SOI, an APP0 segment, a DHT segment, a fill byte, SOF0 with the dimensions, then EOI. no actual image data
+  fn jpeg_header(width: u16, height: u16) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0xff, 0xd8];
+    bytes.extend_from_slice(&[0xff, 0xe0, 0, 16]);
+    bytes.extend_from_slice(b"JFIF\0");
+    bytes.extend_from_slice(&[1, 1, 0, 0, 1, 0, 1, 0, 0]);
+    //DHT has a marker in the SOF range, but isn't one
+    bytes.extend_from_slice(&[0xff, 0xc4, 0, 3, 0]);
+    bytes.extend_from_slice(&[0xff, 0xff, 0xc0, 0, 11, 8]);
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bytes.extend_from_slice(&width.to_be_bytes());
+    bytes.extend_from_slice(&[1, 1, 0x11, 0]);
+    bytes.extend_from_slice(&[0xff, 0xd9]);
+    bytes
+  }
+
+  #[test]
+  fn embedded_formats() {
+    let jpeg = jpeg_header(640, 480);
+    let rgba = test_rgba(5, 3, 4, false);
+    let png = BMP::from_rgba8(5, 3, &rgba, PixelFormat::Rgb24).unwrap().encode_png().unwrap();
+    assert_eq!(EmbeddedFormat::detect(&jpeg), Some(EmbeddedFormat::Jpeg));
+    assert_eq!(EmbeddedFormat::detect(&png), Some(EmbeddedFormat::Png));
+    for other in [&b"GIF89a"[..], &[0xff, 0xd8], &png[..7], &[]] {
+      assert_eq!(EmbeddedFormat::detect(other), None);
+    }
+    assert_eq!(EmbeddedFormat::Jpeg.get_dimensions(&jpeg), Some((640, 480)));
+    assert_eq!(EmbeddedFormat::Png.get_dimensions(&png), Some((5, 3)));
+    //cut off before the frame, end of image before a frame, and a PNG without IHDR first
+    assert_eq!(EmbeddedFormat::Jpeg.get_dimensions(&jpeg[..30]), None);
+    let mut no_frame = jpeg[..27].to_vec();
+    no_frame.extend_from_slice(&[0xff, 0xd9]);
+    assert_eq!(EmbeddedFormat::Jpeg.get_dimensions(&no_frame), None);
+    let mut no_ihdr = png.clone();
+    no_ihdr[12..16].copy_from_slice(b"IDAT");
+    assert_eq!(EmbeddedFormat::Png.get_dimensions(&no_ihdr), None);
+    assert_eq!(EmbeddedFormat::Png.get_dimensions(&png[..20]), None);
+  }
+
+  #[test]
+  fn embedded_round_trip() {
+    let rgba = test_rgba(5, 3, 4, false);
+    let png = BMP::from_rgba8(5, 3, &rgba, PixelFormat::Rgb24).unwrap().encode_png().unwrap();
+    for (bytes, format, dimensions) in [(jpeg_header(640, 480), EmbeddedFormat::Jpeg, (640, 480)), (png.clone(), EmbeddedFormat::Png, (5, 3))] {
+      let embedded = BMP::from_bytes(BMP::new_embedded(&bytes).unwrap().contents);
+      let dib_header = embedded.get_dib_header().unwrap();
+      assert_eq!((dib_header.width, dib_header.height, dib_header.bitcount), (dimensions.0, dimensions.1, 0));
+      assert_eq!((dib_header.compression, dib_header.sizeimage), (Some(format.compression()), Some(bytes.len() as u32)));
+      assert_eq!(embedded.get_size(true), embedded.contents.len());
+      assert_eq!(embedded.embedded_payload().unwrap(), (format, &bytes[..]));
+    }
+    assert_eq!(BMP::new_embedded(&png).unwrap().to_rgba8().unwrap(), (5, 3, rgba.clone()));
+    assert!(matches!(BMP::new_embedded(b"GIF89a"), Err(Error::WrongFileType)));
+    assert!(matches!(BMP::new_embedded(&jpeg_header(640, 480)[..30]), Err(Error::Truncated { .. })));
+    let plain = BMP::from_rgba8(5, 3, &rgba, PixelFormat::Rgb24).unwrap();
+    assert!(matches!(plain.embedded_payload(), Err(Error::DoesNotExist(_))));
+    //biSizeImage past the end of the file
+    let mut embedded = BMP::new_embedded(&png).unwrap();
+    BMP::write_int(&mut embedded.contents, HEADER_OFFSET+20, png.len() as u32+1);
+    assert!(matches!(embedded.embedded_payload(), Err(Error::Truncated { .. })));
+  }
+}
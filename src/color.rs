@@ -0,0 +1,350 @@
+use std::convert::TryInto;
+use crate::bmp::{CIEXYZTRIPLE, Error};
+use crate::icc::IccProfile;
+
+//color management, moving pixels between rgb color spaces through CIE XYZ
+//https://en.wikipedia.org/wiki/CIE_1931_color_space
+//everything goes through the ICC profile connection space, which has a D50 white point
+
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+//https://en.wikipedia.org/wiki/LMS_color_space#Bradford's_spectrally_sharpened_matrix_(LLAB,_CIECAM97s)
+const BRADFORD: [[f64; 3]; 3] = [
+  [0.8951, 0.2664, -0.1614],
+  [-0.7502, 1.7135, 0.0367],
+  [0.0389, -0.0685, 1.0296],
+];
+
+type Matrix = [[f64; 3]; 3];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+  let mut product: Matrix = [[0.0; 3]; 3];
+  for row in 0..3 {
+    for column in 0..3 {
+      product[row][column] = (0..3).map(|i| a[row][i]*b[i][column]).sum();
+    }
+  }
+  return product;
+}
+
+fn apply(matrix: &Matrix, vector: [f64; 3]) -> [f64; 3] {
+  let mut result: [f64; 3] = [0.0; 3];
+  for row in 0..3 {
+    result[row] = (0..3).map(|i| matrix[row][i]*vector[i]).sum();
+  }
+  return result;
+}
+
+//None if the matrix can't be inverted
+#[allow(clippy::needless_range_loop)]
+fn invert(m: &Matrix) -> Option<Matrix> {
+  let determinant = m[0][0]*(m[1][1]*m[2][2]-m[1][2]*m[2][1])-m[0][1]*(m[1][0]*m[2][2]-m[1][2]*m[2][0])+m[0][2]*(m[1][0]*m[2][1]-m[1][1]*m[2][0]);
+  if determinant.abs() < 1e-12 || !determinant.is_finite() {
+    return None;
+  }
+  let mut inverse: Matrix = [[0.0; 3]; 3];
+  for row in 0..3 {
+    for column in 0..3 {
+      //cofactor of the transposed position
+      let (r1, r2) = ((column+1) % 3, (column+2) % 3);
+      let (c1, c2) = ((row+1) % 3, (row+2) % 3);
+      inverse[row][column] = (m[r1][c1]*m[r2][c2]-m[r1][c2]*m[r2][c1])/determinant;
+    }
+  }
+  return Some(inverse);
+}
+
+//chromatic adaptation from one white point to another
+fn bradford(from_white: [f64; 3], to_white: [f64; 3]) -> Option<Matrix> {
+  let from_lms = apply(&BRADFORD, from_white);
+  let to_lms = apply(&BRADFORD, to_white);
+  let mut scale: Matrix = [[0.0; 3]; 3];
+  for i in 0..3 {
+    if from_lms[i] == 0.0 {
+      return None;
+    }
+    scale[i][i] = to_lms[i]/from_lms[i];
+  }
+  return Some(multiply(&invert(&BRADFORD)?, &multiply(&scale, &BRADFORD)));
+}
+
+//turns an encoded channel value (0 to 1) into a linear one (0 to 1)
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToneCurve {
+  Gamma(f64),
+  //evenly spaced samples, linearly interpolated
+  Table(Vec<f64>),
+  //ICC parametricCurveType, function 0 to 4 with the parameters g, a, b, c, d, e, f
+  Parametric { function: u16, params: [f64; 7] },
+}
+
+impl ToneCurve {
+  pub fn srgb() -> ToneCurve {
+    return ToneCurve::Parametric { function: 3, params: [2.4, 1.0/1.055, 0.055/1.055, 1.0/12.92, 0.04045, 0.0, 0.0] };
+  }
+  pub fn eval(&self, x: f64) -> f64 {
+    match self {
+      ToneCurve::Gamma(gamma) => return x.max(0.0).powf(*gamma),
+      ToneCurve::Table(table) => {
+        if table.is_empty() {
+          return x;
+        }
+        let position = x.clamp(0.0, 1.0)*(table.len()-1) as f64;
+        let index = position.floor() as usize;
+        if index+1 >= table.len() {
+          return table[table.len()-1];
+        }
+        let fraction = position-index as f64;
+        return table[index]*(1.0-fraction)+table[index+1]*fraction;
+      },
+      ToneCurve::Parametric { function, params } => {
+        let [g, a, b, c, d, e, f] = *params;
+        let power = |x: f64| (a*x+b).max(0.0).powf(g);
+        match function {
+          0 => return x.max(0.0).powf(g),
+          1 => return if a*x+b >= 0.0 { power(x) } else { 0.0 },
+          2 => return if a*x+b >= 0.0 { power(x)+c } else { c },
+          3 => return if x >= d { power(x) } else { c*x },
+          _ => return if x >= d { power(x)+e } else { c*x+f },
+        }
+      },
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RgbColorSpace {
+  //linear rgb to D50 XYZ, the columns are the red, green and blue primaries
+  pub to_xyz: [[f64; 3]; 3],
+  //red, green, blue
+  pub curves: [ToneCurve; 3],
+}
+
+fn read_s15_fixed16(bytes: &[u8], offset: usize) -> Option<f64> {
+  let int = i32::from_be_bytes(bytes.get(offset..offset+4)?.try_into().unwrap());
+  return Some(int as f64/65536.0);
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+  return Some(u16::from_be_bytes(bytes.get(offset..offset+2)?.try_into().unwrap()));
+}
+
+//XYZType, a signature, 4 reserved bytes then X, Y and Z
+fn parse_xyz_tag(data: &[u8]) -> Option<[f64; 3]> {
+  if data.get(0..4)? != b"XYZ " {
+    return None;
+  }
+  return Some([read_s15_fixed16(data, 8)?, read_s15_fixed16(data, 12)?, read_s15_fixed16(data, 16)?]);
+}
+
+//curveType or parametricCurveType
+fn parse_curve_tag(data: &[u8]) -> Option<ToneCurve> {
+  match data.get(0..4)? {
+    b"curv" => {
+      let count = u32::from_be_bytes(data.get(8..12)?.try_into().unwrap()) as usize;
+      match count {
+        0 => return Some(ToneCurve::Gamma(1.0)),
+        //u8Fixed8Number
+        1 => return Some(ToneCurve::Gamma(read_u16(data, 12)? as f64/256.0)),
+        _ => {
+          //count comes from the file, so check the table is really there before reserving room for it
+          if count.checked_mul(2)?.checked_add(12)? > data.len() {
+            return None;
+          }
+          let mut table: Vec<f64> = Vec::with_capacity(count);
+          for i in 0..count {
+            table.push(read_u16(data, 12+i*2)? as f64/65535.0);
+          }
+          return Some(ToneCurve::Table(table));
+        },
+      }
+    },
+    b"para" => {
+      let function = read_u16(data, 8)?;
+      let param_count = match function {
+        0 => 1,
+        1 => 3,
+        2 => 4,
+        3 => 5,
+        4 => 7,
+        _ => return None,
+      };
+      let mut params: [f64; 7] = [0.0; 7];
+      for (i, param) in params.iter_mut().take(param_count).enumerate() {
+        *param = read_s15_fixed16(data, 12+i*4)?;
+      }
+      return Some(ToneCurve::Parametric { function, params });
+    },
+    _ => return None,
+  }
+}
+
+impl RgbColorSpace {
+  pub fn srgb() -> RgbColorSpace {
+    //the sRGB primaries, adapted to D50 the way ICC profiles store them
+    return RgbColorSpace {
+      to_xyz: [
+        [0.4360747, 0.3850649, 0.1430804],
+        [0.2225045, 0.7168786, 0.0606169],
+        [0.0139322, 0.0971045, 0.7141733],
+      ],
+      curves: [ToneCurve::srgb(), ToneCurve::srgb(), ToneCurve::srgb()],
+    };
+  }
+  //from LCS_CALIBRATED_RGB endpoints (CIE XYZ of the red, green and blue primaries) and gammas
+  //the white point is whatever the primaries add up to, and is adapted to D50
+  //most writers leave the endpoints and gammas as 0, so singular endpoints get the sRGB primaries and a 0 gamma gets the sRGB curve
+  pub fn from_calibrated(endpoints: CIEXYZTRIPLE, gamma: [f64; 3]) -> Result<RgbColorSpace, Error> {
+    let srgb = RgbColorSpace::srgb();
+    let fields = ["GammaRed", "GammaGreen", "GammaBlue"];
+    let mut curves: [ToneCurve; 3] = srgb.curves.clone();
+    for i in 0..3 {
+      if gamma[i] == 0.0 {
+        continue;
+      }
+      if gamma[i] < 0.0 || !gamma[i].is_finite() {
+        return Err(Error::InvalidHeaderField { field: fields[i], value: (gamma[i]*65536.0) as i64 });
+      }
+      curves[i] = ToneCurve::Gamma(gamma[i]);
+    }
+    let mut primaries: Matrix = [[0.0; 3]; 3];
+    for (column, endpoint) in endpoints.to_array().iter().enumerate() {
+      for row in 0..3 {
+        primaries[row][column] = endpoint[row];
+      }
+    }
+    let white = apply(&primaries, [1.0, 1.0, 1.0]);
+    if white[1] <= 0.0 || invert(&primaries).is_none() {
+      return Ok(RgbColorSpace { to_xyz: srgb.to_xyz, curves });
+    }
+    //scale so white has a Y of 1
+    for row in primaries.iter_mut() {
+      for value in row.iter_mut() {
+        *value /= white[1];
+      }
+    }
+    let adaptation = match bradford(apply(&primaries, [1.0, 1.0, 1.0]), D50) {
+      Some(adaptation) => adaptation,
+      None => return Err(Error::InvalidProfile("endpoints can't be adapted to D50".to_string())),
+    };
+    return Ok(RgbColorSpace { to_xyz: multiply(&adaptation, &primaries), curves });
+  }
+  //matrix/TRC profiles, which have rXYZ, gXYZ, bXYZ, rTRC, gTRC and bTRC tags. lookup table (A2B0) profiles aren't supported
+  pub fn from_icc(profile: &IccProfile) -> Result<RgbColorSpace, Error> {
+    if &profile.color_space != b"RGB " || &profile.pcs != b"XYZ " {
+      return Err(Error::Unsupported("only RGB profiles with an XYZ connection space are supported"));
+    }
+    let mut to_xyz: Matrix = [[0.0; 3]; 3];
+    for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+      let xyz = match profile.tag_data(signature).and_then(parse_xyz_tag) {
+        Some(xyz) => xyz,
+        None => return Err(Error::InvalidProfile(format!("missing or invalid {} tag", String::from_utf8_lossy(*signature)))),
+      };
+      for row in 0..3 {
+        to_xyz[row][column] = xyz[row];
+      }
+    }
+    if invert(&to_xyz).is_none() {
+      return Err(Error::InvalidProfile("the primaries can't be inverted".to_string()));
+    }
+    let mut curves: Vec<ToneCurve> = Vec::with_capacity(3);
+    for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+      match profile.tag_data(signature).and_then(parse_curve_tag) {
+        Some(curve) => curves.push(curve),
+        None => return Err(Error::InvalidProfile(format!("missing or invalid {} tag", String::from_utf8_lossy(signature)))),
+      };
+    }
+    return Ok(RgbColorSpace { to_xyz, curves: curves.try_into().unwrap() });
+  }
+}
+
+//converts 8 bit rgb from one color space to another
+pub struct ColorTransform {
+  //encoded value to linear, for every 8 bit value
+  decode: [Vec<f64>; 3],
+  //linear source rgb to linear destination rgb
+  matrix: Matrix,
+  //the linear values halfway between each 8 bit value of the destination, so encoding is a search and never needs the inverse of a curve
+  thresholds: [Vec<f64>; 3],
+}
+
+impl ColorTransform {
+  pub fn new(from: &RgbColorSpace, to: &RgbColorSpace) -> Result<ColorTransform, Error> {
+    let from_xyz = match invert(&to.to_xyz) {
+      Some(from_xyz) => from_xyz,
+      None => return Err(Error::Unsupported("destination color space matrix can't be inverted")),
+    };
+    let decode = [0, 1, 2].map(|c| (0..256).map(|value| from.curves[c].eval(value as f64/255.0)).collect::<Vec<f64>>());
+    let thresholds = [0, 1, 2].map(|c| (0..255).map(|value| to.curves[c].eval((value as f64+0.5)/255.0)).collect::<Vec<f64>>());
+    return Ok(ColorTransform { decode, matrix: multiply(&from_xyz, &from.to_xyz), thresholds });
+  }
+  pub fn apply(&self, rgb: [u8; 3]) -> [u8; 3] {
+    let linear = apply(&self.matrix, [self.decode[0][rgb[0] as usize], self.decode[1][rgb[1] as usize], self.decode[2][rgb[2] as usize]]);
+    //out of gamut colors get clipped, since the thresholds stop at 0 and 255
+    return [0, 1, 2].map(|c| self.thresholds[c].partition_point(|threshold| *threshold <= linear[c]) as u8);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bmp::CIEXYZ;
+
+  //the sRGB primaries with their own D65 white, the way a writer would put them in the endpoints
+  fn srgb_endpoints() -> CIEXYZTRIPLE {
+    CIEXYZTRIPLE {
+      red: CIEXYZ { x: 0.4124, y: 0.2126, z: 0.0193 },
+      green: CIEXYZ { x: 0.3576, y: 0.7152, z: 0.1192 },
+      blue: CIEXYZ { x: 0.1805, y: 0.0722, z: 0.9505 },
+    }
+  }
+
+  #[test]
+  fn calibrated_fallbacks() {
+    let zero = CIEXYZ { x: 0.0, y: 0.0, z: 0.0 };
+    let zeros = CIEXYZTRIPLE { red: zero, green: zero, blue: zero };
+    assert_eq!(RgbColorSpace::from_calibrated(zeros, [0.0; 3]).unwrap(), RgbColorSpace::srgb());
+    let linear = RgbColorSpace::from_calibrated(zeros, [1.0, 0.0, 1.0]).unwrap();
+    assert_eq!(linear.to_xyz, RgbColorSpace::srgb().to_xyz);
+    assert_eq!(linear.curves, [ToneCurve::Gamma(1.0), ToneCurve::srgb(), ToneCurve::Gamma(1.0)]);
+    //all three primaries the same color
+    let gray = CIEXYZ { x: 0.3, y: 0.3, z: 0.3 };
+    assert_eq!(RgbColorSpace::from_calibrated(CIEXYZTRIPLE { red: gray, green: gray, blue: gray }, [0.0; 3]).unwrap(), RgbColorSpace::srgb());
+    assert!(matches!(RgbColorSpace::from_calibrated(srgb_endpoints(), [2.2, -1.0, 2.2]), Err(Error::InvalidHeaderField { field: "GammaGreen", .. })));
+    assert!(matches!(RgbColorSpace::from_calibrated(srgb_endpoints(), [f64::NAN, 2.2, 2.2]), Err(Error::InvalidHeaderField { field: "GammaRed", .. })));
+  }
+
+  #[test]
+  fn calibrated_srgb_primaries() {
+    //adapting the D65 primaries to D50 should land on the ICC sRGB matrix
+    let calibrated = RgbColorSpace::from_calibrated(srgb_endpoints(), [0.0; 3]).unwrap();
+    for (row, expected_row) in calibrated.to_xyz.iter().zip(RgbColorSpace::srgb().to_xyz.iter()) {
+      for (value, expected) in row.iter().zip(expected_row.iter()) {
+        assert!((value-expected).abs() < 1e-3, "{:?}", calibrated.to_xyz);
+      }
+    }
+  }
+
+  #[test]
+  fn transforms() {
+    let srgb = RgbColorSpace::srgb();
+    let identity = ColorTransform::new(&srgb, &srgb).unwrap();
+    for value in 0..=255u8 {
+      assert_eq!(identity.apply([value, value, value]), [value, value, value]);
+    }
+    assert_eq!(identity.apply([255, 0, 0]), [255, 0, 0]);
+    assert_eq!(identity.apply([12, 200, 77]), [12, 200, 77]);
+    //gamma 1 with the sRGB primaries: linear 128/255 is 188 in sRGB, and sRGB 188 is linear 128
+    let linear = RgbColorSpace::from_calibrated(srgb_endpoints(), [1.0; 3]).unwrap();
+    let to_srgb = ColorTransform::new(&linear, &srgb).unwrap();
+    assert_eq!(to_srgb.apply([0, 0, 0]), [0, 0, 0]);
+    assert_eq!(to_srgb.apply([128, 128, 128]), [188, 188, 188]);
+    assert_eq!(to_srgb.apply([255, 255, 255]), [255, 255, 255]);
+    let from_srgb = ColorTransform::new(&srgb, &linear).unwrap();
+    assert_eq!(from_srgb.apply([188, 188, 188]), [128, 128, 128]);
+    assert_eq!(from_srgb.apply([255, 255, 255]), [255, 255, 255]);
+    //a gamma of 2 and the sRGB primaries, 128 is 0.252 linear, which is 137 in sRGB
+    let gamma2 = RgbColorSpace::from_calibrated(srgb_endpoints(), [2.0; 3]).unwrap();
+    assert_eq!(ColorTransform::new(&gamma2, &srgb).unwrap().apply([128, 128, 128]), [137, 137, 137]);
+  }
+}
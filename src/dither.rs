@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use crate::quantize;
+
+//dithering, hiding the loss of colors when mapping to a small color table
+//https://en.wikipedia.org/wiki/Dither
+//https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html
+
+pub enum DitherMethod {
+  //just use the closest color
+  None,
+  FloydSteinberg,
+  Atkinson,
+  JarvisJudiceNinke,
+  Sierra,
+  //ordered dithering with a 2x2, 4x4 or 8x8 threshold matrix
+  Bayer2,
+  Bayer4,
+  Bayer8,
+}
+
+pub struct Dither {
+  pub method: DitherMethod,
+  //go right to left on every other row, only used by the error diffusion methods
+  pub serpentine: bool,
+}
+
+impl Dither {
+  pub fn new(method: DitherMethod) -> Dither {
+    return Dither { method, serpentine: false };
+  }
+  pub fn serpentine(mut self, serpentine: bool) -> Dither {
+    self.serpentine = serpentine;
+    return self;
+  }
+}
+
+//(x offset, y offset, weight) of where the error goes, and what the weights are divided by
+fn kernel(method: &DitherMethod) -> (&'static [(i32, usize, f32)], f32) {
+  match method {
+    DitherMethod::FloydSteinberg => (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0),
+    //only 3/4 of the error is spread
+    DitherMethod::Atkinson => (&[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)], 8.0),
+    DitherMethod::JarvisJudiceNinke => (&[
+      (1, 0, 7.0), (2, 0, 5.0),
+      (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+      (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+    ], 48.0),
+    DitherMethod::Sierra => (&[
+      (1, 0, 5.0), (2, 0, 3.0),
+      (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+      (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+    ], 32.0),
+    _ => (&[], 1.0),
+  }
+}
+
+//maps rgba pixels to indexes into the palette, one per pixel
+pub fn dither(rgba: &[u8], width: usize, height: usize, palette: &[[u8; 3]], dither: &Dither) -> Vec<u8> {
+  match dither.method {
+    DitherMethod::None => return quantize::map_to_palette(rgba, palette),
+    DitherMethod::Bayer2 => return ordered(rgba, width, height, palette, 2),
+    DitherMethod::Bayer4 => return ordered(rgba, width, height, palette, 4),
+    DitherMethod::Bayer8 => return ordered(rgba, width, height, palette, 8),
+    _ => return error_diffusion(rgba, width, height, palette, dither),
+  }
+}
+
+//nearest color, remembering colors already looked up
+fn nearest_cached(cache: &mut HashMap<[u8; 3], u8>, palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+  return *cache.entry(color).or_insert_with(|| quantize::nearest(palette, color) as u8);
+}
+
+fn error_diffusion(rgba: &[u8], width: usize, height: usize, palette: &[[u8; 3]], dither: &Dither) -> Vec<u8> {
+  let (kernel, divisor) = kernel(&dither.method);
+  let mut indexes: Vec<u8> = vec![0; width*height];
+  let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+  //error carried to the current row and the two below it, 3 channels per pixel
+  let mut errors: Vec<Vec<f32>> = vec![vec![0.0; width*3]; 3];
+  for y in 0..height {
+    let reverse = dither.serpentine && y % 2 == 1;
+    for i in 0..width {
+      let x = if reverse { width-1-i } else { i };
+      let pixel = (y*width+x)*4;
+      let mut color: [f32; 3] = [0.0; 3];
+      for c in 0..3 {
+        color[c] = (rgba[pixel+c] as f32+errors[0][x*3+c]).clamp(0.0, 255.0);
+      }
+      let index = nearest_cached(&mut cache, palette, [color[0].round() as u8, color[1].round() as u8, color[2].round() as u8]);
+      indexes[y*width+x] = index;
+      let chosen = palette[index as usize];
+      for (dx, dy, weight) in kernel {
+        //mirror the kernel when going right to left
+        let dx = if reverse { -dx } else { *dx };
+        let target_x = x as i64+dx as i64;
+        if target_x < 0 || target_x >= width as i64 || y+dy >= height {
+          continue;
+        }
+        for c in 0..3 {
+          errors[*dy][target_x as usize*3+c] += (color[c]-chosen[c] as f32)*weight/divisor;
+        }
+      }
+    }
+    //move down a row
+    errors.rotate_left(1);
+    errors[2].iter_mut().for_each(|error| *error = 0.0);
+  }
+  return indexes;
+}
+
+//https://en.wikipedia.org/wiki/Ordered_dithering
+fn bayer_matrix(size: usize) -> Vec<Vec<u32>> {
+  let mut matrix: Vec<Vec<u32>> = vec![vec![0]];
+  while matrix.len() < size {
+    let n = matrix.len();
+    let mut bigger: Vec<Vec<u32>> = vec![vec![0; n*2]; n*2];
+    for y in 0..n {
+      for x in 0..n {
+        let value = matrix[y][x]*4;
+        bigger[y][x] = value;
+        bigger[y][x+n] = value+2;
+        bigger[y+n][x] = value+3;
+        bigger[y+n][x+n] = value+1;
+      }
+    }
+    matrix = bigger;
+  }
+  return matrix;
+}
+
+fn ordered(rgba: &[u8], width: usize, height: usize, palette: &[[u8; 3]], size: usize) -> Vec<u8> {
+  let matrix = bayer_matrix(size);
+  //how far apart the palette colors are, so the threshold moves colors about one step
+  let mut spread: f32 = 0.0;
+  for (i, a) in palette.iter().enumerate() {
+    let mut closest = f32::MAX;
+    for (j, b) in palette.iter().enumerate() {
+      if i != j {
+        //biggest difference in a single channel, since the threshold is added to each channel
+        let distance = (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0) as f32;
+        closest = closest.min(distance);
+      }
+    }
+    if closest != f32::MAX {
+      spread += closest;
+    }
+  }
+  spread /= palette.len().max(1) as f32;
+  let mut indexes: Vec<u8> = Vec::with_capacity(width*height);
+  let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+  for y in 0..height {
+    for x in 0..width {
+      //-0.5 to 0.5
+      let threshold = (matrix[y % size][x % size] as f32+0.5)/(size*size) as f32-0.5;
+      let pixel = (y*width+x)*4;
+      let mut color: [u8; 3] = [0; 3];
+      for c in 0..3 {
+        color[c] = (rgba[pixel+c] as f32+threshold*spread).clamp(0.0, 255.0).round() as u8;
+      }
+      indexes.push(nearest_cached(&mut cache, palette, color));
+    }
+  }
+  return indexes;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BLACK_AND_WHITE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+  fn gray(values: &[u8]) -> Vec<u8> {
+    values.iter().flat_map(|value| [*value, *value, *value, 255]).collect()
+  }
+
+  //3 rows of a left to right ramp
+  fn gradient() -> Vec<u8> {
+    gray(&[[20, 60, 100, 140, 180, 220]; 3].concat())
+  }
+
+  #[test]
+  fn kernels() {
+    for method in [DitherMethod::FloydSteinberg, DitherMethod::JarvisJudiceNinke, DitherMethod::Sierra] {
+      let (weights, divisor) = kernel(&method);
+      assert_eq!(weights.iter().map(|(_, _, weight)| weight).sum::<f32>(), divisor);
+    }
+    let (weights, divisor) = kernel(&DitherMethod::Atkinson);
+    assert_eq!(weights.iter().map(|(_, _, weight)| weight).sum::<f32>()/divisor, 0.75);
+  }
+
+  #[test]
+  fn floyd_steinberg() {
+    //25% gray: the first row is all black and pushes its error down, the second row alternates
+    let rgba = gray(&[64; 8]);
+    assert_eq!(dither(&rgba, 4, 2, &BLACK_AND_WHITE, &Dither::new(DitherMethod::FloydSteinberg)), vec![0, 0, 0, 0, 0, 1, 0, 1]);
+    //going right to left on the second row mirrors it
+    assert_eq!(dither(&rgba, 4, 2, &BLACK_AND_WHITE, &Dither::new(DitherMethod::FloydSteinberg).serpentine(true)), vec![0, 0, 0, 0, 1, 0, 1, 0]);
+  }
+
+  #[test]
+  fn error_diffusion_gradient() {
+    let rgba = gradient();
+    let methods = || [DitherMethod::FloydSteinberg, DitherMethod::Atkinson, DitherMethod::JarvisJudiceNinke, DitherMethod::Sierra];
+    let forward: [[u8; 18]; 4] = [
+      [0, 0, 1, 0, 1, 1, 0, 0, 0, 1, 1, 1, 0, 1, 0, 1, 0, 1],
+      [0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 0, 1, 1],
+      [0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 0, 1, 1],
+      [0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 0, 1, 1],
+    ];
+    //the first row is the same, the rest differ
+    let serpentine: [[u8; 18]; 4] = [
+      [0, 0, 1, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 1, 0, 1, 1, 1],
+      [0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 1],
+      [0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 1],
+      [0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1, 0, 1, 1, 0, 1, 1],
+    ];
+    for (method, expected) in methods().into_iter().zip(forward) {
+      assert_eq!(dither(&rgba, 6, 3, &BLACK_AND_WHITE, &Dither::new(method)), expected);
+    }
+    for (method, expected) in methods().into_iter().zip(serpentine) {
+      assert_eq!(dither(&rgba, 6, 3, &BLACK_AND_WHITE, &Dither::new(method).serpentine(true)), expected);
+    }
+    //serpentine doesn't change ordered dithering or plain mapping
+    assert_eq!(dither(&rgba, 6, 3, &BLACK_AND_WHITE, &Dither::new(DitherMethod::None).serpentine(true)), [[0, 0, 0, 1, 1, 1]; 3].concat());
+  }
+
+  #[test]
+  fn bayer_matrices() {
+    assert_eq!(bayer_matrix(2), vec![vec![0, 2], vec![3, 1]]);
+    assert_eq!(bayer_matrix(4), vec![vec![0, 8, 2, 10], vec![12, 4, 14, 6], vec![3, 11, 1, 9], vec![15, 7, 13, 5]]);
+    let mut values: Vec<u32> = bayer_matrix(8).concat();
+    values.sort_unstable();
+    assert_eq!(values, (0..64).collect::<Vec<u32>>());
+  }
+
+  #[test]
+  fn bayer_thresholds() {
+    //thresholds of -3/8, 1/8, 3/8 and -1/8 of the 255 between black and white, so 50% gray is half white
+    assert_eq!(dither(&gray(&[128; 4]), 2, 2, &BLACK_AND_WHITE, &Dither::new(DitherMethod::Bayer2)), vec![0, 1, 1, 0]);
+    //25% gray is white only where the 4x4 matrix is 12 or more
+    let indexes = dither(&gray(&[64; 16]), 4, 4, &BLACK_AND_WHITE, &Dither::new(DitherMethod::Bayer4));
+    assert_eq!(indexes, bayer_matrix(4).concat().iter().map(|value| (*value >= 12) as u8).collect::<Vec<u8>>());
+    //the pattern repeats past the size of the matrix
+    let wide = dither(&gray(&[128; 8]), 4, 2, &BLACK_AND_WHITE, &Dither::new(DitherMethod::Bayer2));
+    assert_eq!(wide, vec![0, 1, 0, 1, 1, 0, 1, 0]);
+    //black and white stay as they are
+    assert_eq!(dither(&gray(&[0, 255, 0, 255]), 2, 2, &BLACK_AND_WHITE, &Dither::new(DitherMethod::Bayer8)), vec![0, 1, 0, 1]);
+    //how far the threshold moves colors follows the palette, with 5 grays 50% gray is only pushed to its neighbours
+    let grays: [[u8; 3]; 5] = [[0, 0, 0], [64, 64, 64], [128, 128, 128], [192, 192, 192], [255, 255, 255]];
+    assert_eq!(dither(&gray(&[128; 4]), 2, 2, &grays, &Dither::new(DitherMethod::Bayer2)), vec![2, 2, 2, 2]);
+    assert_eq!(dither(&gray(&[96; 4]), 2, 2, &grays, &Dither::new(DitherMethod::Bayer2)), vec![1, 2, 2, 1]);
+  }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use crate::bmp::Error;
+
+//CCITT group 3 one dimensional (modified huffman) decoding, used by OS/2 BCA_HUFFMAN1D bitmaps
+//https://www.itu.int/rec/T-REC-T.4
+//each row is a run of white, then black, then white, and so on. runs of 64 or more are a makeup code followed by a terminating code
+
+//run lengths 0 to 63
+const WHITE_TERMINATING: [&str; 64] = [
+  "00110101", "000111", "0111", "1000", "1011", "1100", "1110", "1111",
+  "10011", "10100", "00111", "01000", "001000", "000011", "110100", "110101",
+  "101010", "101011", "0100111", "0001100", "0001000", "0010111", "0000011", "0000100",
+  "0101000", "0101011", "0010011", "0100100", "0011000", "00000010", "00000011", "00011010",
+  "00011011", "00010010", "00010011", "00010100", "00010101", "00010110", "00010111", "00101000",
+  "00101001", "00101010", "00101011", "00101100", "00101101", "00000100", "00000101", "00001010",
+  "00001011", "01010010", "01010011", "01010100", "01010101", "00100100", "00100101", "01011000",
+  "01011001", "01011010", "01011011", "01001010", "01001011", "00110010", "00110011", "00110100",
+];
+
+const BLACK_TERMINATING: [&str; 64] = [
+  "0000110111", "010", "11", "10", "011", "0011", "0010", "00011",
+  "000101", "000100", "0000100", "0000101", "0000111", "00000100", "00000111", "000011000",
+  "0000010111", "0000011000", "0000001000", "00001100111", "00001101000", "00001101100", "00000110111", "00000101000",
+  "00000010111", "00000011000", "000011001010", "000011001011", "000011001100", "000011001101", "000001101000", "000001101001",
+  "000001101010", "000001101011", "000011010010", "000011010011", "000011010100", "000011010101", "000011010110", "000011010111",
+  "000001101100", "000001101101", "000011011010", "000011011011", "000001010100", "000001010101", "000001010110", "000001010111",
+  "000001100100", "000001100101", "000001010010", "000001010011", "000000100100", "000000110111", "000000111000", "000000100111",
+  "000000101000", "000001011000", "000001011001", "000000101011", "000000101100", "000001011010", "000001100110", "000001100111",
+];
+
+//run lengths 64 to 1728, in steps of 64
+const WHITE_MAKEUP: [&str; 27] = [
+  "11011", "10010", "010111", "0110111", "00110110", "00110111", "01100100", "01100101",
+  "01101000", "01100111", "011001100", "011001101", "011010010", "011010011", "011010100", "011010101",
+  "011010110", "011010111", "011011000", "011011001", "011011010", "011011011", "010011000", "010011001",
+  "010011010", "011000", "010011011",
+];
+
+const BLACK_MAKEUP: [&str; 27] = [
+  "0000001111", "000011001000", "000011001001", "000001011011", "000000110011", "000000110100", "000000110101", "0000001101100",
+  "0000001101101", "0000001001010", "0000001001011", "0000001001100", "0000001001101", "0000001110010", "0000001110011", "0000001110100",
+  "0000001110101", "0000001110110", "0000001110111", "0000001010010", "0000001010011", "0000001010100", "0000001010101", "0000001011010",
+  "0000001011011", "0000001100100", "0000001100101",
+];
+
+//run lengths 1792 to 2560, shared by white and black
+const EXTENDED_MAKEUP: [&str; 13] = [
+  "00000001000", "00000001100", "00000001101", "000000010010", "000000010011", "000000010100", "000000010101", "000000010110",
+  "000000010111", "000000011100", "000000011101", "000000011110", "000000011111",
+];
+
+//(length in bits, code) to run length
+fn code_table(terminating: &[&str; 64], makeup: &[&str; 27]) -> HashMap<(u32, u32), usize> {
+  let mut table: HashMap<(u32, u32), usize> = HashMap::new();
+  let mut add = |code: &str, run: usize| {
+    table.insert((code.len() as u32, u32::from_str_radix(code, 2).unwrap()), run);
+  };
+  for (run, code) in terminating.iter().enumerate() {
+    add(code, run);
+  }
+  for (i, code) in makeup.iter().enumerate() {
+    add(code, (i+1)*64);
+  }
+  for (i, code) in EXTENDED_MAKEUP.iter().enumerate() {
+    add(code, 1792+i*64);
+  }
+  return table;
+}
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  //in bits
+  position: usize,
+}
+
+impl BitReader<'_> {
+  //most significant bit first
+  fn peek(&self, offset: usize) -> Option<u32> {
+    let position = self.position+offset;
+    let byte = self.data.get(position/8)?;
+    return Some(((byte >> (7-position%8)) & 1) as u32);
+  }
+  //skips an EOL (at least 11 zeros then a 1), if there is one
+  fn skip_eol(&mut self) -> bool {
+    let mut zeros: usize = 0;
+    while self.peek(zeros) == Some(0) {
+      zeros += 1;
+    }
+    if zeros >= 11 && self.peek(zeros) == Some(1) {
+      self.position += zeros+1;
+      return true;
+    }
+    return false;
+  }
+  //reads one code, returning its run length
+  fn read_run(&mut self, table: &HashMap<(u32, u32), usize>) -> Option<usize> {
+    let mut code: u32 = 0;
+    //the longest code is 13 bits
+    for length in 1..=13 {
+      code = (code << 1) | self.peek(length-1)?;
+      if let Some(run) = table.get(&(length as u32, code)) {
+        self.position += length;
+        return Some(*run);
+      }
+    }
+    return None;
+  }
+}
+
+//one byte per pixel, 0 for white and 1 for black, rows in the order they are stored
+//errors at the first bit that doesn't start a valid code, including running out of data before the last row
+pub fn decode(data: &[u8], width: usize, height: usize) -> Result<Vec<u8>, Error> {
+  let size = match width.checked_mul(height) {
+    Some(size) => size,
+    None => return Err(Error::Overflow { field: "pixel count" }),
+  };
+  let white = code_table(&WHITE_TERMINATING, &WHITE_MAKEUP);
+  let black = code_table(&BLACK_TERMINATING, &BLACK_MAKEUP);
+  let mut pixels: Vec<u8> = vec![0; size];
+  let mut reader = BitReader { data, position: 0 };
+  for y in 0..height {
+    while reader.skip_eol() {}
+    let mut x: usize = 0;
+    let mut is_black = false;
+    while x < width {
+      //add up makeup codes until a terminating code (less than 64)
+      let mut run: usize = 0;
+      loop {
+        let part = match reader.read_run(if is_black { &black } else { &white }) {
+          Some(part) => part,
+          None => return Err(Error::InvalidHuffmanCode { bit: reader.position }),
+        };
+        run += part;
+        if part < 64 {
+          break;
+        }
+      }
+      if is_black {
+        for pixel in pixels.iter_mut().skip(y*width+x).take(run.min(width-x)) {
+          *pixel = 1;
+        }
+      }
+      x += run;
+      is_black = !is_black;
+    }
+  }
+  return Ok(pixels);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //a string of 0s and 1s packed into bytes, padded with 0s
+  fn bits(text: &str) -> Vec<u8> {
+    let text: String = text.split_whitespace().collect();
+    text.as_bytes().chunks(8).map(|chunk| chunk.iter().enumerate().fold(0, |byte, (i, bit)| byte | ((bit-b'0') << (7-i)))).collect()
+  }
+
+  #[test]
+  fn runs() {
+    //white 2, black 3, then an EOL, white 0 and black 5
+    let data = bits("0111 10 000000000001 00110101 0011");
+    assert_eq!(decode(&data, 5, 2).unwrap(), vec![0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+    //makeup codes add up until a terminating code: white 64+6, black 1792+1
+    let data = bits("11011 1110 00000001000 010");
+    let mut expected = vec![0; 70];
+    expected.extend(vec![1; 1793]);
+    assert_eq!(decode(&data, 70+1793, 1).unwrap(), expected);
+  }
+
+  #[test]
+  fn invalid_codes() {
+    //13 zeros isn't a black code
+    let data = bits("0111 0000000000000 1");
+    assert!(matches!(decode(&data, 5, 1), Err(Error::InvalidHuffmanCode { bit: 4 })));
+    //running out of data before the last row
+    let data = bits("0111 10");
+    assert!(matches!(decode(&data, 5, 2), Err(Error::InvalidHuffmanCode { bit: 6 })));
+    assert!(matches!(decode(&[], 1, 1), Err(Error::InvalidHuffmanCode { bit: 0 })));
+  }
+}
@@ -0,0 +1,299 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::bmp::Error;
+
+//ICC color profiles, as embedded in BITMAPV5HEADER bitmaps
+//https://www.color.org/specification/ICC.1-2022-05.pdf
+//only the header and tag table are parsed, tag data is left as bytes
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccTag {
+  //four character code, eg: rXYZ
+  pub signature: [u8; 4],
+  //from the start of the profile
+  pub offset: u32,
+  pub size: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+  //the whole profile, header included
+  pub bytes: Vec<u8>,
+  pub preferred_cmm: [u8; 4],
+  //major, minor, bug fix
+  pub version: (u8, u8, u8),
+  //eg: mntr (display), prtr (printer), scnr (input)
+  pub device_class: [u8; 4],
+  //eg: RGB (with a trailing space), GRAY, CMYK
+  pub color_space: [u8; 4],
+  //profile connection space, XYZ or Lab
+  pub pcs: [u8; 4],
+  pub rendering_intent: u32,
+  pub tags: Vec<IccTag>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+  return u32::from_be_bytes(bytes[offset..offset+4].try_into().unwrap());
+}
+
+fn four_cc(bytes: &[u8], offset: usize) -> [u8; 4] {
+  return bytes[offset..offset+4].try_into().unwrap();
+}
+
+impl IccProfile {
+  //everything in an ICC profile is big endian
+  pub fn parse(bytes: &[u8]) -> Result<IccProfile, Error> {
+    //128 byte header, then a 4 byte tag count
+    if bytes.len() < 132 {
+      return Err(Error::InvalidProfile(format!("{} bytes is too short for a profile", bytes.len())));
+    }
+    if &bytes[36..40] != b"acsp" {
+      return Err(Error::InvalidProfile("missing the acsp signature".to_string()));
+    }
+    let size = read_u32(bytes, 0) as usize;
+    if size > bytes.len() || size < 132 {
+      return Err(Error::InvalidProfile(format!("header says the profile is {} bytes, but there are {}", size, bytes.len())));
+    }
+    let bytes = &bytes[..size];
+    let tag_count = read_u32(bytes, 128) as usize;
+    //12 bytes per tag
+    if tag_count > (size-132)/12 {
+      return Err(Error::InvalidProfile(format!("{} tags don't fit in the profile", tag_count)));
+    }
+    let mut tags: Vec<IccTag> = Vec::with_capacity(tag_count);
+    for i in 0..tag_count {
+      let entry = 132+i*12;
+      let tag = IccTag { signature: four_cc(bytes, entry), offset: read_u32(bytes, entry+4), size: read_u32(bytes, entry+8) };
+      if tag.offset as u64+tag.size as u64 > size as u64 {
+        return Err(Error::InvalidProfile(format!("tag {} is outside of the profile", String::from_utf8_lossy(&tag.signature))));
+      }
+      tags.push(tag);
+    }
+    return Ok(IccProfile {
+      bytes: bytes.to_vec(),
+      preferred_cmm: four_cc(bytes, 4),
+      version: (bytes[8], bytes[9] >> 4, bytes[9] & 0b00001111),
+      device_class: four_cc(bytes, 12),
+      color_space: four_cc(bytes, 16),
+      pcs: four_cc(bytes, 20),
+      rendering_intent: read_u32(bytes, 64),
+      tags,
+    });
+  }
+  //the data of a tag, eg: tag_data(b"wtpt")
+  pub fn tag_data(&self, signature: &[u8; 4]) -> Option<&[u8]> {
+    let tag = self.tags.iter().find(|tag| &tag.signature == signature)?;
+    return Some(&self.bytes[tag.offset as usize..(tag.offset+tag.size) as usize]);
+  }
+}
+
+//where the profiles PROFILE_LINKED bitmaps point to come from. the name is from the bitmap, so it can't be trusted
+//ProfileLoader looks for them on disk, and any Fn(&str) -> Option<Vec<u8>> can be used to get them some other way
+pub trait ProfileSource {
+  fn load_profile(&self, name: &str) -> Result<IccProfile, Error>;
+}
+
+impl<F: Fn(&str) -> Option<Vec<u8>>> ProfileSource for F {
+  fn load_profile(&self, name: &str) -> Result<IccProfile, Error> {
+    return match self(name) {
+      Some(bytes) => IccProfile::parse(&bytes),
+      None => Err(Error::DoesNotExist("linked profile file")),
+    };
+  }
+}
+
+//finds linked profiles on disk
+//the file names are usually absolute windows paths from the machine that made the bitmap, so the file name is looked for in the directories
+//only files inside the directories are read, unless absolute paths are allowed
+#[derive(Clone, Debug, Default)]
+pub struct ProfileLoader {
+  //tried first, usually the bitmap's directory
+  pub directory: Option<PathBuf>,
+  //tried in order after the directory, eg: C:\Windows\System32\spool\drivers\color or /usr/share/color/icc
+  pub search_paths: Vec<PathBuf>,
+  //also try an absolute path as it is, before the directories. off by default, since it could be anywhere
+  pub absolute_paths: bool,
+}
+
+impl ProfileLoader {
+  pub fn new() -> ProfileLoader {
+    return ProfileLoader::default();
+  }
+  //relative names are resolved from the directory the bitmap is in
+  pub fn for_bitmap(bitmap_path: &str) -> ProfileLoader {
+    let directory = Path::new(bitmap_path).parent().map(|parent| parent.to_path_buf());
+    return ProfileLoader { directory, search_paths: Vec::new(), absolute_paths: false };
+  }
+  pub fn directory(mut self, directory: &str) -> ProfileLoader {
+    self.directory = Some(PathBuf::from(directory));
+    return self;
+  }
+  pub fn search_path(mut self, search_path: &str) -> ProfileLoader {
+    self.search_paths.push(PathBuf::from(search_path));
+    return self;
+  }
+  pub fn absolute_paths(mut self, absolute_paths: bool) -> ProfileLoader {
+    self.absolute_paths = absolute_paths;
+    return self;
+  }
+  //where the profile could be, in the order they are tried
+  pub fn candidates(&self, name: &str) -> Vec<PathBuf> {
+    //windows paths use backslashes
+    let name = if std::path::MAIN_SEPARATOR == '\\' { name.to_string() } else { name.replace('\\', "/") };
+    let path = Path::new(&name);
+    let file_name = Path::new(name.rsplit('/').next().unwrap_or(&name));
+    //drive letters (C:) aren't absolute outside of windows, but shouldn't be joined onto directories either
+    let is_absolute = path.is_absolute() || name.get(1..2) == Some(":");
+    //only plain names (no .., no root) are joined, so the result can't leave the directory
+    let stays_inside = |path: &Path| path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) && path.file_name().is_some();
+    let relative = !is_absolute && stays_inside(path);
+    let file_name = if stays_inside(file_name) { Some(file_name) } else { None };
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    if is_absolute && self.absolute_paths {
+      candidates.push(path.to_path_buf());
+    }
+    for directory in self.directory.iter().chain(self.search_paths.iter()) {
+      if relative {
+        candidates.push(directory.join(path));
+      }
+      if let Some(file_name) = file_name {
+        candidates.push(directory.join(file_name));
+      }
+    }
+    if self.directory.is_none() && self.search_paths.is_empty() && relative {
+      //relative to the working directory
+      candidates.push(path.to_path_buf());
+    }
+    candidates.dedup();
+    return candidates;
+  }
+  //loads and parses the first candidate that exists
+  pub fn load(&self, name: &str) -> Result<IccProfile, Error> {
+    for candidate in self.candidates(name) {
+      if candidate.is_file() {
+        let bytes = match fs::read(&candidate) {
+          Ok(returned_bytes) => returned_bytes,
+          Err(e) => return Err(Error::Io(e)),
+        };
+        return IccProfile::parse(&bytes);
+      }
+    }
+    return Err(Error::DoesNotExist("linked profile file"));
+  }
+}
+
+impl ProfileSource for ProfileLoader {
+  fn load_profile(&self, name: &str) -> Result<IccProfile, Error> {
+    return self.load(name);
+  }
+}
+
+//windows-1252, the usual windows code page. the same as latin-1 except for 0x80 to 0x9f
+//https://en.wikipedia.org/wiki/Windows-1252
+const WINDOWS_1252_HIGH: [char; 32] = [
+  '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+  '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+  '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+  '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+//stops at the first null
+pub fn decode_windows_1252(bytes: &[u8]) -> String {
+  return bytes.iter().take_while(|byte| **byte != 0).map(|byte| match byte {
+    0x80..=0x9f => WINDOWS_1252_HIGH[(byte-0x80) as usize],
+    _ => *byte as char,
+  }).collect();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //a header and an empty tag table
+  fn minimal_profile() -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0; 132];
+    bytes[0..4].copy_from_slice(&132u32.to_be_bytes());
+    bytes[36..40].copy_from_slice(b"acsp");
+    bytes
+  }
+
+  #[test]
+  fn candidates_stay_in_the_directories() {
+    let loader = ProfileLoader::new().directory("bitmaps").search_path("profiles");
+    let absolute = if cfg!(windows) { "C:\\Windows\\color\\srgb.icc" } else { "/usr/share/color/srgb.icc" };
+    assert_eq!(loader.candidates(absolute), vec![PathBuf::from("bitmaps/srgb.icc"), PathBuf::from("profiles/srgb.icc")]);
+    assert_eq!(loader.candidates("C:\\Windows\\color\\srgb.icc"), vec![PathBuf::from("bitmaps/srgb.icc"), PathBuf::from("profiles/srgb.icc")]);
+    assert_eq!(loader.clone().absolute_paths(true).candidates(absolute)[0], PathBuf::from(absolute));
+    assert_eq!(loader.candidates("sub/srgb.icc"), vec![
+      PathBuf::from("bitmaps/sub/srgb.icc"), PathBuf::from("bitmaps/srgb.icc"), PathBuf::from("profiles/sub/srgb.icc"), PathBuf::from("profiles/srgb.icc"),
+    ]);
+    //only the file name of a path going up is used
+    assert_eq!(loader.candidates("../../etc/srgb.icc"), vec![PathBuf::from("bitmaps/srgb.icc"), PathBuf::from("profiles/srgb.icc")]);
+    assert!(loader.candidates("..").is_empty());
+    assert!(loader.candidates("sub/..").is_empty());
+    assert!(ProfileLoader::new().candidates("../srgb.icc").is_empty());
+    assert_eq!(ProfileLoader::new().candidates("srgb.icc"), vec![PathBuf::from("srgb.icc")]);
+  }
+
+  #[test]
+  fn closures_are_profile_sources() {
+    let source = |name: &str| if name == "srgb.icc" { Some(minimal_profile()) } else { None };
+    assert_eq!(source.load_profile("srgb.icc").unwrap().bytes, minimal_profile());
+    assert!(matches!(source.load_profile("other.icc"), Err(Error::DoesNotExist(_))));
+    assert!(matches!((|_: &str| Some(vec![0; 10])).load_profile("short.icc"), Err(Error::InvalidProfile(_))));
+  }
+
+  //minimal_profile with one 8 byte tag after the tag table
+  fn profile_with_tag(offset: u32, size: u32) -> Vec<u8> {
+    let mut bytes = minimal_profile();
+    bytes[128..132].copy_from_slice(&1u32.to_be_bytes());
+    bytes.extend_from_slice(b"wtpt");
+    bytes.extend_from_slice(&offset.to_be_bytes());
+    bytes.extend_from_slice(&size.to_be_bytes());
+    bytes.extend_from_slice(b"XYZ \0\0\0\0");
+    let length = bytes.len() as u32;
+    bytes[0..4].copy_from_slice(&length.to_be_bytes());
+    bytes
+  }
+
+  #[test]
+  fn parse() {
+    let mut bytes = profile_with_tag(144, 8);
+    bytes[4..8].copy_from_slice(b"lcms");
+    bytes[8..10].copy_from_slice(&[4, 0x30]);
+    bytes[12..24].copy_from_slice(b"mntrRGB XYZ ");
+    bytes[64..68].copy_from_slice(&1u32.to_be_bytes());
+    //anything after the size in the header isn't part of the profile
+    bytes.extend_from_slice(&[0xff; 4]);
+    let profile = IccProfile::parse(&bytes).unwrap();
+    assert_eq!(profile.bytes, bytes[..152]);
+    assert_eq!((&profile.preferred_cmm, profile.version, &profile.device_class, &profile.color_space, &profile.pcs), (b"lcms", (4, 3, 0), b"mntr", b"RGB ", b"XYZ "));
+    assert_eq!(profile.rendering_intent, 1);
+    assert_eq!(profile.tags, vec![IccTag { signature: *b"wtpt", offset: 144, size: 8 }]);
+    assert_eq!(profile.tag_data(b"wtpt"), Some(&b"XYZ \0\0\0\0"[..]));
+    assert_eq!(profile.tag_data(b"rXYZ"), None);
+  }
+
+  #[test]
+  fn parse_rejections() {
+    let message = |bytes: &[u8]| match IccProfile::parse(bytes) {
+      Err(Error::InvalidProfile(reason)) => reason,
+      other => panic!("expected InvalidProfile, got {:?}", other.map(|profile| profile.bytes.len())),
+    };
+    assert_eq!(message(&minimal_profile()[..131]), "131 bytes is too short for a profile");
+    let mut no_signature = minimal_profile();
+    no_signature[36..40].copy_from_slice(b"ACSP");
+    assert_eq!(message(&no_signature), "missing the acsp signature");
+    let mut too_long = minimal_profile();
+    too_long[0..4].copy_from_slice(&200u32.to_be_bytes());
+    assert_eq!(message(&too_long), "header says the profile is 200 bytes, but there are 132");
+    let mut too_many_tags = minimal_profile();
+    too_many_tags[128..132].copy_from_slice(&1u32.to_be_bytes());
+    assert_eq!(message(&too_many_tags), "1 tags don't fit in the profile");
+    //past the end, and an offset that would overflow a u32
+    assert_eq!(message(&profile_with_tag(148, 8)), "tag wtpt is outside of the profile");
+    assert_eq!(message(&profile_with_tag(u32::MAX, 8)), "tag wtpt is outside of the profile");
+    assert!(IccProfile::parse(&profile_with_tag(144, 8)).is_ok());
+  }
+}
@@ -0,0 +1,12 @@
+//Win32 field and type names, plus the explicit returns and match on errors used all through the crate
+#![allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms, clippy::needless_return, clippy::needless_late_init, clippy::question_mark)]
+
+pub mod bmp;
+pub mod quantize;
+pub mod dither;
+mod huffman;
+pub mod icc;
+pub mod color;
+mod zlib;
+mod png;
+pub mod netpbm;
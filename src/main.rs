@@ -1,5 +1,4 @@
-mod bmp;
-use crate::bmp::BMP;
+use bmp_manipulation::bmp::BMP;
 
 fn main() {
   let file = BMP::new_from_file("src/images/example.bmp");
@@ -7,7 +6,7 @@ fn main() {
   let file_size = file.get_size(true);
   println!("File size (bytes): {}", file_size);
   assert_eq!(file_size/1024, BMP::num_bytes_to_kilobytes(file_size));
-  assert_eq!((((5 as i8)-(13 as i8)).abs() as u8), 8);
+  assert_eq!((5i8-13i8).unsigned_abs(), 8);
   let dib_header = file.get_dib_header();
   //height, width, bitcount, etc dib size
   if let Ok(unwrapped_dib_header) = dib_header {
@@ -32,10 +31,10 @@ fn main() {
   //println!("{}", ttt);
   //println!("{}", ttt & 0b00000000111111110000000000000000);\
   //test color changing
-  small_file.change_color_of_pixel(10, 10, [233, 71, 255, 255]);
-  small_file.save_to_new("src/images/e.bmp");
+  small_file.change_color_of_pixel(10, 10, [233, 71, 255, 255]).unwrap();
+  small_file.save_to_new("src/images/e.bmp").unwrap();
   let mut small_file2 = BMP::new_from_file("src/images/e.bmp");
   //fill color may not be right
-  small_file2.fill_bucket([155, 42, 66, 255], 35, 40);
-  small_file2.save_to_new("src/images/e2.bmp");
+  small_file2.fill_bucket([155, 42, 66, 255], 35, 40).unwrap();
+  small_file2.save_to_new("src/images/e2.bmp").unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,491 @@
+use crate::bmp::Error;
+
+//Netpbm images, PBM (P1, P4), PGM (P2, P5), PPM (P3, P6) and PAM (P7)
+//https://netpbm.sourceforge.net/doc/pbm.html
+//https://netpbm.sourceforge.net/doc/pam.html
+//everything is read into PAM style tuples, so PBM is turned around to 0 for black and 1 for white
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetpbmFormat {
+  //black and white
+  Pbm,
+  //grayscale
+  Pgm,
+  //rgb
+  Ppm,
+  //any number of channels, the only one with alpha. there is no ascii version
+  Pam,
+}
+
+//how encode_netpbm writes the image. by default the format is picked from the image, binary with a maxval of 255
+#[derive(Clone, Debug)]
+pub struct NetpbmOptions {
+  pub format: Option<NetpbmFormat>,
+  pub ascii: bool,
+  pub maxval: u16,
+}
+
+impl Default for NetpbmOptions {
+  fn default() -> NetpbmOptions {
+    return NetpbmOptions { format: None, ascii: false, maxval: 255 };
+  }
+}
+
+impl NetpbmOptions {
+  pub fn new() -> NetpbmOptions {
+    return NetpbmOptions::default();
+  }
+  pub fn format(mut self, format: NetpbmFormat) -> NetpbmOptions {
+    self.format = Some(format);
+    return self;
+  }
+  //P1, P2 and P3 instead of P4, P5 and P6
+  pub fn ascii(mut self, ascii: bool) -> NetpbmOptions {
+    self.ascii = ascii;
+    return self;
+  }
+  //over 255 means 2 bytes per sample, 65535 for 16 bit. PBM is always 1
+  pub fn maxval(mut self, maxval: u16) -> NetpbmOptions {
+    self.maxval = maxval;
+    return self;
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetpbmImage {
+  pub width: u32,
+  pub height: u32,
+  //channels per pixel
+  pub depth: usize,
+  pub maxval: u16,
+  //BLACKANDWHITE, GRAYSCALE, RGB, with _ALPHA on the end if there is alpha
+  pub tuple_type: String,
+  //depth samples per pixel, rows from the top
+  pub samples: Vec<u16>,
+}
+
+fn invalid(reason: &str) -> Error {
+  return Error::InvalidNetpbm(reason.to_string());
+}
+
+struct HeaderReader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl HeaderReader<'_> {
+  //comments go from # to the end of the line
+  fn skip_whitespace(&mut self) {
+    while let Some(byte) = self.bytes.get(self.position) {
+      if *byte == b'#' {
+        while self.position < self.bytes.len() && self.bytes[self.position] != b'\n' && self.bytes[self.position] != b'\r' {
+          self.position += 1;
+        }
+      } else if byte.is_ascii_whitespace() {
+        self.position += 1;
+      } else {
+        break;
+      }
+    }
+  }
+  fn read_number(&mut self) -> Result<u32, Error> {
+    self.skip_whitespace();
+    let start = self.position;
+    while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_digit() {
+      self.position += 1;
+    }
+    if start == self.position {
+      return Err(invalid("expected a number"));
+    }
+    return match std::str::from_utf8(&self.bytes[start..self.position]).unwrap().parse::<u32>() {
+      Ok(number) => Ok(number),
+      Err(_) => Err(invalid("number too big")),
+    };
+  }
+  //P1 bits don't need whitespace between them
+  fn read_bit(&mut self) -> Result<u16, Error> {
+    self.skip_whitespace();
+    let bit = match self.bytes.get(self.position) {
+      Some(b'0') => 0,
+      Some(b'1') => 1,
+      Some(_) => return Err(invalid("expected a 0 or 1")),
+      None => return Err(Error::Truncated { offset: self.position, length: 1, file_size: self.bytes.len() }),
+    };
+    self.position += 1;
+    return Ok(bit);
+  }
+  //the one whitespace character between the header and a binary raster
+  fn end_header(&mut self) -> Result<(), Error> {
+    match self.bytes.get(self.position) {
+      Some(byte) if byte.is_ascii_whitespace() => {
+        self.position += 1;
+        return Ok(());
+      },
+      _ => return Err(invalid("expected whitespace after the header")),
+    }
+  }
+}
+
+//PAM headers are lines of a keyword and a value, ending with ENDHDR
+fn read_pam_header(reader: &mut HeaderReader) -> Result<(u32, u32, usize, u32, String), Error> {
+  let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+  let mut tuple_type: Vec<String> = Vec::new();
+  loop {
+    let end = match reader.bytes[reader.position..].iter().position(|byte| *byte == b'\n') {
+      Some(end) => reader.position+end,
+      None => return Err(invalid("no ENDHDR")),
+    };
+    let line = String::from_utf8_lossy(&reader.bytes[reader.position..end]).to_string();
+    reader.position = end+1;
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let (keyword, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let value = value.trim();
+    let number = value.parse::<u32>().ok();
+    match keyword {
+      "ENDHDR" => break,
+      "WIDTH" => width = number,
+      "HEIGHT" => height = number,
+      "DEPTH" => depth = number,
+      "MAXVAL" => maxval = number,
+      //can be split over several lines
+      "TUPLTYPE" => tuple_type.push(value.to_string()),
+      _ => return Err(Error::InvalidNetpbm(format!("unknown header line {}", keyword))),
+    }
+  }
+  return match (width, height, depth, maxval) {
+    (Some(width), Some(height), Some(depth), Some(maxval)) => Ok((width, height, depth as usize, maxval, tuple_type.join(" "))),
+    _ => Err(invalid("missing WIDTH, HEIGHT, DEPTH or MAXVAL")),
+  };
+}
+
+//the binary samples after the header. length is None if working it out overflowed
+fn get_raster(bytes: &[u8], position: usize, length: Option<usize>) -> Result<&[u8], Error> {
+  let length = match length {
+    Some(length) => length,
+    None => return Err(Error::Overflow { field: "raster" }),
+  };
+  return match position.checked_add(length).and_then(|end| bytes.get(position..end)) {
+    Some(raster) => Ok(raster),
+    None => Err(Error::Truncated { offset: position, length, file_size: bytes.len() }),
+  };
+}
+
+pub fn decode(bytes: &[u8]) -> Result<NetpbmImage, Error> {
+  let magic = match bytes.get(0..2) {
+    Some([b'P', magic @ b'1'..=b'7']) => *magic,
+    _ => return Err(Error::WrongFileType),
+  };
+  let mut reader = HeaderReader { bytes, position: 2 };
+  let (width, height, depth, maxval, tuple_type) = match magic {
+    b'7' => {
+      match reader.end_header() {
+        Ok(_) => {},
+        Err(e) => return Err(e),
+      };
+      match read_pam_header(&mut reader) {
+        Ok(header) => header,
+        Err(e) => return Err(e),
+      }
+    },
+    _ => {
+      let mut numbers: Vec<u32> = Vec::with_capacity(3);
+      //PBM has no maxval
+      let count = if magic == b'1' || magic == b'4' { 2 } else { 3 };
+      for _ in 0..count {
+        match reader.read_number() {
+          Ok(number) => numbers.push(number),
+          Err(e) => return Err(e),
+        };
+      }
+      let (depth, tuple_type) = match magic {
+        b'1' | b'4' => (1, "BLACKANDWHITE"),
+        b'2' | b'5' => (1, "GRAYSCALE"),
+        _ => (3, "RGB"),
+      };
+      (numbers[0], numbers[1], depth, numbers.get(2).copied().unwrap_or(1), tuple_type.to_string())
+    },
+  };
+  if maxval == 0 || maxval > 65535 {
+    return Err(Error::InvalidNetpbm(format!("maxval of {}", maxval)));
+  }
+  if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 || depth == 0 {
+    return Err(invalid("bad dimensions"));
+  }
+  let sample_count = match (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(depth)) {
+    Some(sample_count) => sample_count,
+    None => return Err(Error::Overflow { field: "sample count" }),
+  };
+  //the header could say anything, so don't allocate more than the file could hold
+  let mut samples: Vec<u16> = Vec::with_capacity(sample_count.min(bytes.len()*8));
+  match magic {
+    //ascii, 1 is black
+    b'1' => {
+      for _ in 0..sample_count {
+        match reader.read_bit() {
+          Ok(bit) => samples.push(1-bit),
+          Err(e) => return Err(e),
+        };
+      }
+    },
+    b'2' | b'3' => {
+      for _ in 0..sample_count {
+        match reader.read_number() {
+          Ok(sample) => samples.push(sample.min(maxval) as u16),
+          Err(e) => return Err(e),
+        };
+      }
+    },
+    //packed bits, rows padded to a byte, 1 is black
+    b'4' => {
+      match reader.end_header() {
+        Ok(_) => {},
+        Err(e) => return Err(e),
+      };
+      let row_length = (width as usize).div_ceil(8);
+      let raster = match get_raster(bytes, reader.position, row_length.checked_mul(height as usize)) {
+        Ok(raster) => raster,
+        Err(e) => return Err(e),
+      };
+      for row in raster.chunks(row_length) {
+        for x in 0..width as usize {
+          samples.push(1-((row[x/8] >> (7-x % 8)) & 1) as u16);
+        }
+      }
+    },
+    //1 byte samples, or 2 bytes big endian if maxval is over 255
+    _ => {
+      if magic != b'7' {
+        match reader.end_header() {
+          Ok(_) => {},
+          Err(e) => return Err(e),
+        };
+      }
+      let sample_size = if maxval > 255 { 2 } else { 1 };
+      let raster = match get_raster(bytes, reader.position, sample_count.checked_mul(sample_size)) {
+        Ok(raster) => raster,
+        Err(e) => return Err(e),
+      };
+      for sample in raster.chunks(sample_size) {
+        let value = if sample_size == 2 { u16::from_be_bytes([sample[0], sample[1]]) } else { sample[0] as u16 };
+        samples.push(value.min(maxval as u16));
+      }
+    },
+  }
+  return Ok(NetpbmImage { width, height, depth, maxval: maxval as u16, tuple_type, samples });
+}
+
+//ascii samples separated by spaces, lines no longer than 70 characters
+fn write_ascii(output: &mut Vec<u8>, samples: impl Iterator<Item = u16>) {
+  let mut line_length: usize = 0;
+  for sample in samples {
+    let text = sample.to_string();
+    if line_length > 0 && line_length+1+text.len() > 70 {
+      output.push(b'\n');
+      line_length = 0;
+    } else if line_length > 0 {
+      output.push(b' ');
+      line_length += 1;
+    }
+    output.extend_from_slice(text.as_bytes());
+    line_length += text.len();
+  }
+  output.push(b'\n');
+}
+
+impl NetpbmImage {
+  //samples scaled to 0 to 255
+  pub fn to_8_bit(&self) -> Vec<u8> {
+    let maxval = self.maxval as u32;
+    return self.samples.iter().map(|sample| ((*sample as u32*255+maxval/2)/maxval) as u8).collect();
+  }
+  //the samples have to already be in the format's shape: depth 1 and maxval 1 for PBM, depth 1 for PGM, depth 3 for PPM
+  //there is no ascii PAM
+  pub fn encode(&self, format: NetpbmFormat, ascii: bool) -> Result<Vec<u8>, Error> {
+    let expected_depth = match format {
+      NetpbmFormat::Pbm | NetpbmFormat::Pgm => Some(1),
+      NetpbmFormat::Ppm => Some(3),
+      NetpbmFormat::Pam => None,
+    };
+    if expected_depth.is_some_and(|depth| depth != self.depth) {
+      return Err(Error::Unsupported("samples don't match the format's depth"));
+    } else if format == NetpbmFormat::Pbm && self.maxval != 1 {
+      return Err(Error::Unsupported("PBM needs a maxval of 1"));
+    } else if format == NetpbmFormat::Pam && ascii {
+      return Err(Error::Unsupported("there is no ascii PAM"));
+    }
+    //PBM rows are split by the width, and no format can hold an empty image
+    if self.width == 0 || self.height == 0 || self.depth == 0 {
+      return Err(invalid("bad dimensions"));
+    }
+    let expected = (self.width as usize).checked_mul(self.height as usize).and_then(|pixels| pixels.checked_mul(self.depth));
+    if expected != Some(self.samples.len()) {
+      return Err(Error::WrongDimensions { expected: expected.unwrap_or(usize::MAX), actual: self.samples.len() });
+    }
+    let mut output: Vec<u8> = Vec::with_capacity(self.samples.len()*2+64);
+    let magic = match (format, ascii) {
+      (NetpbmFormat::Pbm, true) => "P1",
+      (NetpbmFormat::Pgm, true) => "P2",
+      (NetpbmFormat::Ppm, true) => "P3",
+      (NetpbmFormat::Pbm, false) => "P4",
+      (NetpbmFormat::Pgm, false) => "P5",
+      (NetpbmFormat::Ppm, false) => "P6",
+      (NetpbmFormat::Pam, _) => "P7",
+    };
+    if format == NetpbmFormat::Pam {
+      output.extend_from_slice(format!("{}\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n", magic, self.width, self.height, self.depth, self.maxval, self.tuple_type).as_bytes());
+    } else if format == NetpbmFormat::Pbm {
+      output.extend_from_slice(format!("{}\n{} {}\n", magic, self.width, self.height).as_bytes());
+    } else {
+      output.extend_from_slice(format!("{}\n{} {}\n{}\n", magic, self.width, self.height, self.maxval).as_bytes());
+    }
+    match (format, ascii) {
+      //PBM is 1 for black
+      (NetpbmFormat::Pbm, true) => write_ascii(&mut output, self.samples.iter().map(|sample| 1-sample)),
+      (NetpbmFormat::Pbm, false) => {
+        for row in self.samples.chunks(self.width as usize) {
+          let mut packed: Vec<u8> = vec![0; row.len().div_ceil(8)];
+          for (x, sample) in row.iter().enumerate() {
+            if *sample == 0 {
+              packed[x/8] |= 0x80 >> (x % 8);
+            }
+          }
+          output.extend_from_slice(&packed);
+        }
+      },
+      (_, true) => write_ascii(&mut output, self.samples.iter().copied()),
+      _ => {
+        for sample in &self.samples {
+          if self.maxval > 255 {
+            output.extend_from_slice(&sample.to_be_bytes());
+          } else {
+            output.push(*sample as u8);
+          }
+        }
+      },
+    }
+    return Ok(output);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn image(width: u32, height: u32, depth: usize, maxval: u16, tuple_type: &str, samples: Vec<u16>) -> NetpbmImage {
+    NetpbmImage { width, height, depth, maxval, tuple_type: tuple_type.to_string(), samples }
+  }
+
+  #[test]
+  fn ascii_formats() {
+    //P1 bits don't need spaces between them
+    assert_eq!(decode(b"P1\n3 2\n1 0 1\n011\n").unwrap(), image(3, 2, 1, 1, "BLACKANDWHITE", vec![0, 1, 0, 1, 0, 0]));
+    assert_eq!(decode(b"P2\n2 2\n15\n0 5\n10 15\n").unwrap(), image(2, 2, 1, 15, "GRAYSCALE", vec![0, 5, 10, 15]));
+    assert_eq!(decode(b"P3 1 2 255 255 0 0\n0 128 255").unwrap(), image(1, 2, 3, 255, "RGB", vec![255, 0, 0, 0, 128, 255]));
+  }
+
+  #[test]
+  fn binary_formats() {
+    //rows are padded to a byte
+    assert_eq!(decode(b"P4\n10 2\n\x80\x40\xff\xc0").unwrap(), image(10, 2, 1, 1, "BLACKANDWHITE", vec![0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+    assert_eq!(decode(b"P5\n3 1\n255\n\x00\x7f\xff").unwrap(), image(3, 1, 1, 255, "GRAYSCALE", vec![0, 127, 255]));
+    assert_eq!(decode(b"P6\n1 1\n255\n\x01\x02\x03").unwrap(), image(1, 1, 3, 255, "RGB", vec![1, 2, 3]));
+    let pam = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02\x03\x04\x05\x06\x07\x08";
+    assert_eq!(decode(pam).unwrap(), image(2, 1, 4, 255, "RGB_ALPHA", vec![1, 2, 3, 4, 5, 6, 7, 8]));
+  }
+
+  #[test]
+  fn maxvals() {
+    let gray = decode(b"P2 3 1 1 0 1 1").unwrap();
+    assert_eq!((gray.maxval, gray.to_8_bit()), (1, vec![0, 255, 255]));
+    //over 255 is 2 bytes per sample, big endian
+    let gray = decode(b"P5 3 1 1000 \x00\x00\x01\xf4\x03\xe8").unwrap();
+    assert_eq!((gray.maxval, gray.samples.clone(), gray.to_8_bit()), (1000, vec![0, 500, 1000], vec![0, 128, 255]));
+    let gray = decode(b"P2 2 1 1000 999 1000").unwrap();
+    assert_eq!(gray.samples, vec![999, 1000]);
+    let gray = decode(b"P5 2 1 65535 \x80\x00\xff\xff").unwrap();
+    assert_eq!((gray.samples.clone(), gray.to_8_bit()), (vec![32768, 65535], vec![128, 255]));
+    //samples over maxval are cut down to it
+    assert_eq!(decode(b"P5 2 1 100 \x64\xc8").unwrap().samples, vec![100, 100]);
+    assert!(matches!(decode(b"P2 1 1 0 0"), Err(Error::InvalidNetpbm(_))));
+    assert!(matches!(decode(b"P2 1 1 65536 0"), Err(Error::InvalidNetpbm(_))));
+  }
+
+  #[test]
+  fn comments() {
+    let expected = image(2, 1, 1, 255, "GRAYSCALE", vec![7, 9]);
+    assert_eq!(decode(b"P2# right after the magic number 123\n2 # width\n#height 5\n1\n255\n7 9").unwrap(), expected);
+    //the one whitespace character after the maxval ends the header, even after a comment
+    assert_eq!(decode(b"P5\n# made by hand\n2 1\n# 8 bit\n255\n\x07\x09").unwrap(), expected);
+    let pam = b"P7\n# a comment\nWIDTH 2\nHEIGHT 1\n\n# another\nDEPTH 1\nMAXVAL 255\nTUPLTYPE GRAYSCALE\nENDHDR\n\x07\x09";
+    assert_eq!(decode(pam).unwrap(), expected);
+  }
+
+  #[test]
+  fn pbm_is_1_for_black() {
+    let black_then_white = image(2, 1, 1, 1, "BLACKANDWHITE", vec![0, 1]);
+    assert_eq!(decode(b"P1 2 1 1 0").unwrap(), black_then_white);
+    assert_eq!(decode(b"P4 2 1 \x80").unwrap(), black_then_white);
+    assert_eq!(black_then_white.encode(NetpbmFormat::Pbm, true).unwrap(), b"P1\n2 1\n1 0\n");
+    assert_eq!(black_then_white.encode(NetpbmFormat::Pbm, false).unwrap(), b"P4\n2 1\n\x80");
+  }
+
+  #[test]
+  fn bad_dimensions() {
+    for bytes in [&b"P2 0 1 255 "[..], b"P5 1 0 255 ", b"P1 3000000000 1 0", b"P6 1 2147483648 255 ", b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 0\nMAXVAL 255\nENDHDR\n"] {
+      assert_eq!(decode(bytes).unwrap_err().to_string(), invalid("bad dimensions").to_string());
+    }
+  }
+
+  #[test]
+  fn huge_rasters() {
+    //the sample count fits, but not once it is 2 bytes a sample
+    assert!(matches!(decode(b"P6 2147483647 2147483647 1000 "), Err(Error::Overflow { .. })));
+    assert!(matches!(decode(b"P6 2147483647 2147483647 255 "), Err(Error::Truncated { .. })));
+    assert!(matches!(decode(b"P4 2147483647 2147483647 "), Err(Error::Truncated { .. })));
+  }
+
+  #[test]
+  fn encode_checks_dimensions() {
+    let empty = image(0, 1, 1, 1, "BLACKANDWHITE", vec![]);
+    for (format, ascii) in [(NetpbmFormat::Pbm, false), (NetpbmFormat::Pbm, true), (NetpbmFormat::Pgm, false)] {
+      assert_eq!(empty.encode(format, ascii).unwrap_err().to_string(), invalid("bad dimensions").to_string());
+    }
+    let short = image(2, 2, 1, 1, "BLACKANDWHITE", vec![0, 1, 1]);
+    assert!(matches!(short.encode(NetpbmFormat::Pbm, false), Err(Error::WrongDimensions { expected: 4, actual: 3 })));
+  }
+
+  #[test]
+  fn round_trip() {
+    let pbm = image(9, 2, 1, 1, "BLACKANDWHITE", (0..18).map(|i| (i % 3 == 0) as u16).collect());
+    let mut images: Vec<(NetpbmFormat, NetpbmImage, bool)> = vec![
+      (NetpbmFormat::Pbm, pbm.clone(), true),
+      (NetpbmFormat::Pbm, pbm, false),
+      (NetpbmFormat::Pam, image(9, 2, 1, 1, "BLACKANDWHITE", (0..18).map(|i| (i % 2) as u16).collect()), false),
+    ];
+    for maxval in [1, 255, 1000, 65535] {
+      let samples = |count: u32| -> Vec<u16> { (0..count).map(|i| (i*7919 % (maxval as u32+1)) as u16).collect() };
+      for ascii in [true, false] {
+        images.push((NetpbmFormat::Pgm, image(5, 3, 1, maxval, "GRAYSCALE", samples(15)), ascii));
+        images.push((NetpbmFormat::Ppm, image(5, 3, 3, maxval, "RGB", samples(45)), ascii));
+      }
+      images.push((NetpbmFormat::Pam, image(5, 3, 2, maxval, "GRAYSCALE_ALPHA", samples(30)), false));
+      images.push((NetpbmFormat::Pam, image(5, 3, 4, maxval, "RGB_ALPHA", samples(60)), false));
+    }
+    for (format, image, ascii) in images {
+      let decoded = decode(&image.encode(format, ascii).unwrap()).unwrap();
+      assert_eq!(decoded, image, "{:?} with a maxval of {}, ascii: {}", format, image.maxval, ascii);
+    }
+  }
+
+  #[test]
+  fn encode_errors() {
+    let rgb = image(1, 1, 3, 255, "RGB", vec![1, 2, 3]);
+    assert!(matches!(rgb.encode(NetpbmFormat::Pgm, false), Err(Error::Unsupported(_))));
+    assert!(matches!(rgb.encode(NetpbmFormat::Pam, true), Err(Error::Unsupported(_))));
+    let gray = image(1, 1, 1, 255, "GRAYSCALE", vec![1]);
+    assert!(matches!(gray.encode(NetpbmFormat::Pbm, false), Err(Error::Unsupported(_))));
+  }
+}
@@ -0,0 +1,618 @@
+use std::convert::TryInto;
+use crate::bmp::Error;
+use crate::zlib;
+
+//PNG decoding (for BI_PNG bitmaps) and encoding
+//https://www.w3.org/TR/png/
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+//https://www.w3.org/TR/png/#D-CRCAppendix
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut table: [u32; 256] = [0; 256];
+  for (n, entry) in table.iter_mut().enumerate() {
+    let mut c = n as u32;
+    for _ in 0..8 {
+      c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+    }
+    *entry = c;
+  }
+  let mut crc: u32 = 0xffffffff;
+  for byte in data {
+    crc = table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+  }
+  return crc ^ 0xffffffff;
+}
+
+//(x start, y start, x step, y step) of the 7 Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorType {
+  Grayscale,
+  Rgb,
+  Indexed,
+  GrayscaleAlpha,
+  Rgba,
+}
+
+impl ColorType {
+  fn from_u8(int: u8) -> Option<ColorType> {
+    match int {
+      0 => Some(ColorType::Grayscale),
+      2 => Some(ColorType::Rgb),
+      3 => Some(ColorType::Indexed),
+      4 => Some(ColorType::GrayscaleAlpha),
+      6 => Some(ColorType::Rgba),
+      _ => None,
+    }
+  }
+  pub fn channels(self) -> usize {
+    match self {
+      ColorType::Grayscale | ColorType::Indexed => 1,
+      ColorType::GrayscaleAlpha => 2,
+      ColorType::Rgb => 3,
+      ColorType::Rgba => 4,
+    }
+  }
+  //which bit depths are allowed
+  fn allows(self, bit_depth: u8) -> bool {
+    match self {
+      ColorType::Grayscale => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+      ColorType::Indexed => matches!(bit_depth, 1 | 2 | 4 | 8),
+      _ => matches!(bit_depth, 8 | 16),
+    }
+  }
+}
+
+pub struct PngImage {
+  pub width: u32,
+  pub height: u32,
+  pub bit_depth: u8,
+  pub color_type: ColorType,
+  pub interlaced: bool,
+  //PLTE
+  pub palette: Vec<[u8; 3]>,
+  //tRNS, alpha for each palette entry, or the one transparent gray or rgb color (at the image's bit depth)
+  pub palette_alpha: Vec<u8>,
+  pub transparent_color: Option<[u16; 3]>,
+  //pHYs, only if the unit is meters
+  pub pixels_per_meter: Option<(u32, u32)>,
+  //channels() samples per pixel, rows from the top, at the image's bit depth
+  pub samples: Vec<u16>,
+}
+
+fn invalid(reason: &str) -> Error {
+  return Error::InvalidPng(reason.to_string());
+}
+
+//https://www.w3.org/TR/png/#9Filter-type-4-Paeth
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+  let p = a as i16+b as i16-c as i16;
+  let (pa, pb, pc) = ((p-a as i16).abs(), (p-b as i16).abs(), (p-c as i16).abs());
+  if pa <= pb && pa <= pc {
+    return a;
+  } else if pb <= pc {
+    return b;
+  }
+  return c;
+}
+
+//undoes the filter of a row in place, bpp is the bytes per pixel (at least 1)
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), Error> {
+  for i in 0..row.len() {
+    let left = if i >= bpp { row[i-bpp] } else { 0 };
+    let up = previous[i];
+    let up_left = if i >= bpp { previous[i-bpp] } else { 0 };
+    let predictor = match filter {
+      0 => 0,
+      1 => left,
+      2 => up,
+      3 => ((left as u16+up as u16)/2) as u8,
+      4 => paeth(left, up, up_left),
+      _ => return Err(invalid("unknown filter type")),
+    };
+    row[i] = row[i].wrapping_add(predictor);
+  }
+  return Ok(());
+}
+
+pub fn decode(bytes: &[u8]) -> Result<PngImage, Error> {
+  if !bytes.starts_with(&SIGNATURE) {
+    return Err(Error::WrongFileType);
+  }
+  let mut image: Option<PngImage> = None;
+  let mut compressed: Vec<u8> = Vec::new();
+  let mut position = SIGNATURE.len();
+  let mut ended = false;
+  while position+12 <= bytes.len() {
+    let length = u32::from_be_bytes(bytes[position..position+4].try_into().unwrap()) as usize;
+    let chunk_type: [u8; 4] = bytes[position+4..position+8].try_into().unwrap();
+    let data = match bytes.get(position+8..(position+8).saturating_add(length)) {
+      Some(data) => data,
+      None => return Err(Error::Truncated { offset: position, length: length+12, file_size: bytes.len() }),
+    };
+    let crc = match bytes.get(position+8+length..position+12+length) {
+      Some(crc) => u32::from_be_bytes(crc.try_into().unwrap()),
+      None => return Err(Error::Truncated { offset: position, length: length+12, file_size: bytes.len() }),
+    };
+    if crc32(&bytes[position+4..position+8+length]) != crc {
+      return Err(Error::InvalidPng(format!("bad crc in the {} chunk", String::from_utf8_lossy(&chunk_type))));
+    }
+    position += length+12;
+    if image.is_none() && &chunk_type != b"IHDR" {
+      return Err(invalid("the first chunk isn't IHDR"));
+    }
+    match &chunk_type {
+      b"IHDR" => {
+        if data.len() != 13 {
+          return Err(invalid("IHDR is the wrong size"));
+        }
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        let bit_depth = data[8];
+        let color_type = match ColorType::from_u8(data[9]) {
+          Some(color_type) if color_type.allows(bit_depth) => color_type,
+          _ => return Err(Error::InvalidPng(format!("color type {} can't have a bit depth of {}", data[9], bit_depth))),
+        };
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+          return Err(invalid("bad dimensions"));
+        }
+        if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+          return Err(invalid("unknown compression, filter or interlace method"));
+        }
+        image = Some(PngImage { width, height, bit_depth, color_type, interlaced: data[12] == 1, palette: Vec::new(), palette_alpha: Vec::new(), transparent_color: None, pixels_per_meter: None, samples: Vec::new() });
+      },
+      b"PLTE" => {
+        if data.len() % 3 != 0 || data.len() > 256*3 {
+          return Err(invalid("PLTE is the wrong size"));
+        }
+        image.as_mut().unwrap().palette = data.chunks(3).map(|color| [color[0], color[1], color[2]]).collect();
+      },
+      b"tRNS" => {
+        let image = image.as_mut().unwrap();
+        let read = |i: usize| -> u16 { data.get(i*2..i*2+2).map_or(0, |sample| u16::from_be_bytes(sample.try_into().unwrap())) };
+        match image.color_type {
+          ColorType::Indexed => image.palette_alpha = data.to_vec(),
+          ColorType::Grayscale => image.transparent_color = Some([read(0); 3]),
+          ColorType::Rgb => image.transparent_color = Some([read(0), read(1), read(2)]),
+          _ => {},
+        }
+      },
+      //unit 1 is meters, 0 is only an aspect ratio
+      b"pHYs" if data.len() == 9 && data[8] == 1 => {
+        image.as_mut().unwrap().pixels_per_meter = Some((u32::from_be_bytes(data[0..4].try_into().unwrap()), u32::from_be_bytes(data[4..8].try_into().unwrap())));
+      },
+      b"IDAT" => compressed.extend_from_slice(data),
+      b"IEND" => {
+        ended = true;
+        break;
+      },
+      //ancillary chunks that aren't needed
+      _ => {},
+    }
+  }
+  let mut image = match image {
+    Some(image) => image,
+    None => return Err(invalid("no IHDR")),
+  };
+  if !ended {
+    return Err(Error::Truncated { offset: position, length: 12, file_size: bytes.len() });
+  }
+  if image.color_type == ColorType::Indexed && image.palette.is_empty() {
+    return Err(invalid("no PLTE"));
+  }
+  let (width, height) = (image.width as usize, image.height as usize);
+  let channels = image.color_type.channels();
+  let bits_per_pixel = channels*image.bit_depth as usize;
+  let bpp = bits_per_pixel.div_ceil(8);
+  let passes: &[(usize, usize, usize, usize)] = if image.interlaced { &ADAM7 } else { &[(0, 0, 1, 1)] };
+  //(x start, y start, x step, y step, width, height, row length) of each pass with pixels
+  //empty passes have no rows at all, not even filter bytes
+  let mut pass_sizes: Vec<(usize, usize, usize, usize, usize, usize, usize)> = Vec::with_capacity(passes.len());
+  let mut expected_length: usize = 0;
+  for (x_start, y_start, x_step, y_step) in passes {
+    let pass_width = if width > *x_start { (width-x_start).div_ceil(*x_step) } else { 0 };
+    let pass_height = if height > *y_start { (height-y_start).div_ceil(*y_step) } else { 0 };
+    if pass_width == 0 || pass_height == 0 {
+      continue;
+    }
+    let row_length = (pass_width*bits_per_pixel).div_ceil(8);
+    expected_length = match (row_length+1).checked_mul(pass_height).and_then(|length| length.checked_add(expected_length)) {
+      Some(expected_length) => expected_length,
+      //no stream could be this long
+      None => return Err(invalid("image data is the wrong size for the dimensions")),
+    };
+    pass_sizes.push((*x_start, *y_start, *x_step, *y_step, pass_width, pass_height, row_length));
+  }
+  //the dimensions say exactly how much data there is, so a stream that inflates to more is stopped early
+  let data = match zlib::decompress(&compressed, expected_length) {
+    Ok(data) => data,
+    Err(e) => return Err(Error::InvalidPng(format!("bad image data, {}", e))),
+  };
+  if data.len() != expected_length {
+    return Err(invalid("image data is the wrong size for the dimensions"));
+  }
+  let mut samples: Vec<u16> = vec![0; width*height*channels];
+  let mut position: usize = 0;
+  for (x_start, y_start, x_step, y_step, pass_width, pass_height, row_length) in pass_sizes {
+    let mut previous: Vec<u8> = vec![0; row_length];
+    for pass_y in 0..pass_height {
+      let row = &data[position..position+1+row_length];
+      position += 1+row_length;
+      let mut row = row.to_vec();
+      let filter = row.remove(0);
+      match unfilter(filter, &mut row, &previous, bpp) {
+        Ok(_) => {},
+        Err(e) => return Err(e),
+      };
+      let y = y_start+pass_y*y_step;
+      for pass_x in 0..pass_width {
+        let x = x_start+pass_x*x_step;
+        for c in 0..channels {
+          let sample = pass_x*channels+c;
+          let value = match image.bit_depth {
+            16 => u16::from_be_bytes([row[sample*2], row[sample*2+1]]),
+            8 => row[sample] as u16,
+            //packed, leftmost pixel in the high bits
+            bit_depth => {
+              let bit = sample*bit_depth as usize;
+              ((row[bit/8] >> (8-bit_depth as usize-bit % 8)) & ((1 << bit_depth)-1)) as u16
+            },
+          };
+          samples[(y*width+x)*channels+c] = value;
+        }
+      }
+      previous = row;
+    }
+  }
+  image.samples = samples;
+  return Ok(image);
+}
+
+impl PngImage {
+  //a sample scaled to 8 bits
+  fn to_8_bit(&self, sample: u16) -> u8 {
+    match self.bit_depth {
+      16 => return ((sample as u32*255+32767)/65535) as u8,
+      8 => return sample as u8,
+      bit_depth => return (sample as u32*255/((1 << bit_depth)-1)) as u8,
+    }
+  }
+  //palette indexes and the palette, for palette and grayscale images without transparency
+  //gray levels become a palette of evenly spaced grays, 16 bit gray is cut down to 256 levels
+  pub fn to_indexed(&self) -> Option<(Vec<u8>, Vec<[u8; 3]>)> {
+    match self.color_type {
+      ColorType::Indexed if self.palette_alpha.iter().all(|alpha| *alpha == 255) => {
+        let indexes: Vec<u8> = self.samples.iter().map(|sample| *sample as u8).collect();
+        //indexes past the end of the palette are black, like in to_rgba8
+        let mut palette = self.palette.clone();
+        let highest = indexes.iter().copied().max().unwrap_or(0) as usize;
+        if palette.len() <= highest {
+          palette.resize(highest+1, [0; 3]);
+        }
+        return Some((indexes, palette));
+      },
+      ColorType::Grayscale if self.transparent_color.is_none() => {
+        let levels: usize = if self.bit_depth == 16 { 256 } else { 1 << self.bit_depth };
+        let palette: Vec<[u8; 3]> = (0..levels).map(|level| {
+          let gray = (level*255/(levels-1)) as u8;
+          return [gray, gray, gray];
+        }).collect();
+        let indexes: Vec<u8> = self.samples.iter().map(|sample| if self.bit_depth == 16 { self.to_8_bit(*sample) } else { *sample as u8 }).collect();
+        return Some((indexes, palette));
+      },
+      _ => return None,
+    }
+  }
+  //4 bytes per pixel, starting at the top left
+  pub fn to_rgba8(&self) -> Vec<u8> {
+    let channels = self.color_type.channels();
+    let mut rgba: Vec<u8> = Vec::with_capacity(self.samples.len()/channels*4);
+    for pixel in self.samples.chunks(channels) {
+      let color: [u8; 4] = match self.color_type {
+        ColorType::Grayscale | ColorType::Rgb => {
+          let transparent = match self.transparent_color {
+            Some(transparent_color) => transparent_color[..channels] == *pixel,
+            None => false,
+          };
+          let alpha = if transparent { 0 } else { 255 };
+          if channels == 1 {
+            let gray = self.to_8_bit(pixel[0]);
+            [gray, gray, gray, alpha]
+          } else {
+            [self.to_8_bit(pixel[0]), self.to_8_bit(pixel[1]), self.to_8_bit(pixel[2]), alpha]
+          }
+        },
+        ColorType::Indexed => {
+          //indexes past the end of the palette are black
+          let [red, green, blue] = self.palette.get(pixel[0] as usize).copied().unwrap_or([0; 3]);
+          [red, green, blue, self.palette_alpha.get(pixel[0] as usize).copied().unwrap_or(255)]
+        },
+        ColorType::GrayscaleAlpha => {
+          let gray = self.to_8_bit(pixel[0]);
+          [gray, gray, gray, self.to_8_bit(pixel[1])]
+        },
+        ColorType::Rgba => [self.to_8_bit(pixel[0]), self.to_8_bit(pixel[1]), self.to_8_bit(pixel[2]), self.to_8_bit(pixel[3])],
+      };
+      rgba.extend_from_slice(&color);
+    }
+    return rgba;
+  }
+}
+
+fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+  output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = output.len();
+  output.extend_from_slice(chunk_type);
+  output.extend_from_slice(data);
+  let crc = crc32(&output[start..]);
+  output.extend_from_slice(&crc.to_be_bytes());
+}
+
+//the filter that makes the row's bytes smallest (as signed numbers) tends to compress best
+//https://www.w3.org/TR/png/#12Filter-selection
+fn filter_row(row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+  let mut best: Vec<u8> = Vec::new();
+  let mut best_sum = u64::MAX;
+  for filter in 0..5u8 {
+    let mut filtered: Vec<u8> = Vec::with_capacity(row.len()+1);
+    filtered.push(filter);
+    for i in 0..row.len() {
+      let left = if i >= bpp { row[i-bpp] } else { 0 };
+      let up = previous[i];
+      let up_left = if i >= bpp { previous[i-bpp] } else { 0 };
+      let predictor = match filter {
+        0 => 0,
+        1 => left,
+        2 => up,
+        3 => ((left as u16+up as u16)/2) as u8,
+        _ => paeth(left, up, up_left),
+      };
+      filtered.push(row[i].wrapping_sub(predictor));
+    }
+    let sum: u64 = filtered[1..].iter().map(|byte| (*byte as i8).unsigned_abs() as u64).sum();
+    if sum < best_sum {
+      best_sum = sum;
+      best = filtered;
+    }
+  }
+  return best;
+}
+
+impl PngImage {
+  //a non interlaced PNG file
+  pub fn encode(&self) -> Vec<u8> {
+    let (width, height) = (self.width as usize, self.height as usize);
+    let channels = self.color_type.channels();
+    let bits_per_pixel = channels*self.bit_depth as usize;
+    let bpp = bits_per_pixel.div_ceil(8);
+    let row_length = (width*bits_per_pixel).div_ceil(8);
+    let mut filtered: Vec<u8> = Vec::with_capacity((row_length+1)*height);
+    let mut previous: Vec<u8> = vec![0; row_length];
+    for y in 0..height {
+      let mut row: Vec<u8> = vec![0; row_length];
+      for (i, sample) in self.samples[y*width*channels..(y+1)*width*channels].iter().enumerate() {
+        match self.bit_depth {
+          16 => row[i*2..i*2+2].copy_from_slice(&sample.to_be_bytes()),
+          8 => row[i] = *sample as u8,
+          //packed, leftmost pixel in the high bits
+          bit_depth => {
+            let bit = i*bit_depth as usize;
+            row[bit/8] |= (*sample as u8) << (8-bit_depth as usize-bit % 8);
+          },
+        }
+      }
+      filtered.extend_from_slice(&filter_row(&row, &previous, bpp));
+      previous = row;
+    }
+    let mut output: Vec<u8> = SIGNATURE.to_vec();
+    let color_type: u8 = match self.color_type {
+      ColorType::Grayscale => 0,
+      ColorType::Rgb => 2,
+      ColorType::Indexed => 3,
+      ColorType::GrayscaleAlpha => 4,
+      ColorType::Rgba => 6,
+    };
+    let mut header: Vec<u8> = Vec::with_capacity(13);
+    header.extend_from_slice(&self.width.to_be_bytes());
+    header.extend_from_slice(&self.height.to_be_bytes());
+    //compression, filter and interlace methods are all 0
+    header.extend_from_slice(&[self.bit_depth, color_type, 0, 0, 0]);
+    write_chunk(&mut output, b"IHDR", &header);
+    if let Some((x, y)) = self.pixels_per_meter {
+      let mut physical: Vec<u8> = Vec::with_capacity(9);
+      physical.extend_from_slice(&x.to_be_bytes());
+      physical.extend_from_slice(&y.to_be_bytes());
+      //meters
+      physical.push(1);
+      write_chunk(&mut output, b"pHYs", &physical);
+    }
+    if self.color_type == ColorType::Indexed {
+      write_chunk(&mut output, b"PLTE", &self.palette.concat());
+      if !self.palette_alpha.is_empty() {
+        write_chunk(&mut output, b"tRNS", &self.palette_alpha);
+      }
+    } else if let Some(transparent_color) = self.transparent_color {
+      let samples = if self.color_type == ColorType::Grayscale { &transparent_color[..1] } else { &transparent_color[..] };
+      write_chunk(&mut output, b"tRNS", &samples.iter().flat_map(|sample| sample.to_be_bytes()).collect::<Vec<u8>>());
+    }
+    write_chunk(&mut output, b"IDAT", &zlib::compress(&filtered));
+    write_chunk(&mut output, b"IEND", &[]);
+    return output;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //filter type 0 to 4 of a row, for making test data
+  fn filter_with(filter: u8, row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    let mut filtered: Vec<u8> = vec![filter];
+    for i in 0..row.len() {
+      let left = if i >= bpp { row[i-bpp] } else { 0 };
+      let up = previous[i];
+      let up_left = if i >= bpp { previous[i-bpp] } else { 0 };
+      let predictor = match filter {
+        0 => 0,
+        1 => left,
+        2 => up,
+        3 => ((left as u16+up as u16)/2) as u8,
+        _ => paeth(left, up, up_left),
+      };
+      filtered.push(row[i].wrapping_sub(predictor));
+    }
+    filtered
+  }
+
+  //a grayscale PNG with the given filtered (and maybe interlaced) image data
+  fn grayscale_png(width: u32, height: u32, bit_depth: u8, interlaced: bool, filtered: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = SIGNATURE.to_vec();
+    let mut header: Vec<u8> = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[bit_depth, 0, 0, 0, interlaced as u8]);
+    write_chunk(&mut output, b"IHDR", &header);
+    write_chunk(&mut output, b"IDAT", &zlib::compress(filtered));
+    write_chunk(&mut output, b"IEND", &[]);
+    output
+  }
+
+  //rows of packed samples, the way they are stored before filtering
+  fn pack(samples: &[u16], bit_depth: u8) -> Vec<u8> {
+    let mut row: Vec<u8> = vec![0; (samples.len()*bit_depth as usize).div_ceil(8)];
+    for (i, sample) in samples.iter().enumerate() {
+      match bit_depth {
+        16 => row[i*2..i*2+2].copy_from_slice(&sample.to_be_bytes()),
+        8 => row[i] = *sample as u8,
+        _ => {
+          let bit = i*bit_depth as usize;
+          row[bit/8] |= (*sample as u8) << (8-bit_depth as usize-bit % 8);
+        },
+      }
+    }
+    row
+  }
+
+  #[test]
+  fn unfilter_each_type() {
+    let previous = [50, 60, 90, 0, 200, 10];
+    let raw = [1, 2, 3, 200, 100, 7];
+    //worked out from the spec, with 2 bytes per pixel
+    let expected: [[u8; 6]; 5] = [
+      [1, 2, 3, 200, 100, 7],
+      [1, 2, 4, 202, 104, 209],
+      [51, 62, 93, 200, 44, 17],
+      [26, 32, 61, 216, 230, 120],
+      [51, 62, 93, 200, 44, 207],
+    ];
+    for (filter, expected) in expected.iter().enumerate() {
+      let mut row = raw;
+      unfilter(filter as u8, &mut row, &previous, 2).unwrap();
+      assert_eq!(&row, expected, "filter type {}", filter);
+    }
+    let mut row = raw;
+    assert!(matches!(unfilter(5, &mut row, &previous, 2), Err(Error::InvalidPng(_))));
+  }
+
+  #[test]
+  fn unfilter_undoes_filter_row() {
+    let previous: Vec<u8> = (0..24).map(|i| (i*53+7) as u8).collect();
+    let row: Vec<u8> = (0..24).map(|i| (i*i*13+100) as u8).collect();
+    for bpp in [1, 3, 4, 8] {
+      let filtered = filter_row(&row, &previous, bpp);
+      let mut unfiltered = filtered[1..].to_vec();
+      unfilter(filtered[0], &mut unfiltered, &previous, bpp).unwrap();
+      assert_eq!(unfiltered, row);
+    }
+  }
+
+  #[test]
+  fn filter_types_in_a_file() {
+    let (width, height) = (5, 5);
+    let samples: Vec<u16> = (0..width*height).map(|i| (i*i*29 % 256) as u16).collect();
+    let mut filtered: Vec<u8> = Vec::new();
+    let mut previous: Vec<u8> = vec![0; width];
+    //a different filter type on every row
+    for y in 0..height {
+      let row = pack(&samples[y*width..(y+1)*width], 8);
+      filtered.extend_from_slice(&filter_with(y as u8, &row, &previous, 1));
+      previous = row;
+    }
+    let image = decode(&grayscale_png(width as u32, height as u32, 8, false, &filtered)).unwrap();
+    assert_eq!(image.samples, samples);
+  }
+
+  //splits the image into the 7 passes, filtering every row with the next filter type
+  fn adam7_data(samples: &[u16], width: usize, height: usize, bit_depth: u8) -> Vec<u8> {
+    let bpp = (bit_depth as usize).div_ceil(8);
+    let mut filtered: Vec<u8> = Vec::new();
+    let mut filter: u8 = 0;
+    for (x_start, y_start, x_step, y_step) in ADAM7 {
+      let pass_width = if width > x_start { (width-x_start).div_ceil(x_step) } else { 0 };
+      if pass_width == 0 {
+        continue;
+      }
+      let mut previous: Vec<u8> = vec![0; (pass_width*bit_depth as usize).div_ceil(8)];
+      for y in (y_start..height).step_by(y_step) {
+        let pass_samples: Vec<u16> = (x_start..width).step_by(x_step).map(|x| samples[y*width+x]).collect();
+        let row = pack(&pass_samples, bit_depth);
+        filtered.extend_from_slice(&filter_with(filter, &row, &previous, bpp));
+        filter = (filter+1) % 5;
+        previous = row;
+      }
+    }
+    filtered
+  }
+
+  #[test]
+  fn adam7() {
+    for (width, height, bit_depth) in [(8, 8, 8), (13, 11, 8), (7, 9, 16), (10, 6, 2), (9, 5, 1), (1, 1, 8), (3, 1, 4), (1, 5, 8)] {
+      let max: u32 = (1 << bit_depth)-1;
+      let samples: Vec<u16> = (0..width*height).map(|i| ((i as u32*7919+13) % (max+1)) as u16).collect();
+      let filtered = adam7_data(&samples, width, height, bit_depth);
+      let image = decode(&grayscale_png(width as u32, height as u32, bit_depth, true, &filtered)).unwrap();
+      assert!(image.interlaced);
+      assert_eq!(image.samples, samples, "{}x{} at {} bits", width, height, bit_depth);
+    }
+  }
+
+  #[test]
+  fn data_size_has_to_match() {
+    let filtered = adam7_data(&[0; 64], 8, 8, 8);
+    assert!(decode(&grayscale_png(8, 8, 8, true, &filtered)).is_ok());
+    assert!(matches!(decode(&grayscale_png(8, 8, 8, true, &filtered[..filtered.len()-1])), Err(Error::InvalidPng(_))));
+    //one more byte is more than the dimensions allow
+    let mut longer = filtered.clone();
+    longer.push(0);
+    assert!(matches!(decode(&grayscale_png(8, 8, 8, true, &longer)), Err(Error::InvalidPng(_))));
+    assert!(matches!(decode(&grayscale_png(0x7fffffff, 0x7fffffff, 16, false, &[0; 10])), Err(Error::InvalidPng(_))));
+  }
+
+  #[test]
+  fn encode_round_trip() {
+    let color_types = [
+      (ColorType::Grayscale, vec![1, 2, 4, 8, 16]),
+      (ColorType::Rgb, vec![8, 16]),
+      (ColorType::Indexed, vec![1, 2, 4, 8]),
+      (ColorType::GrayscaleAlpha, vec![8, 16]),
+      (ColorType::Rgba, vec![8, 16]),
+    ];
+    let (width, height) = (11, 6);
+    for (color_type, bit_depths) in color_types {
+      for bit_depth in bit_depths {
+        let max: u32 = (1 << bit_depth)-1;
+        let sample_count = width*height*color_type.channels();
+        let samples: Vec<u16> = (0..sample_count).map(|i| ((i as u32*7919+13) % (max+1)) as u16).collect();
+        let palette: Vec<[u8; 3]> = if color_type == ColorType::Indexed { (0..=max).map(|i| [i as u8, 255-i as u8, 7]).collect() } else { Vec::new() };
+        let image = PngImage { width: width as u32, height: height as u32, bit_depth, color_type, interlaced: false, palette: palette.clone(), palette_alpha: Vec::new(), transparent_color: None, pixels_per_meter: Some((2835, 2835)), samples: samples.clone() };
+        let decoded = decode(&image.encode()).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.bit_depth, decoded.color_type), (image.width, image.height, bit_depth, color_type));
+        assert_eq!(decoded.samples, samples);
+        assert_eq!(decoded.palette, palette);
+        assert_eq!(decoded.pixels_per_meter, Some((2835, 2835)));
+      }
+    }
+  }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+
+//color quantization, picking a small color table that represents an image well
+//https://en.wikipedia.org/wiki/Color_quantization
+
+pub enum Quantizer {
+  //repeatedly split the box of colors with the biggest range in half
+  MedianCut,
+  //merge the least used branches of a tree of colors
+  Octree,
+  //start from median cut, then move each color to the average of the pixels closest to it
+  KMeans { iterations: usize },
+  //don't look at the image, just use a known color table
+  Fixed(FixedPalette),
+}
+
+pub enum FixedPalette {
+  //6 levels per channel, 216 colors. only Indexed8 has room for it, build_palette returns None for smaller formats
+  WebSafe,
+  //evenly spaced grays, as many as fit
+  Grayscale,
+  Custom(Vec<[u8; 3]>),
+}
+
+//every distinct color in rgba pixels and how many pixels have it. alpha is ignored
+pub fn histogram(rgba: &[u8]) -> Vec<([u8; 3], u32)> {
+  let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+  for pixel in rgba.chunks_exact(4) {
+    *counts.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+  }
+  let mut histogram: Vec<([u8; 3], u32)> = counts.into_iter().collect();
+  //hashmap order is random, keep the result the same every time
+  histogram.sort_unstable();
+  return histogram;
+}
+
+//returns None if a fixed palette has more colors than allowed
+pub fn build_palette(rgba: &[u8], max_colors: usize, quantizer: &Quantizer) -> Option<Vec<[u8; 3]>> {
+  let max_colors = max_colors.max(1);
+  if let Quantizer::Fixed(fixed_palette) = quantizer {
+    let palette = fixed(fixed_palette, max_colors);
+    if palette.len() > max_colors || palette.is_empty() {
+      return None;
+    }
+    return Some(palette);
+  }
+  let histogram = histogram(rgba);
+  if histogram.len() <= max_colors {
+    //already few enough colors
+    return Some(histogram.iter().map(|(color, _)| *color).collect());
+  }
+  match quantizer {
+    Quantizer::MedianCut => return Some(median_cut(histogram, max_colors)),
+    Quantizer::Octree => return Some(octree(&histogram, max_colors)),
+    Quantizer::KMeans { iterations } => {
+      let initial = median_cut(histogram.clone(), max_colors);
+      return Some(k_means(&histogram, initial, *iterations));
+    },
+    Quantizer::Fixed(_) => return None,
+  }
+}
+
+pub fn fixed(fixed_palette: &FixedPalette, max_colors: usize) -> Vec<[u8; 3]> {
+  match fixed_palette {
+    FixedPalette::WebSafe => {
+      let mut palette: Vec<[u8; 3]> = Vec::with_capacity(216);
+      for r in 0..6 {
+        for g in 0..6 {
+          for b in 0..6 {
+            palette.push([r*51, g*51, b*51]);
+          }
+        }
+      }
+      return palette;
+    },
+    FixedPalette::Grayscale => {
+      let levels = max_colors.clamp(2, 256);
+      return (0..levels).map(|i| {
+        let gray = (i*255/(levels-1)) as u8;
+        [gray, gray, gray]
+      }).collect();
+    },
+    FixedPalette::Custom(colors) => return colors.clone(),
+  }
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+  let dr = a[0] as i32-b[0] as i32;
+  let dg = a[1] as i32-b[1] as i32;
+  let db = a[2] as i32-b[2] as i32;
+  return (dr*dr+dg*dg+db*db) as u32;
+}
+
+pub fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> usize {
+  let mut closest: usize = 0;
+  let mut closest_distance: u32 = u32::MAX;
+  for (i, entry) in palette.iter().enumerate() {
+    let d = distance(*entry, color);
+    if d < closest_distance {
+      closest = i;
+      closest_distance = d;
+      if d == 0 {
+        break;
+      }
+    }
+  }
+  return closest;
+}
+
+//nearest() for every pixel, remembering colors already looked up since images tend to repeat them
+pub fn map_to_palette(rgba: &[u8], palette: &[[u8; 3]]) -> Vec<u8> {
+  let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+  let mut indexes: Vec<u8> = Vec::with_capacity(rgba.len()/4);
+  for pixel in rgba.chunks_exact(4) {
+    let color: [u8; 3] = [pixel[0], pixel[1], pixel[2]];
+    let index = *cache.entry(color).or_insert_with(|| nearest(palette, color) as u8);
+    indexes.push(index);
+  }
+  return indexes;
+}
+
+fn weighted_average(colors: &[([u8; 3], u32)]) -> [u8; 3] {
+  let mut sum: [u64; 3] = [0; 3];
+  let mut total: u64 = 0;
+  for (color, count) in colors {
+    for c in 0..3 {
+      sum[c] += color[c] as u64*(*count as u64);
+    }
+    total += *count as u64;
+  }
+  if total == 0 {
+    return [0, 0, 0];
+  }
+  return [((sum[0]+total/2)/total) as u8, ((sum[1]+total/2)/total) as u8, ((sum[2]+total/2)/total) as u8];
+}
+
+//(channel, range) of the channel with the biggest difference between its min and max
+fn widest_channel(colors: &[([u8; 3], u32)]) -> (usize, u8) {
+  let mut widest: (usize, u8) = (0, 0);
+  for c in 0..3 {
+    let min = colors.iter().map(|(color, _)| color[c]).min().unwrap_or(0);
+    let max = colors.iter().map(|(color, _)| color[c]).max().unwrap_or(0);
+    if max-min > widest.1 {
+      widest = (c, max-min);
+    }
+  }
+  return widest;
+}
+
+//https://en.wikipedia.org/wiki/Median_cut
+fn median_cut(histogram: Vec<([u8; 3], u32)>, max_colors: usize) -> Vec<[u8; 3]> {
+  let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![histogram];
+  while boxes.len() < max_colors {
+    //split the box with the widest range
+    let mut widest_box: Option<(usize, usize, u8)> = None;
+    for (i, colors) in boxes.iter().enumerate() {
+      if colors.len() < 2 {
+        continue;
+      }
+      let (channel, range) = widest_channel(colors);
+      if widest_box.is_none_or(|(_, _, widest_range)| range > widest_range) {
+        widest_box = Some((i, channel, range));
+      }
+    }
+    let (i, channel) = match widest_box {
+      Some((i, channel, _)) => (i, channel),
+      None => break,
+    };
+    let mut colors = boxes.swap_remove(i);
+    colors.sort_unstable_by_key(|(color, _)| color[channel]);
+    //split where half of the pixels are on each side, but keep at least one color in each box
+    let total: u64 = colors.iter().map(|(_, count)| *count as u64).sum();
+    let mut seen: u64 = 0;
+    let mut split: usize = 1;
+    for (j, (_, count)) in colors.iter().enumerate() {
+      seen += *count as u64;
+      if seen*2 >= total {
+        split = j+1;
+        break;
+      }
+    }
+    split = split.clamp(1, colors.len()-1);
+    let second_half = colors.split_off(split);
+    boxes.push(colors);
+    boxes.push(second_half);
+  }
+  return boxes.iter().map(|colors| weighted_average(colors)).collect();
+}
+
+struct OctreeNode {
+  sum: [u64; 3],
+  //pixels in this node and everything under it
+  count: u64,
+  children: [Option<usize>; 8],
+  leaf: bool,
+}
+
+//https://en.wikipedia.org/wiki/Octree#Color_quantization
+#[allow(clippy::needless_range_loop)]
+fn octree(histogram: &[([u8; 3], u32)], max_colors: usize) -> Vec<[u8; 3]> {
+  let mut nodes: Vec<OctreeNode> = vec![OctreeNode { sum: [0; 3], count: 0, children: [None; 8], leaf: false }];
+  //nodes that have children, by depth
+  let mut levels: Vec<Vec<usize>> = vec![Vec::new(); 8];
+  levels[0].push(0);
+  let mut leaves: usize = 0;
+  for (color, count) in histogram {
+    let mut node = 0;
+    nodes[node].count += *count as u64;
+    //one bit of each channel per level, most significant first
+    for depth in 0..8 {
+      let shift = 7-depth;
+      let child = ((((color[0] >> shift) & 1) << 2) | (((color[1] >> shift) & 1) << 1) | ((color[2] >> shift) & 1)) as usize;
+      let next = match nodes[node].children[child] {
+        Some(next) => next,
+        None => {
+          nodes.push(OctreeNode { sum: [0; 3], count: 0, children: [None; 8], leaf: depth == 7 });
+          let next = nodes.len()-1;
+          nodes[node].children[child] = Some(next);
+          if depth == 7 {
+            leaves += 1;
+          } else {
+            levels[depth+1].push(next);
+          }
+          next
+        },
+      };
+      node = next;
+      nodes[node].count += *count as u64;
+    }
+    for c in 0..3 {
+      nodes[node].sum[c] += color[c] as u64*(*count as u64);
+    }
+  }
+  //merge the least used nodes into their parents, deepest first, until there are few enough leaves
+  for depth in (0..8).rev() {
+    if leaves <= max_colors {
+      break;
+    }
+    let mut level = levels[depth].clone();
+    level.sort_by_key(|node| nodes[*node].count);
+    for node in level {
+      if leaves <= max_colors {
+        break;
+      }
+      let mut sum: [u64; 3] = [0; 3];
+      let mut merged: usize = 0;
+      for child in nodes[node].children.iter().flatten() {
+        for c in 0..3 {
+          sum[c] += nodes[*child].sum[c];
+        }
+        merged += 1;
+      }
+      nodes[node].sum = sum;
+      nodes[node].children = [None; 8];
+      nodes[node].leaf = true;
+      leaves = leaves+1-merged;
+    }
+  }
+  let mut palette: Vec<[u8; 3]> = Vec::with_capacity(leaves);
+  let mut stack: Vec<usize> = vec![0];
+  while let Some(node) = stack.pop() {
+    if nodes[node].leaf {
+      let count = nodes[node].count.max(1);
+      let sum = nodes[node].sum;
+      palette.push([((sum[0]+count/2)/count) as u8, ((sum[1]+count/2)/count) as u8, ((sum[2]+count/2)/count) as u8]);
+    } else {
+      stack.extend(nodes[node].children.iter().flatten());
+    }
+  }
+  return palette;
+}
+
+//https://en.wikipedia.org/wiki/K-means_clustering
+fn k_means(histogram: &[([u8; 3], u32)], mut centroids: Vec<[u8; 3]>, iterations: usize) -> Vec<[u8; 3]> {
+  for _ in 0..iterations {
+    let mut sums: Vec<[u64; 4]> = vec![[0; 4]; centroids.len()];
+    for (color, count) in histogram {
+      let closest = nearest(&centroids, *color);
+      for c in 0..3 {
+        sums[closest][c] += color[c] as u64*(*count as u64);
+      }
+      sums[closest][3] += *count as u64;
+    }
+    let mut moved = false;
+    for (centroid, sum) in centroids.iter_mut().zip(sums) {
+      //a centroid nothing is closest to stays where it is
+      if sum[3] == 0 {
+        continue;
+      }
+      let total = sum[3];
+      let new_centroid: [u8; 3] = [((sum[0]+total/2)/total) as u8, ((sum[1]+total/2)/total) as u8, ((sum[2]+total/2)/total) as u8];
+      if new_centroid != *centroid {
+        *centroid = new_centroid;
+        moved = true;
+      }
+    }
+    if !moved {
+      break;
+    }
+  }
+  return centroids;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //256 different colors
+  fn gradient() -> Vec<u8> {
+    let mut rgba: Vec<u8> = Vec::with_capacity(256*4);
+    for y in 0..16u8 {
+      for x in 0..16u8 {
+        rgba.extend_from_slice(&[x*17, y*17, 255-x*8-y*7, 255]);
+      }
+    }
+    rgba
+  }
+
+  //4 groups of close colors, in a different order on every channel so a split on any of them doesn't mix groups
+  fn clusters() -> Vec<u8> {
+    let centers: [[u8; 3]; 4] = [[20, 30, 40], [200, 60, 80], [60, 200, 120], [100, 100, 220]];
+    let mut rgba: Vec<u8> = Vec::new();
+    for center in centers {
+      for offset in [[0, 0, 0], [3, 1, 1], [1, 3, 1], [1, 1, 3], [1, 1, 1]] {
+        rgba.extend_from_slice(&[center[0]+offset[0], center[1]+offset[1], center[2]+offset[2], 255]);
+      }
+    }
+    rgba
+  }
+
+  #[test]
+  fn palette_sizes() {
+    let rgba = gradient();
+    assert_eq!(histogram(&rgba).len(), 256);
+    for quantizer in [Quantizer::MedianCut, Quantizer::Octree, Quantizer::KMeans { iterations: 5 }] {
+      for max_colors in [2, 4, 16, 256] {
+        let palette = build_palette(&rgba, max_colors, &quantizer).unwrap();
+        assert!(!palette.is_empty() && palette.len() <= max_colors, "{} {}", max_colors, palette.len());
+        if !matches!(quantizer, Quantizer::Octree) {
+          //these split until there are exactly max_colors boxes
+          assert_eq!(palette.len(), max_colors);
+        }
+      }
+      //few enough colors already, they are used as they are
+      let few = &rgba[..3*4];
+      assert_eq!(build_palette(few, 16, &quantizer).unwrap(), histogram(few).iter().map(|(color, _)| *color).collect::<Vec<[u8; 3]>>());
+    }
+  }
+
+  #[test]
+  fn clusters_are_found() {
+    let rgba = clusters();
+    for (i, quantizer) in [Quantizer::MedianCut, Quantizer::Octree, Quantizer::KMeans { iterations: 5 }].iter().enumerate() {
+      let mut palette = build_palette(&rgba, 4, quantizer).unwrap();
+      palette.sort_unstable();
+      assert_eq!(palette, [[20, 30, 40], [60, 200, 120], [100, 100, 220], [200, 60, 80]].iter().map(|center: &[u8; 3]| {
+        //the average of each group of 5
+        [center[0]+1, center[1]+1, center[2]+1]
+      }).collect::<Vec<[u8; 3]>>(), "{}", ["median cut", "octree", "k-means"][i]);
+    }
+  }
+
+  #[test]
+  fn mapping() {
+    let palette: [[u8; 3]; 3] = [[0, 0, 0], [255, 255, 255], [255, 0, 0]];
+    let rgba: Vec<u8> = [[10, 10, 10], [250, 240, 245], [200, 30, 20], [128, 0, 0], [127, 127, 127], [0, 0, 0]].iter().flat_map(|rgb: &[u8; 3]| [rgb[0], rgb[1], rgb[2], 255]).collect();
+    assert_eq!(map_to_palette(&rgba, &palette), vec![0, 1, 2, 2, 0, 0]);
+    assert_eq!(nearest(&palette, [255, 255, 255]), 1);
+    //ties go to the first entry
+    assert_eq!(nearest(&[[0, 0, 0], [2, 0, 0], [0, 0, 0]], [1, 0, 0]), 0);
+  }
+
+  #[test]
+  fn fixed_palettes() {
+    let web_safe = build_palette(&[], 256, &Quantizer::Fixed(FixedPalette::WebSafe)).unwrap();
+    assert_eq!(web_safe.len(), 216);
+    assert_eq!((web_safe[0], web_safe[215], web_safe[6*6+2*6+3]), ([0, 0, 0], [255, 255, 255], [51, 102, 153]));
+    //216 colors don't fit in 1, 2 or 4 bits
+    for max_colors in [2, 4, 16] {
+      assert_eq!(build_palette(&[], max_colors, &Quantizer::Fixed(FixedPalette::WebSafe)), None);
+    }
+    assert_eq!(build_palette(&[], 4, &Quantizer::Fixed(FixedPalette::Grayscale)).unwrap(), vec![[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]]);
+    assert_eq!(build_palette(&[], 2, &Quantizer::Fixed(FixedPalette::Custom(vec![[1, 2, 3]; 3]))), None);
+    assert_eq!(build_palette(&[], 2, &Quantizer::Fixed(FixedPalette::Custom(Vec::new()))), None);
+  }
+}
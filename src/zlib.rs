@@ -0,0 +1,757 @@
+use std::collections::BinaryHeap;
+use std::cmp::Reverse;
+
+//zlib streams, which is how PNG compresses its image data
+//https://www.rfc-editor.org/rfc/rfc1950 (zlib)
+//https://www.rfc-editor.org/rfc/rfc1951 (deflate)
+
+//length codes 257 to 285
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+//distance codes 0 to 29
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+//the order code length code lengths are stored in, for dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+//https://en.wikipedia.org/wiki/Adler-32
+pub fn adler32(data: &[u8]) -> u32 {
+  let mut a: u32 = 1;
+  let mut b: u32 = 0;
+  //5552 bytes is the most that can be added before b could overflow
+  for chunk in data.chunks(5552) {
+    for byte in chunk {
+      a += *byte as u32;
+      b += a;
+    }
+    a %= 65521;
+    b %= 65521;
+  }
+  return (b << 16) | a;
+}
+
+//least significant bit first
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+  buffer: u64,
+  count: u32,
+}
+
+impl BitReader<'_> {
+  fn refill(&mut self) {
+    while self.count <= 56 && self.position < self.data.len() {
+      self.buffer |= (self.data[self.position] as u64) << self.count;
+      self.position += 1;
+      self.count += 8;
+    }
+  }
+  //the next n bits without using them, padded with zeros past the end
+  fn peek(&mut self, n: u32) -> u32 {
+    self.refill();
+    return (self.buffer & ((1u64 << n)-1)) as u32;
+  }
+  fn consume(&mut self, n: u32) -> Result<(), String> {
+    if n > self.count {
+      return Err("unexpected end of data".to_string());
+    }
+    self.buffer >>= n;
+    self.count -= n;
+    return Ok(());
+  }
+  fn bits(&mut self, n: u32) -> Result<u32, String> {
+    let value = self.peek(n);
+    return match self.consume(n) {
+      Ok(_) => Ok(value),
+      Err(e) => Err(e),
+    };
+  }
+  //stored blocks and the adler32 start on a byte
+  fn align_to_byte(&mut self) {
+    let extra = self.count % 8;
+    self.buffer >>= extra;
+    self.count -= extra;
+  }
+}
+
+//canonical huffman code, looked up with max_length bits at a time
+struct Huffman {
+  //indexed by the next max_length bits, (symbol, code length). a length of 0 means the bits aren't a code
+  table: Vec<(u16, u8)>,
+  max_length: u32,
+}
+
+impl Huffman {
+  //codes are given out in order of length, then symbol
+  fn new(lengths: &[u8]) -> Result<Huffman, String> {
+    let max_length = lengths.iter().copied().max().unwrap_or(0) as u32;
+    let mut counts: [u32; 16] = [0; 16];
+    for length in lengths {
+      counts[*length as usize] += 1;
+    }
+    counts[0] = 0;
+    //over subscribed, more codes than fit in the lengths
+    let mut left: i32 = 1;
+    for count in &counts[1..] {
+      left = (left << 1)-*count as i32;
+      if left < 0 {
+        return Err("invalid huffman code lengths".to_string());
+      }
+    }
+    let mut next_code: [u32; 16] = [0; 16];
+    let mut code: u32 = 0;
+    for length in 1..16 {
+      code = (code+counts[length-1]) << 1;
+      next_code[length] = code;
+    }
+    let mut table: Vec<(u16, u8)> = vec![(0, 0); 1 << max_length];
+    for (symbol, length) in lengths.iter().enumerate() {
+      let length = *length as u32;
+      if length == 0 {
+        continue;
+      }
+      let code = next_code[length as usize];
+      next_code[length as usize] += 1;
+      //huffman codes are stored most significant bit first, but read least significant bit first
+      let mut reversed: u32 = 0;
+      for i in 0..length {
+        reversed |= ((code >> i) & 1) << (length-1-i);
+      }
+      let mut index = reversed as usize;
+      while index < table.len() {
+        table[index] = (symbol as u16, length as u8);
+        index += 1 << length;
+      }
+    }
+    return Ok(Huffman { table, max_length });
+  }
+  fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+    let (symbol, length) = self.table[reader.peek(self.max_length) as usize];
+    if length == 0 {
+      return Err("invalid huffman code".to_string());
+    }
+    return match reader.consume(length as u32) {
+      Ok(_) => Ok(symbol),
+      Err(e) => Err(e),
+    };
+  }
+}
+
+fn fixed_huffman() -> (Huffman, Huffman) {
+  let mut lengths: [u8; 288] = [8; 288];
+  lengths[144..256].fill(9);
+  lengths[256..280].fill(7);
+  return (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap());
+}
+
+fn dynamic_huffman(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+  let header = match reader.bits(14) {
+    Ok(header) => header,
+    Err(e) => return Err(e),
+  };
+  let literal_count = (header & 0b11111) as usize+257;
+  let distance_count = ((header >> 5) & 0b11111) as usize+1;
+  let code_length_count = (header >> 10) as usize+4;
+  let mut code_length_lengths: [u8; 19] = [0; 19];
+  for i in 0..code_length_count {
+    code_length_lengths[CODE_LENGTH_ORDER[i]] = match reader.bits(3) {
+      Ok(length) => length as u8,
+      Err(e) => return Err(e),
+    };
+  }
+  let code_lengths = match Huffman::new(&code_length_lengths) {
+    Ok(code_lengths) => code_lengths,
+    Err(e) => return Err(e),
+  };
+  //literal/length and distance code lengths are run length encoded together
+  let mut lengths: Vec<u8> = Vec::with_capacity(literal_count+distance_count);
+  while lengths.len() < literal_count+distance_count {
+    let symbol = match code_lengths.decode(reader) {
+      Ok(symbol) => symbol,
+      Err(e) => return Err(e),
+    };
+    let (repeat, extra_bits, base) = match symbol {
+      0..=15 => {
+        lengths.push(symbol as u8);
+        continue;
+      },
+      16 => match lengths.last() {
+        Some(previous) => (*previous, 2, 3),
+        None => return Err("repeat with no previous code length".to_string()),
+      },
+      17 => (0, 3, 3),
+      _ => (0, 7, 11),
+    };
+    let count = match reader.bits(extra_bits) {
+      Ok(extra) => extra as usize+base,
+      Err(e) => return Err(e),
+    };
+    if lengths.len()+count > literal_count+distance_count {
+      return Err("too many code lengths".to_string());
+    }
+    lengths.extend(std::iter::repeat_n(repeat, count));
+  }
+  if lengths[256] == 0 {
+    return Err("no end of block code".to_string());
+  }
+  let literals = match Huffman::new(&lengths[..literal_count]) {
+    Ok(literals) => literals,
+    Err(e) => return Err(e),
+  };
+  let distances = match Huffman::new(&lengths[literal_count..]) {
+    Ok(distances) => distances,
+    Err(e) => return Err(e),
+  };
+  return Ok((literals, distances));
+}
+
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, limit: usize, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+  loop {
+    let symbol = match literals.decode(reader) {
+      Ok(symbol) => symbol as usize,
+      Err(e) => return Err(e),
+    };
+    if symbol < 256 {
+      if output.len() >= limit {
+        return Err(TOO_LONG.to_string());
+      }
+      output.push(symbol as u8);
+      continue;
+    } else if symbol == 256 {
+      return Ok(());
+    } else if symbol > 285 {
+      return Err("invalid length code".to_string());
+    }
+    let length = match reader.bits(LENGTH_EXTRA[symbol-257] as u32) {
+      Ok(extra) => LENGTH_BASE[symbol-257] as usize+extra as usize,
+      Err(e) => return Err(e),
+    };
+    let distance_symbol = match distances.decode(reader) {
+      Ok(distance_symbol) => distance_symbol as usize,
+      Err(e) => return Err(e),
+    };
+    if distance_symbol >= 30 {
+      return Err("invalid distance code".to_string());
+    }
+    let distance = match reader.bits(DISTANCE_EXTRA[distance_symbol] as u32) {
+      Ok(extra) => DISTANCE_BASE[distance_symbol] as usize+extra as usize,
+      Err(e) => return Err(e),
+    };
+    if distance > output.len() {
+      return Err("distance goes back past the start".to_string());
+    }
+    if output.len()+length > limit {
+      return Err(TOO_LONG.to_string());
+    }
+    //the copy can overlap what it is writing, so go a byte at a time
+    let start = output.len()-distance;
+    for i in 0..length {
+      output.push(output[start+i]);
+    }
+  }
+}
+
+const TOO_LONG: &str = "more data than expected";
+
+//raw deflate data, returns the data and how many bytes were used
+//limit is the most output there should be, so a small stream can't inflate to something huge
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+  let mut reader = BitReader { data, position: 0, buffer: 0, count: 0 };
+  let mut output: Vec<u8> = Vec::new();
+  loop {
+    let header = match reader.bits(3) {
+      Ok(header) => header,
+      Err(e) => return Err(e),
+    };
+    let result = match header >> 1 {
+      //stored
+      0 => {
+        reader.align_to_byte();
+        let (length, inverse) = match (reader.bits(16), reader.bits(16)) {
+          (Ok(length), Ok(inverse)) => (length, inverse),
+          (Err(e), _) | (_, Err(e)) => return Err(e),
+        };
+        if length != !inverse & 0xffff {
+          return Err("stored block length doesn't match its complement".to_string());
+        }
+        if output.len()+length as usize > limit {
+          return Err(TOO_LONG.to_string());
+        }
+        (0..length).try_for_each(|_| match reader.bits(8) {
+          Ok(byte) => {
+            output.push(byte as u8);
+            Ok(())
+          },
+          Err(e) => Err(e),
+        })
+      },
+      1 => {
+        let (literals, distances) = fixed_huffman();
+        inflate_block(&mut reader, &mut output, limit, &literals, &distances)
+      },
+      2 => match dynamic_huffman(&mut reader) {
+        Ok((literals, distances)) => inflate_block(&mut reader, &mut output, limit, &literals, &distances),
+        Err(e) => Err(e),
+      },
+      _ => Err("invalid block type".to_string()),
+    };
+    if let Err(e) = result {
+      return Err(e);
+    }
+    //last block
+    if header & 1 == 1 {
+      break;
+    }
+  }
+  reader.align_to_byte();
+  //bytes read into the buffer but not used
+  let used = reader.position-(reader.count/8) as usize;
+  return Ok((output, used));
+}
+
+//a zlib stream: 2 byte header, deflate data, then the adler32 of the uncompressed data
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+  if data.len() < 6 {
+    return Err("too short".to_string());
+  }
+  let (cmf, flg) = (data[0], data[1]);
+  if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+    return Err("not deflate compression".to_string());
+  }
+  if !(cmf as u32*256+flg as u32).is_multiple_of(31) {
+    return Err("bad header check".to_string());
+  }
+  if flg & 0x20 != 0 {
+    return Err("preset dictionaries aren't supported".to_string());
+  }
+  let (output, used) = match inflate(&data[2..], limit) {
+    Ok(inflated) => inflated,
+    Err(e) => return Err(e),
+  };
+  let checksum = match data.get(2+used..2+used+4) {
+    Some(checksum) => u32::from_be_bytes(checksum.try_into().unwrap()),
+    None => return Err("missing adler32".to_string()),
+  };
+  if checksum != adler32(&output) {
+    return Err("adler32 doesn't match".to_string());
+  }
+  return Ok(output);
+}
+
+//compression
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+//how many earlier positions with the same hash are checked, more is smaller but slower
+const MAX_CHAIN: usize = 64;
+//symbols per block, each block gets its own huffman codes
+const BLOCK_SIZE: usize = 65536;
+
+//least significant bit first
+struct BitWriter {
+  output: Vec<u8>,
+  buffer: u64,
+  count: u32,
+}
+
+impl BitWriter {
+  fn write(&mut self, bits: u32, n: u32) {
+    self.buffer |= (bits as u64) << self.count;
+    self.count += n;
+    while self.count >= 8 {
+      self.output.push(self.buffer as u8);
+      self.buffer >>= 8;
+      self.count -= 8;
+    }
+  }
+  //huffman codes go most significant bit first
+  fn write_code(&mut self, code: u16, length: u8) {
+    let mut reversed: u32 = 0;
+    for i in 0..length {
+      reversed |= ((code as u32 >> i) & 1) << (length-1-i);
+    }
+    self.write(reversed, length as u32);
+  }
+  fn align_to_byte(&mut self) {
+    if self.count > 0 {
+      self.write(0, 8-self.count);
+    }
+  }
+}
+
+//a literal byte, or a (length, distance) copy of earlier data
+#[derive(Clone, Copy)]
+enum Symbol {
+  Literal(u8),
+  Copy { length: u16, distance: u16 },
+}
+
+//code and number of extra bits for a length or distance
+fn length_code(length: u16) -> usize {
+  return LENGTH_BASE.iter().rposition(|base| *base <= length).unwrap();
+}
+
+fn distance_code(distance: u16) -> usize {
+  return DISTANCE_BASE.iter().rposition(|base| *base <= distance).unwrap();
+}
+
+//LZ77 with hash chains, greedy
+fn find_matches(data: &[u8]) -> Vec<Symbol> {
+  let hash = |i: usize| ((data[i] as usize) << 10 ^ (data[i+1] as usize) << 5 ^ data[i+2] as usize) & (WINDOW_SIZE-1);
+  //most recent position with each hash, and the position before that with the same hash
+  let mut head: Vec<usize> = vec![usize::MAX; WINDOW_SIZE];
+  let mut previous: Vec<usize> = vec![usize::MAX; WINDOW_SIZE];
+  let mut symbols: Vec<Symbol> = Vec::with_capacity(data.len()/2);
+  let insert = |head: &mut Vec<usize>, previous: &mut Vec<usize>, i: usize| {
+    if i+MIN_MATCH <= data.len() {
+      let h = hash(i);
+      previous[i % WINDOW_SIZE] = head[h];
+      head[h] = i;
+    }
+  };
+  let mut i: usize = 0;
+  while i < data.len() {
+    let mut best_length: usize = 0;
+    let mut best_distance: usize = 0;
+    if i+MIN_MATCH <= data.len() {
+      let mut candidate = head[hash(i)];
+      let mut chain: usize = 0;
+      let max_length = MAX_MATCH.min(data.len()-i);
+      while candidate != usize::MAX && i-candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+        let mut length: usize = 0;
+        while length < max_length && data[candidate+length] == data[i+length] {
+          length += 1;
+        }
+        if length > best_length {
+          best_length = length;
+          best_distance = i-candidate;
+          if length == max_length {
+            break;
+          }
+        }
+        let next = previous[candidate % WINDOW_SIZE];
+        //positions that old have been written over
+        if next == usize::MAX || next >= candidate {
+          break;
+        }
+        candidate = next;
+        chain += 1;
+      }
+    }
+    if best_length >= MIN_MATCH {
+      symbols.push(Symbol::Copy { length: best_length as u16, distance: best_distance as u16 });
+      for j in i..i+best_length {
+        insert(&mut head, &mut previous, j);
+      }
+      i += best_length;
+    } else {
+      symbols.push(Symbol::Literal(data[i]));
+      insert(&mut head, &mut previous, i);
+      i += 1;
+    }
+  }
+  return symbols;
+}
+
+//huffman code lengths from how often each symbol is used, none longer than max_length
+fn code_lengths(frequencies: &[u32], max_length: u8) -> Vec<u8> {
+  let mut frequencies = frequencies.to_vec();
+  loop {
+    let mut lengths: Vec<u8> = vec![0; frequencies.len()];
+    let used: Vec<usize> = (0..frequencies.len()).filter(|symbol| frequencies[*symbol] > 0).collect();
+    if used.len() == 1 {
+      lengths[used[0]] = 1;
+      return lengths;
+    } else if used.is_empty() {
+      return lengths;
+    }
+    //nodes are (frequency, children), leaves have no children
+    let mut nodes: Vec<(u64, Option<(usize, usize)>)> = used.iter().map(|symbol| (frequencies[*symbol] as u64, None)).collect();
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = (0..nodes.len()).map(|node| Reverse((nodes[node].0, node))).collect();
+    while heap.len() > 1 {
+      let Reverse((frequency_a, a)) = heap.pop().unwrap();
+      let Reverse((frequency_b, b)) = heap.pop().unwrap();
+      nodes.push((frequency_a+frequency_b, Some((a, b))));
+      heap.push(Reverse((frequency_a+frequency_b, nodes.len()-1)));
+    }
+    //depth of every leaf
+    let mut stack: Vec<(usize, u8)> = vec![(nodes.len()-1, 0)];
+    let mut too_long = false;
+    while let Some((node, depth)) = stack.pop() {
+      match nodes[node].1 {
+        Some((a, b)) => {
+          stack.push((a, depth+1));
+          stack.push((b, depth+1));
+        },
+        None => {
+          too_long |= depth > max_length;
+          lengths[used[node]] = depth;
+        },
+      }
+    }
+    if !too_long {
+      return lengths;
+    }
+    //flatten the frequencies and try again
+    for frequency in frequencies.iter_mut() {
+      if *frequency > 0 {
+        *frequency = (*frequency >> 1) | 1;
+      }
+    }
+  }
+}
+
+//the same canonical codes as Huffman::new
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+  let mut counts: [u16; 16] = [0; 16];
+  for length in lengths {
+    counts[*length as usize] += 1;
+  }
+  counts[0] = 0;
+  let mut next_code: [u16; 16] = [0; 16];
+  let mut code: u16 = 0;
+  for length in 1..16 {
+    code = (code+counts[length-1]) << 1;
+    next_code[length] = code;
+  }
+  return lengths.iter().map(|length| {
+    if *length == 0 {
+      return 0;
+    }
+    let code = next_code[*length as usize];
+    next_code[*length as usize] += 1;
+    return code;
+  }).collect();
+}
+
+//run length encodes code lengths with 16 (repeat the last), 17 and 18 (zeros), as (symbol, extra bits)
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+  let mut encoded: Vec<(u8, u8)> = Vec::new();
+  let mut i: usize = 0;
+  while i < lengths.len() {
+    let length = lengths[i];
+    let mut run: usize = 1;
+    while i+run < lengths.len() && lengths[i+run] == length {
+      run += 1;
+    }
+    i += run;
+    if length == 0 {
+      while run >= 11 {
+        let count = run.min(138);
+        encoded.push((18, (count-11) as u8));
+        run -= count;
+      }
+      if run >= 3 {
+        encoded.push((17, (run-3) as u8));
+        run = 0;
+      }
+    } else {
+      encoded.push((length, 0));
+      run -= 1;
+      while run >= 3 {
+        let count = run.min(6);
+        encoded.push((16, (count-3) as u8));
+        run -= count;
+      }
+    }
+    for _ in 0..run {
+      encoded.push((length, 0));
+    }
+  }
+  return encoded;
+}
+
+fn write_block(writer: &mut BitWriter, symbols: &[Symbol], last: bool) {
+  let mut literal_frequencies: [u32; 286] = [0; 286];
+  let mut distance_frequencies: [u32; 30] = [0; 30];
+  for symbol in symbols {
+    match symbol {
+      Symbol::Literal(byte) => literal_frequencies[*byte as usize] += 1,
+      Symbol::Copy { length, distance } => {
+        literal_frequencies[257+length_code(*length)] += 1;
+        distance_frequencies[distance_code(*distance)] += 1;
+      },
+    }
+  }
+  //end of block
+  literal_frequencies[256] = 1;
+  let literal_lengths = code_lengths(&literal_frequencies, 15);
+  let mut distance_lengths = code_lengths(&distance_frequencies, 15);
+  //there has to be at least one distance code, even if it is never used
+  if distance_lengths.iter().all(|length| *length == 0) {
+    distance_lengths[0] = 1;
+  }
+  let literal_count = 257.max(literal_lengths.iter().rposition(|length| *length > 0).unwrap_or(0)+1);
+  let distance_count = 1.max(distance_lengths.iter().rposition(|length| *length > 0).unwrap_or(0)+1);
+  let mut all_lengths: Vec<u8> = literal_lengths[..literal_count].to_vec();
+  all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+  let encoded = encode_code_lengths(&all_lengths);
+  let mut code_length_frequencies: [u32; 19] = [0; 19];
+  for (symbol, _) in &encoded {
+    code_length_frequencies[*symbol as usize] += 1;
+  }
+  let code_length_lengths = code_lengths(&code_length_frequencies, 7);
+  let code_length_count = 4.max(CODE_LENGTH_ORDER.iter().rposition(|symbol| code_length_lengths[*symbol] > 0).unwrap_or(0)+1);
+  //header, dynamic huffman codes
+  writer.write(last as u32 | 0b100, 3);
+  writer.write((literal_count-257) as u32, 5);
+  writer.write((distance_count-1) as u32, 5);
+  writer.write((code_length_count-4) as u32, 4);
+  for symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+    writer.write(code_length_lengths[*symbol] as u32, 3);
+  }
+  let code_length_codes = canonical_codes(&code_length_lengths);
+  for (symbol, extra) in encoded {
+    writer.write_code(code_length_codes[symbol as usize], code_length_lengths[symbol as usize]);
+    match symbol {
+      16 => writer.write(extra as u32, 2),
+      17 => writer.write(extra as u32, 3),
+      18 => writer.write(extra as u32, 7),
+      _ => {},
+    }
+  }
+  let literal_codes = canonical_codes(&literal_lengths);
+  let distance_codes = canonical_codes(&distance_lengths);
+  for symbol in symbols {
+    match symbol {
+      Symbol::Literal(byte) => writer.write_code(literal_codes[*byte as usize], literal_lengths[*byte as usize]),
+      Symbol::Copy { length, distance } => {
+        let code = length_code(*length);
+        writer.write_code(literal_codes[257+code], literal_lengths[257+code]);
+        writer.write((length-LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code] as u32);
+        let code = distance_code(*distance);
+        writer.write_code(distance_codes[code], distance_lengths[code]);
+        writer.write((distance-DISTANCE_BASE[code]) as u32, DISTANCE_EXTRA[code] as u32);
+      },
+    }
+  }
+  writer.write_code(literal_codes[256], literal_lengths[256]);
+}
+
+//raw deflate data
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+  let mut writer = BitWriter { output: Vec::with_capacity(data.len()/2), buffer: 0, count: 0 };
+  let symbols = find_matches(data);
+  if symbols.is_empty() {
+    //one empty stored block
+    writer.write(1, 3);
+    writer.align_to_byte();
+    writer.write(0, 16);
+    writer.write(0xffff, 16);
+    return writer.output;
+  }
+  let block_count = symbols.len().div_ceil(BLOCK_SIZE);
+  for (i, block) in symbols.chunks(BLOCK_SIZE).enumerate() {
+    write_block(&mut writer, block, i == block_count-1);
+  }
+  writer.align_to_byte();
+  //data that doesn't compress (like random bytes) is smaller as stored blocks, which have 5 bytes of header per 65535 bytes
+  let stored_size = data.len()+data.len().div_ceil(65535)*5;
+  if writer.output.len() > stored_size {
+    let mut output: Vec<u8> = Vec::with_capacity(stored_size);
+    let block_count = data.len().div_ceil(65535);
+    for (i, block) in data.chunks(65535).enumerate() {
+      //the 3 header bits are padded out to a byte
+      output.push((i == block_count-1) as u8);
+      output.extend_from_slice(&(block.len() as u16).to_le_bytes());
+      output.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+      output.extend_from_slice(block);
+    }
+    return output;
+  }
+  return writer.output;
+}
+
+//a zlib stream, the other way from decompress
+pub fn compress(data: &[u8]) -> Vec<u8> {
+  //deflate with a 32k window, default compression level
+  let mut output: Vec<u8> = vec![0x78, 0x9c];
+  output.extend_from_slice(&deflate(data));
+  output.extend_from_slice(&adler32(data).to_be_bytes());
+  return output;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i+2], 16).unwrap()).collect()
+  }
+
+  //made by python's zlib module
+  const STORED: &str = "7801010500faff68656c6c6f062c0215";
+  const FIXED: &str = "789ccb48cdc9c90700062c0215";
+  const DYNAMIC: &str = "78da1d88c11100300c406625f69fa1691eee2003f2590926dbd6fba9e1f400398c0f51";
+  const DYNAMIC_TEXT: &[u8] = b"bacaabaaabacaadaacdbdbaabbcaabadbbbdabcd";
+
+  #[test]
+  fn stored_block() {
+    let stream = from_hex(STORED);
+    assert_eq!(stream[2] >> 1 & 3, 0);
+    assert_eq!(decompress(&stream, usize::MAX).unwrap(), b"hello");
+  }
+
+  #[test]
+  fn fixed_block() {
+    let stream = from_hex(FIXED);
+    assert_eq!(stream[2] >> 1 & 3, 1);
+    assert_eq!(decompress(&stream, usize::MAX).unwrap(), b"hello");
+  }
+
+  #[test]
+  fn dynamic_block() {
+    let stream = from_hex(DYNAMIC);
+    assert_eq!(stream[2] >> 1 & 3, 2);
+    assert_eq!(decompress(&stream, usize::MAX).unwrap(), DYNAMIC_TEXT);
+  }
+
+  #[test]
+  fn adler32_mismatch() {
+    let mut stream = from_hex(FIXED);
+    *stream.last_mut().unwrap() ^= 1;
+    assert_eq!(decompress(&stream, usize::MAX).unwrap_err(), "adler32 doesn't match");
+  }
+
+  #[test]
+  fn truncated_stream() {
+    let stream = from_hex(DYNAMIC);
+    //cut off inside the deflate data, and between the deflate data and adler32
+    assert_eq!(decompress(&stream[..20], usize::MAX).unwrap_err(), "unexpected end of data");
+    assert_eq!(decompress(&stream[..stream.len()-2], usize::MAX).unwrap_err(), "missing adler32");
+  }
+
+  #[test]
+  fn output_limit() {
+    assert_eq!(decompress(&from_hex(DYNAMIC), DYNAMIC_TEXT.len()).unwrap(), DYNAMIC_TEXT);
+    assert_eq!(decompress(&from_hex(DYNAMIC), DYNAMIC_TEXT.len()-1).unwrap_err(), TOO_LONG);
+    assert_eq!(decompress(&from_hex(STORED), 4).unwrap_err(), TOO_LONG);
+  }
+
+  //a deterministic stand in for random bytes, which deflate can't make smaller
+  fn noise(length: usize) -> Vec<u8> {
+    let mut state: u32 = 0x12345678;
+    (0..length).map(|_| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state as u8
+    }).collect()
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut repetitive: Vec<u8> = Vec::new();
+    for i in 0..100000 {
+      repetitive.push(b"abcabcabd"[i % 9]);
+    }
+    let inputs: [Vec<u8>; 6] = [Vec::new(), vec![42], noise(100), noise(200000), repetitive, vec![0; 300000]];
+    for input in &inputs {
+      let compressed = compress(input);
+      assert_eq!(decompress(&compressed, input.len()).unwrap(), *input, "{} bytes", input.len());
+    }
+    //incompressible data is stored, so it only grows by the block headers
+    assert!(compress(&inputs[3]).len() < inputs[3].len()+100);
+    assert!(compress(&inputs[4]).len() < 1000);
+  }
+}